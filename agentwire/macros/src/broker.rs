@@ -3,6 +3,7 @@ use proc_macro::TokenStream;
 use quote::{format_ident, quote};
 use std::collections::HashSet;
 use syn::{
+    bracketed,
     parse::{Parse, ParseStream, Result},
     parse_macro_input,
     punctuated::{Pair, Punctuated},
@@ -18,6 +19,10 @@ enum AgentAttr {
     Init,
     InitAsync,
     Logger(Expr),
+    Restart(Expr),
+    Record,
+    Watchdog(Expr),
+    ForwardTo(Vec<Ident>),
 }
 
 impl Parse for AgentAttr {
@@ -33,6 +38,23 @@ impl Parse for AgentAttr {
                 input.parse::<Token![=]>()?;
                 Ok(Self::Logger(input.parse()?))
             }
+            "restart" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Restart(input.parse()?))
+            }
+            "record" => Ok(Self::Record),
+            "watchdog" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Watchdog(input.parse()?))
+            }
+            "forward_to" => {
+                input.parse::<Token![=]>()?;
+                let content;
+                bracketed!(content in input);
+                let targets =
+                    Punctuated::<Ident, Token![,]>::parse_terminated(&content)?;
+                Ok(Self::ForwardTo(targets.into_iter().collect()))
+            }
             ident => panic!("Unknown #[agent] option: {ident}"),
         }
     }
@@ -141,17 +163,102 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
         }
     };
 
+    let forward_graph = agent_fields
+        .clone()
+        .map(|(field, attrs)| {
+            let targets = attrs
+                .iter()
+                .find_map(|attr| {
+                    if let AgentAttr::ForwardTo(targets) = attr {
+                        Some(targets.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default();
+            (field.ident.clone().unwrap(), targets)
+        })
+        .collect::<Vec<_>>();
+
     let run_fut_name = format_ident!("Run{}", ident);
-    let run_handlers = agent_fields.clone().map(|(field, _)| {
+    let run_handlers = agent_fields.clone().map(|(field, attrs)| {
         let ident = field.ident.as_ref().unwrap();
         let handler = format_ident!("handle_{}", ident);
+        let disable = format_ident!("disable_{}", ident);
+        let upstream = forward_graph
+            .iter()
+            .filter(|(_, targets)| targets.contains(ident))
+            .map(|(source, _)| source);
+        let watched = attrs.iter().any(|attr| matches!(attr, AgentAttr::Watchdog(_)));
+        let forward_to = attrs
+            .iter()
+            .find_map(|attr| {
+                if let AgentAttr::ForwardTo(targets) = attr {
+                    Some(targets.as_slice())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default();
+        let watchdog = watched.then(|| {
+            assert!(
+                !attrs.contains(&AgentAttr::InitAsync),
+                "`watchdog` is not supported with `init_async`"
+            );
+            let timer = format_ident!("watchdog_{}", ident);
+            let enable = format_ident!("enable_{}", ident);
+            quote! {
+                if let ::std::option::Option::Some(watchdog) = port.watchdog() {
+                    if let ::std::task::Poll::Ready(stalled) = fut.#timer.poll(cx, &watchdog, port) {
+                        if watchdog.action != ::agentwire::agent::WatchdogAction::Report {
+                            fut.#timer.kill(&mut fut.broker.#ident, stalled);
+                        }
+                        if watchdog.action != ::agentwire::agent::WatchdogAction::Restart {
+                            return ::std::task::Poll::Ready(::std::result::Result::Err(
+                                ::agentwire::BrokerError::AgentHung(::std::stringify!(#ident), stalled),
+                            ));
+                        }
+                        if fut.drain {
+                            // The killed agent is considered drained.
+                            continue 'outer;
+                        }
+                        ::agentwire::metrics::get().restart(::std::stringify!(#ident));
+                        if let ::std::result::Result::Err(err) = fut.broker.#enable() {
+                            return ::std::task::Poll::Ready(::std::result::Result::Err(err));
+                        }
+                        continue 'outer;
+                    }
+                }
+            }
+        });
         quote! {
+            if fut.drain #(&& !fut.broker.#upstream.is_enabled())* {
+                if let Some(port) = fut.broker.#ident.enabled() {
+                    if !port.tx.is_closed() {
+                        port.tx.close_channel();
+                    }
+                }
+            }
             if let Some(port) = fut.broker.#ident.enabled() {
                 loop {
                     match ::futures::StreamExt::poll_next_unpin(port, cx) {
-                        ::std::task::Poll::Ready(Some(output)) if output.source_ts > fence => {
-                            match fut.broker.#handler(fut.plan, output) {
+                        ::std::task::Poll::Ready(Some(output))
+                            if fut.drain || output.source_ts > fence =>
+                        {
+                            let metrics = ::agentwire::metrics::get();
+                            metrics.queue_depth(::std::stringify!(#ident), port.queue_depth());
+                            if let ::std::option::Option::Some(latency) = port.since_dequeued() {
+                                metrics.latency(::std::stringify!(#ident), latency);
+                            }
+                            #(::agentwire::agent::forward(&output, &mut fut.broker.#forward_to);)*
+                            let handler_start = ::std::time::Instant::now();
+                            let result = fut.broker.#handler(fut.plan, output);
+                            metrics.handler_time(::std::stringify!(#ident), handler_start.elapsed());
+                            match result {
                                 ::std::result::Result::Ok(::agentwire::BrokerFlow::Break) => {
+                                    if fut.drain {
+                                        continue 'outer;
+                                    }
                                     return ::std::task::Poll::Ready(::std::result::Result::Ok(()));
                                 }
                                 ::std::result::Result::Ok(::agentwire::BrokerFlow::Continue) => {
@@ -173,6 +280,10 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
                             continue;
                         }
                         ::std::task::Poll::Ready(::std::option::Option::None) => {
+                            if fut.drain {
+                                fut.broker.#disable();
+                                continue 'outer;
+                            }
                             return ::std::task::Poll::Ready(
                                 ::std::result::Result::Err(
                                     ::agentwire::BrokerError::AgentTerminated(
@@ -186,9 +297,21 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
                         }
                     }
                 }
+                #watchdog
             }
         }
     });
+    let drained = agent_fields
+        .clone()
+        .map(|(field, _)| field.ident.as_ref().unwrap());
+    let watchdog_timers = agent_fields.clone().filter_map(|(field, attrs)| {
+        attrs
+            .iter()
+            .any(|attr| matches!(attr, AgentAttr::Watchdog(_)))
+            .then(|| format_ident!("watchdog_{}", field.ident.as_ref().unwrap()))
+    });
+    let watchdog_timers_init = watchdog_timers.clone();
+    let watchdog_timers_drain = watchdog_timers.clone();
     let poll_extra = broker_attrs.contains(&BrokerAttr::PollExtra).then(|| {
         quote! {
             match fut.broker.poll_extra(fut.plan, cx, fence) {
@@ -212,6 +335,8 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
             broker: &'a mut #ident,
             plan: &'a mut dyn #broker_plan,
             fence: ::std::time::Instant,
+            drain: bool,
+            #(#watchdog_timers: ::agentwire::agent::WatchdogTimer,)*
         }
 
         impl ::futures::future::Future for #run_fut_name<'_> {
@@ -225,6 +350,12 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
                 let fut = self.as_mut().get_mut();
                 'outer: loop {
                     #(#run_handlers)*
+                    if fut.drain {
+                        if true #(&& !fut.broker.#drained.is_enabled())* {
+                            return ::std::task::Poll::Ready(::std::result::Result::Ok(()));
+                        }
+                        return ::std::task::Poll::Pending;
+                    }
                     #poll_extra
                 }
             }
@@ -246,6 +377,28 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
                     broker: self,
                     plan,
                     fence,
+                    drain: false,
+                    #(#watchdog_timers_init: ::std::default::Default::default(),)*
+                }
+            }
+
+            /// Stops accepting new inputs and runs until all agents are
+            /// drained. Inputs of an agent are closed once all agents
+            /// forwarding to it are drained. An agent is drained when its
+            /// output stream ends. A process-based agent ends it after it has
+            /// received all inputs and acknowledged the closed input, see
+            /// [`RemoteInner::recv`](::agentwire::port::RemoteInner::recv).
+            /// Drained agents are disabled. All outputs are
+            /// passed to the plan regardless of the fence, and
+            /// [`BrokerFlow::Break`](::agentwire::BrokerFlow::Break) is
+            /// ignored.
+            pub fn drain<'a>(&'a mut self, plan: &'a mut dyn #broker_plan) -> #run_fut_name<'a> {
+                #run_fut_name {
+                    broker: self,
+                    plan,
+                    fence: ::std::time::Instant::now(),
+                    drain: true,
+                    #(#watchdog_timers_drain: ::std::default::Default::default(),)*
                 }
             }
         }
@@ -274,7 +427,14 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
         } else {
             (quote!(Default::default()), quote!())
         };
+        let restart = attrs
+            .iter()
+            .find_map(|attr| if let AgentAttr::Restart(expr) = attr { Some(expr) } else { None });
         let constructor = if attrs.contains(&AgentAttr::Process) {
+            assert!(
+                restart.is_none(),
+                "`restart` is not supported for process agents, use `Process::exit_strategy`"
+            );
             let logger = if let Some(logger) = attrs
                 .iter()
                 .find_map(|attr| if let AgentAttr::Logger(expr) = attr { Some(expr) } else { None })
@@ -285,8 +445,13 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
             };
             quote!(::agentwire::agent::Process::spawn_process(#init, #logger))
         } else if attrs.contains(&AgentAttr::Thread) {
+            let spawn = if let Some(restart) = restart {
+                quote!(::agentwire::agent::Thread::spawn_thread_supervised(#init, #restart))
+            } else {
+                quote!(::agentwire::agent::Thread::spawn_thread(#init))
+            };
             quote! {
-                match ::agentwire::agent::Thread::spawn_thread(#init) {
+                match #spawn {
                     ::std::result::Result::Ok(cell) => cell,
                     ::std::result::Result::Err(err) => {
                         return ::std::result::Result::Err(
@@ -296,10 +461,45 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
                 }
             }
         } else if attrs.contains(&AgentAttr::Task) {
-            quote!(::agentwire::agent::Task::spawn_task(#init))
+            if let Some(restart) = restart {
+                quote!(::agentwire::agent::Task::spawn_task_supervised(#init, #restart))
+            } else {
+                quote!(::agentwire::agent::Task::spawn_task(#init))
+            }
         } else {
             panic!("must have `task`, `thread`, or `process` tag");
         };
+        let constructor = if attrs.contains(&AgentAttr::Record) {
+            quote! {
+                match ::agentwire::replay::replay_agent() {
+                    ::std::option::Option::Some(cell) => cell,
+                    ::std::option::Option::None => ::agentwire::replay::record_agent(#constructor),
+                }
+            }
+        } else {
+            constructor
+        };
+        let constructor = quote! {
+            match ::agentwire::agent::mock_agent() {
+                ::std::option::Option::Some(cell) => cell,
+                ::std::option::Option::None => #constructor,
+            }
+        };
+        // Evaluated once for each new agent.
+        let set_watchdog = attrs.iter().find_map(|attr| {
+            if let AgentAttr::Watchdog(expr) = attr {
+                Some(quote! {
+                    let watchdog: ::agentwire::agent::Watchdog = #expr;
+                    let agent = {
+                        let (mut port, kill) = agent;
+                        port.set_watchdog(watchdog);
+                        (port, kill)
+                    };
+                })
+            } else {
+                None
+            }
+        });
 
         quote! {
             #[allow(missing_docs)]
@@ -308,7 +508,9 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
             ) -> ::std::result::Result<(), ::agentwire::BrokerError<#broker_error>> {
                 match ::std::mem::replace(&mut self.#ident, ::agentwire::agent::Cell::Vacant) {
                     ::agentwire::agent::Cell::Vacant => {
-                        self.#ident = ::agentwire::agent::Cell::Enabled(#constructor);
+                        let agent = #constructor;
+                        #set_watchdog
+                        self.#ident = ::agentwire::agent::Cell::Enabled(agent);
                     }
                     ::agentwire::agent::Cell::Enabled(agent)
                     | ::agentwire::agent::Cell::Disabled(agent) => {
@@ -342,6 +544,22 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
         }
     });
 
+    let teardown = shutdown_order(&forward_graph)
+        .into_iter()
+        .map(|ident| {
+            let (_, attrs) = agent_fields
+                .clone()
+                .find(|(field, _)| field.ident.as_ref() == Some(ident))
+                .unwrap();
+            if attrs.contains(&AgentAttr::Process) {
+                quote!(self.#ident.kill().await;)
+            } else {
+                let disable = format_ident!("disable_{}", ident);
+                quote!(self.#disable();)
+            }
+        })
+        .collect::<Vec<_>>();
+
     let disable_agents = agent_fields.map(|(field, _)| {
         let disable = format_ident!("disable_{}", field.ident.as_ref().unwrap());
         quote!(#disable)
@@ -358,7 +576,41 @@ pub fn proc_macro_derive(input: TokenStream) -> TokenStream {
             pub fn disable_agents(&mut self) {
                 #(self.#disable_agents();)*
             }
+
+            /// Shuts the broker down gracefully. Drains the agents with
+            /// [`drain`](Self::drain) for at most `timeout`, then disables
+            /// task-based and thread-based agents and kills process-based
+            /// agents, upstream agents of `forward_to` first. Finally waits for
+            /// the recorded messages to be written, if a recording is in
+            /// progress.
+            pub async fn shutdown(
+                &mut self,
+                plan: &mut dyn #broker_plan,
+                timeout: ::std::time::Duration,
+            ) -> ::std::result::Result<(), ::agentwire::BrokerError<#broker_error>> {
+                let result = ::agentwire::agent::drain(self.drain(plan), timeout).await;
+                #(#teardown)*
+                ::agentwire::replay::flush_recording().await;
+                result
+            }
         }
     };
     expanded.into()
 }
+
+/// Orders the agents so that the sources of `forward_to` come before their
+/// targets. Otherwise keeps the declaration order, which also breaks cycles.
+fn shutdown_order(graph: &[(Ident, Vec<Ident>)]) -> Vec<&Ident> {
+    let mut remaining = graph.iter().collect::<Vec<_>>();
+    let mut order = Vec::with_capacity(graph.len());
+    while !remaining.is_empty() {
+        let index = remaining
+            .iter()
+            .position(|(ident, _)| {
+                !remaining.iter().any(|(_, targets)| targets.contains(ident))
+            })
+            .unwrap_or(0);
+        order.push(&remaining.remove(index).0);
+    }
+    order
+}
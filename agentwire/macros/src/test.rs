@@ -1,16 +1,19 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{quote, quote_spanned};
 use std::mem::take;
 use syn::{
     parse::{Parse, ParseStream, Result},
     parse_macro_input,
     punctuated::Punctuated,
+    spanned::Spanned,
     Expr, Ident, ItemFn, LitStr, Token,
 };
 
 enum TestAttr {
     Init(Expr),
     Timeout(Expr),
+    Replay(LitStr),
+    Mock(Expr),
 }
 
 impl Parse for TestAttr {
@@ -25,6 +28,14 @@ impl Parse for TestAttr {
                 input.parse::<Token![=]>()?;
                 Ok(Self::Timeout(input.parse()?))
             }
+            "replay" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Replay(input.parse()?))
+            }
+            "mock" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Mock(input.parse()?))
+            }
             ident => panic!("Unknown option: {ident}"),
         }
     }
@@ -52,6 +63,57 @@ pub fn proc_macro_attribute(attr: TokenStream, item: TokenStream) -> TokenStream
             }
         })
         .unwrap_or_else(|| quote!(::agentwire::testing_rt::DEFAULT_TIMEOUT));
+    let init = if let Some(replay) = test_attrs.iter().find_map(|attr| {
+        if let TestAttr::Replay(path) = attr {
+            Some(path)
+        } else {
+            None
+        }
+    }) {
+        quote! {{
+            let init = #init;
+            move || {
+                init();
+                ::agentwire::replay::start_replay(::std::concat!(
+                    ::std::env!("CARGO_MANIFEST_DIR"),
+                    "/",
+                    #replay,
+                ))
+                .expect("failed to load the replay recording");
+            }
+        }}
+    } else {
+        init
+    };
+    let mocks = test_attrs
+        .iter()
+        .filter_map(|attr| {
+            if let TestAttr::Mock(expr) = attr {
+                Some(expr)
+            } else {
+                None
+            }
+        })
+        .map(|expr| {
+            if cfg!(feature = "mock") {
+                quote!(::agentwire::mock::install(#expr);)
+            } else {
+                quote_spanned! {expr.span()=>
+                    ::std::compile_error!(
+                        "the `mock` option requires the `mock` feature of agentwire"
+                    );
+                }
+            }
+        });
+    let init = quote! {{
+        let init = #init;
+        move || {
+            init();
+            #(#mocks)*
+        }
+    }};
+    // Unfinished or failed mocks fail the test.
+    let verify = cfg!(feature = "mock").then(|| quote!(::agentwire::mock::verify();));
 
     let ItemFn {
         attrs,
@@ -72,11 +134,14 @@ pub fn proc_macro_attribute(attr: TokenStream, item: TokenStream) -> TokenStream
             struct TestId;
             let test_id = ::std::any::TypeId::of::<TestId>();
             ::agentwire::testing_rt::run_broker_test(
-                ::std::stringify!(#test_name),
+                #test_name,
                 &::std::format!("{test_id:?}"),
                 ::std::time::Duration::from_millis(#timeout),
                 #init,
-                ::std::boxed::Box::pin(async move #block),
+                ::std::boxed::Box::pin(async move {
+                    async move #block.await;
+                    #verify
+                }),
             )
         }
     };
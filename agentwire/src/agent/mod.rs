@@ -52,14 +52,28 @@
 
 pub mod process;
 
+mod sandbox;
+mod supervisor;
 mod task;
 mod thread;
+mod watchdog;
 
-pub use self::{process::Process, task::Task, thread::Thread};
+pub use self::{
+    process::{AsyncProcess, Process},
+    supervisor::RestartPolicy,
+    task::Task,
+    thread::Thread,
+    watchdog::{Watchdog, WatchdogAction, WatchdogTimer},
+};
 
 use crate::port::{self, Port};
 use futures::prelude::*;
-use std::{mem::replace, pin::Pin};
+use std::{
+    mem::replace,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+use tokio::time;
 
 /// Abstract agent.
 pub trait Agent: Port + Sized + 'static {
@@ -67,6 +81,21 @@ pub trait Agent: Port + Sized + 'static {
     const NAME: &'static str;
 }
 
+/// Exit strategy applied when an agent terminates.
+///
+/// Process-based agents return it from [`Process::exit_strategy`], task-based
+/// and thread-based agents receive it as a part of [`RestartPolicy`].
+#[derive(Clone, Copy, Default, Debug)]
+pub enum ExitStrategy {
+    /// Close the port without restarting the agent.
+    Close,
+    /// Keep the port open and restart the agent.
+    Restart,
+    /// Keep the port open, restart the agent, and retry the latest input.
+    #[default]
+    Retry,
+}
+
 /// Future to kill an agent.
 pub type Kill = Pin<Box<dyn Future<Output = ()> + Send>>;
 
@@ -109,3 +138,59 @@ impl<T: Agent> Cell<T> {
         }
     }
 }
+
+/// Forwards `output` to the agent in `target` if the agent is enabled. Never
+/// blocks, the message is dropped if the input queue of the target is full,
+/// and counted in [`port::Outer::dropped_inputs`]. Used by the generated broker
+/// `run` method.
+#[doc(hidden)]
+pub fn forward<S: Agent + port::Forward<T>, T: Agent>(
+    output: &port::Output<S>,
+    target: &mut Cell<T>,
+) {
+    let Some(port) = target.enabled() else {
+        return;
+    };
+    if let Some(value) = S::forward(&output.value) {
+        if let Err(err) = port.try_send(output.chain(value)) {
+            if err.is_full() {
+                port.counters.drop_input();
+                tracing::warn!(
+                    "Agent {} input queue is full, dropping a message from {}",
+                    T::NAME,
+                    S::NAME
+                );
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+#[doc(hidden)]
+pub use crate::mock::mock_agent;
+
+/// Never finds a mock without the `mock` feature. Used by the generated broker
+/// `enable_*` methods.
+#[cfg(not(any(test, feature = "mock")))]
+#[doc(hidden)]
+#[must_use]
+pub fn mock_agent<T: Agent>() -> Option<(port::Outer<T>, Kill)> {
+    None
+}
+
+/// Runs the `drain` future of a broker for at most `timeout`. Used by the
+/// generated broker `shutdown` method.
+#[doc(hidden)]
+pub async fn drain<E>(
+    drain: impl Future<Output = Result<(), E>>,
+    timeout: Duration,
+) -> Result<(), E> {
+    let started = Instant::now();
+    if let Ok(result) = time::timeout(timeout, drain).await {
+        tracing::info!("Broker drained in {:?}", started.elapsed());
+        result
+    } else {
+        tracing::warn!("Broker didn't drain in {timeout:?}, shutting down anyway");
+        Ok(())
+    }
+}
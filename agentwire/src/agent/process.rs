@@ -1,7 +1,13 @@
 //! Process-based agents.
 
-use super::{Agent, Kill};
+pub use super::{
+    sandbox::{Sandbox, SeccompAction, SeccompFilter},
+    ExitStrategy,
+};
+
+use super::{sandbox, Agent, Kill};
 use crate::{
+    metrics,
     port::{self, SharedPort, SharedSerializer},
     spawn_named_thread,
 };
@@ -9,7 +15,6 @@ use close_fds::close_open_fds;
 use futures::{future::Either, prelude::*};
 use nix::{
     errno::Errno,
-    sched::{unshare, CloneFlags},
     sys::signal::{self, Signal},
     unistd::Pid,
 };
@@ -44,6 +49,7 @@ pub const ARGS_ENV: &str = "AGENTWIRE_PROCESS_ARGS";
 
 const SHMEM_ENV: &str = "AGENTWIRE_PROCESS_SHMEM";
 const PARENT_PID_ENV: &str = "AGENTWIRE_PROCESS_PARENT_PID";
+const SECCOMP_ENV: &str = "AGENTWIRE_PROCESS_SECCOMP";
 
 static INIT_PROCESSES: AtomicBool = AtomicBool::new(false);
 
@@ -58,18 +64,6 @@ pub enum CallError<T: Debug> {
     SharedMemory(Errno),
 }
 
-/// Exit strategy returned from [`Process::exit_strategy`].
-#[derive(Clone, Copy, Default, Debug)]
-pub enum ExitStrategy {
-    /// Close the port without restarting the agent.
-    Close,
-    /// Keep the port open and restart the agent.
-    Restart,
-    /// Keep the port open, restart the agent, and retry the latest input.
-    #[default]
-    Retry,
-}
-
 /// Additional settings for starting a new process.
 pub trait Initializer: Send {
     /// File descriptors to keep open when starting a new process.
@@ -79,6 +73,13 @@ pub trait Initializer: Send {
     /// Additional environment variables for the process.
     #[must_use]
     fn envs(&self) -> Vec<(String, String)>;
+
+    /// Hardening applied to the process. See [`Sandbox`] for available
+    /// options.
+    #[must_use]
+    fn sandbox(&self) -> Sandbox {
+        Sandbox::default()
+    }
 }
 
 /// Default initializer with no additional settings.
@@ -113,7 +114,14 @@ where
     /// Error type returned by the agent.
     type Error: Debug;
 
-    /// Runs the agent event-loop inside a dedicated OS thread.
+    /// Whether the agent waits on the shared memory doorbells. Only such agent
+    /// processes inherit the doorbell file descriptors. Set by the blanket
+    /// implementation for [`AsyncProcess`].
+    #[doc(hidden)]
+    const ASYNC: bool = false;
+
+    /// Runs the agent event-loop inside a dedicated OS thread. The event-loop
+    /// should return once [`port::RemoteInner::recv`] returns `None`.
     fn run(self, port: port::RemoteInner<Self>) -> Result<(), Self::Error>;
 
     /// Spawns a new process running the agent event-loop and returns a handle
@@ -174,6 +182,93 @@ where
     }
 }
 
+/// Process-based agent with an asynchronous event-loop.
+///
+/// The agent runs on a Tokio runtime inside the agent process and receives a
+/// [`port::AsyncRemoteInner`], which implements [`Stream`] and [`Sink`] over
+/// the shared memory. Every type implementing this trait is also a
+/// [`Process`], so it's spawned and called in the same way.
+pub trait AsyncProcess
+where
+    Self: Agent
+        + SharedPort
+        + Clone
+        + Send
+        + Debug
+        + Archive
+        + for<'a> Serialize<SharedSerializer<'a>>,
+    <Self as Archive>::Archived: Deserialize<Self, Infallible>,
+    Self::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    Self::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <Self::Input as Archive>::Archived: Deserialize<Self::Input, SharedDeserializeMap>,
+    <Self::Output as Archive>::Archived:
+        Deserialize<Self::Output, SharedDeserializeMap>,
+{
+    /// Error type returned by the agent.
+    type Error: Debug;
+
+    /// Runs the agent event-loop inside the runtime returned by
+    /// [`runtime`](Self::runtime).
+    fn run(
+        self,
+        port: port::AsyncRemoteInner<Self>,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Creates a runtime for the agent event-loop. The default is a
+    /// current-thread runtime with all drivers enabled.
+    #[must_use]
+    fn runtime() -> runtime::Runtime {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build a runtime for the process agent")
+    }
+
+    /// When the agent process terminates, this method decides how to proceed.
+    /// See [`ExitStrategy`] for available options.
+    #[must_use]
+    fn exit_strategy(_code: Option<i32>, _signal: Option<i32>) -> ExitStrategy {
+        ExitStrategy::default()
+    }
+
+    /// Additional settings for starting a new process.
+    #[must_use]
+    fn initializer() -> impl Initializer {
+        DefaultInitializer
+    }
+}
+
+impl<T> Process for T
+where
+    T: AsyncProcess,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Input as Archive>::Archived: Deserialize<T::Input, SharedDeserializeMap>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    type Error = <T as AsyncProcess>::Error;
+
+    const ASYNC: bool = true;
+
+    fn run(self, port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        T::runtime().block_on(async move {
+            let port = port
+                .into_async()
+                .expect("failed to register the shared memory doorbell");
+            AsyncProcess::run(self, port).await
+        })
+    }
+
+    fn exit_strategy(code: Option<i32>, signal: Option<i32>) -> ExitStrategy {
+        <T as AsyncProcess>::exit_strategy(code, signal)
+    }
+
+    fn initializer() -> impl Initializer {
+        <T as AsyncProcess>::initializer()
+    }
+}
+
 /// Initializes process-based agents.
 ///
 /// This function must be called as early in the program lifetime as possible.
@@ -196,6 +291,14 @@ pub fn init(
                 // The parent exited before the above `prctl` call.
                 process::exit(1);
             }
+            if let Ok(seccomp) = env::var(SECCOMP_ENV) {
+                let program =
+                    sandbox::decode_seccomp(&seccomp).expect("invalid seccomp filter");
+                if let Err(err) = sandbox::install_seccomp(&program) {
+                    eprintln!("Failed to install the seccomp filter: {err:#?}");
+                    process::exit(1);
+                }
+            }
             let shmem_fd = unsafe {
                 OwnedFd::from_raw_fd(
                     shmem
@@ -280,7 +383,7 @@ async fn spawn_process_impl<T: Process, Fut, F>(
 {
     let mut recovered_inputs = Vec::new();
     loop {
-        let (shmem_fd, close) = inner
+        let (shmem_fd, doorbell_fds, close) = inner
             .into_shared_memory(T::NAME, &init_state, recovered_inputs)
             .expect("couldn't initialize shared memory");
         let exe =
@@ -289,6 +392,11 @@ async fn spawn_process_impl<T: Process, Fut, F>(
         let initializer = T::initializer();
         let mut child_fds = initializer.keep_file_descriptors();
         child_fds.push(shmem_fd.as_raw_fd());
+        if T::ASYNC {
+            child_fds.extend(doorbell_fds);
+        }
+        let sandbox = initializer.sandbox();
+        let seccomp = sandbox::encode_seccomp(&sandbox);
         let mut child = unsafe {
             Command::new(exe)
                 .arg0(format!("proc-{}", T::NAME))
@@ -303,10 +411,11 @@ async fn spawn_process_impl<T: Process, Fut, F>(
                 .envs(initializer.envs())
                 .env(SHMEM_ENV, shmem_fd.as_raw_fd().to_string())
                 .env(PARENT_PID_ENV, process::id().to_string())
+                .envs(seccomp.map(|seccomp| (SECCOMP_ENV, seccomp)))
                 .stdin(Stdio::null())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
-                .pre_exec(sandbox_agent)
+                .pre_exec(move || sandbox::apply_pre_exec(&sandbox))
                 .pre_exec(move || {
                     close_open_fds(libc::STDERR_FILENO + 1, &child_fds);
                     Ok(())
@@ -335,14 +444,25 @@ async fn spawn_process_impl<T: Process, Fut, F>(
                     tracing::warn!("Process agent {} exited on Ctrl-C", T::NAME);
                     break;
                 }
+                (inner, recovered_inputs) =
+                    close.await.expect("shared memory deinitialization failure");
+                if inner.tx.is_closed() {
+                    // The agent has acknowledged the closed input, or the
+                    // broker has dropped the port.
+                    tracing::info!(
+                        "Process agent {} exited with code {code:?} and signal {signal:?} after \
+                         its port was closed",
+                        T::NAME
+                    );
+                    let _ = wait_kill_tx.send(());
+                    break;
+                }
                 let exit_strategy = T::exit_strategy(code, signal);
                 tracing::info!(
                     "Process agent {} exited with code {code:?} and signal {signal:?}, proceeding \
                      with {exit_strategy:?}",
                     T::NAME
                 );
-                (inner, recovered_inputs) =
-                    close.await.expect("shared memory deinitialization failure");
                 match exit_strategy {
                     ExitStrategy::Close => {
                         let _ = wait_kill_tx.send(());
@@ -353,6 +473,7 @@ async fn spawn_process_impl<T: Process, Fut, F>(
                     }
                     ExitStrategy::Retry => {}
                 }
+                metrics::get().restart(T::NAME);
             }
             Either::Right((_kill, wait)) => {
                 signal::kill(pid, Signal::SIGKILL)
@@ -365,16 +486,3 @@ async fn spawn_process_impl<T: Process, Fut, F>(
         };
     }
 }
-
-fn sandbox_agent() -> std::io::Result<()> {
-    #[allow(unused_mut)]
-    let mut flags = CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWIPC;
-    #[cfg(feature = "sandbox-network")]
-    {
-        flags |= CloneFlags::CLONE_NEWNET;
-    }
-    match unshare(flags) {
-        Ok(()) => Ok(()),
-        Err(err) => Err(err.into()),
-    }
-}
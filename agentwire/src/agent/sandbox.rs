@@ -0,0 +1,339 @@
+use nix::{
+    sched::{unshare, CloneFlags},
+    sys::resource::{setrlimit, Resource},
+    unistd::{setgid, setgroups, setuid, Gid, Uid},
+};
+use std::{io, mem, ptr};
+
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("process agent sandboxing supports only x86_64 and aarch64");
+
+// System calls of the x32 ABI share the x86_64 audit architecture and have
+// this bit set in the number.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// Offsets in `struct seccomp_data`.
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Hardening applied to a process-based agent before
+/// [`Process::call`](super::Process::call).
+///
+/// The default sandbox only isolates the agent in new user and IPC namespaces
+/// (and a network namespace with the `sandbox-network` feature), which is the
+/// behavior of the process agents without an explicit sandbox.
+///
+/// # Examples
+///
+/// ```ignore
+/// impl Initializer for FooInitializer {
+///     // ...
+///
+///     fn sandbox(&self) -> Sandbox {
+///         Sandbox {
+///             no_new_privs: true,
+///             drop_capabilities: true,
+///             rlimits: vec![(Resource::RLIMIT_AS, 1 << 30)],
+///             seccomp: Some(SeccompFilter::deny(&[libc::SYS_ptrace])),
+///             ..Sandbox::default()
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    /// Namespaces to unshare.
+    pub namespaces: CloneFlags,
+    /// User ID to switch to. Requires `CAP_SETUID` in the broker. Namespaces
+    /// other than the user namespace are entered before the switch, so they
+    /// require `CAP_SYS_ADMIN` in the broker.
+    pub uid: Option<u32>,
+    /// Group ID to switch to. Supplementary groups are dropped. Requires
+    /// `CAP_SETGID` in the broker.
+    pub gid: Option<u32>,
+    /// Resource limits. Each limit is set as both soft and hard limit.
+    pub rlimits: Vec<(Resource, u64)>,
+    /// Drop all capabilities including the bounding and ambient sets.
+    /// Requires `CAP_SETPCAP`, which the agent has inside a new user
+    /// namespace.
+    pub drop_capabilities: bool,
+    /// Set `PR_SET_NO_NEW_PRIVS`. Implied by `seccomp`.
+    pub no_new_privs: bool,
+    /// Seccomp filter installed right before
+    /// [`Process::call`](super::Process::call). It must allow everything the
+    /// agent does from that point, including connecting to the shared memory.
+    pub seccomp: Option<SeccompFilter>,
+}
+
+/// Seccomp filter matching system calls by number.
+#[derive(Clone, Debug)]
+pub struct SeccompFilter {
+    /// Action for the system calls not listed in `rules`.
+    pub default_action: SeccompAction,
+    /// Actions for specific system calls.
+    pub rules: Vec<(libc::c_long, SeccompAction)>,
+}
+
+/// Action of a [`SeccompFilter`] rule.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SeccompAction {
+    /// Allow the system call.
+    Allow,
+    /// Allow the system call and log it.
+    Log,
+    /// Fail the system call with the given `errno`.
+    Errno(u16),
+    /// Send `SIGSYS` to the agent.
+    Trap,
+    /// Kill the agent process.
+    KillProcess,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        #[allow(unused_mut)]
+        let mut namespaces = CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWIPC;
+        #[cfg(feature = "sandbox-network")]
+        {
+            namespaces |= CloneFlags::CLONE_NEWNET;
+        }
+        Self {
+            namespaces,
+            uid: None,
+            gid: None,
+            rlimits: Vec::new(),
+            drop_capabilities: false,
+            no_new_privs: false,
+            seccomp: None,
+        }
+    }
+}
+
+impl SeccompFilter {
+    /// Creates a filter allowing only the listed system calls. Other system
+    /// calls fail with `EPERM`.
+    #[must_use]
+    pub fn allow(syscalls: &[libc::c_long]) -> Self {
+        Self {
+            default_action: SeccompAction::Errno(libc::EPERM.try_into().unwrap()),
+            rules: syscalls
+                .iter()
+                .map(|&nr| (nr, SeccompAction::Allow))
+                .collect(),
+        }
+    }
+
+    /// Creates a filter denying the listed system calls with `EPERM`.
+    #[must_use]
+    pub fn deny(syscalls: &[libc::c_long]) -> Self {
+        let errno = SeccompAction::Errno(libc::EPERM.try_into().unwrap());
+        Self {
+            default_action: SeccompAction::Allow,
+            rules: syscalls.iter().map(|&nr| (nr, errno)).collect(),
+        }
+    }
+
+    /// Compiles the filter into a BPF program. System calls of a foreign
+    /// architecture or ABI kill the process.
+    fn compile(&self) -> Vec<libc::sock_filter> {
+        let mut program = vec![
+            stmt(
+                libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                SECCOMP_DATA_ARCH,
+            ),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH,
+                1,
+                0,
+            ),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, SECCOMP_DATA_NR),
+            jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                X32_SYSCALL_BIT,
+                0,
+                1,
+            ),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ];
+        for &(nr, action) in &self.rules {
+            // System call numbers are non-negative and fit 32 bits.
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let nr = nr as u32;
+            program.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr, 0, 1));
+            program.push(stmt(libc::BPF_RET | libc::BPF_K, action.ret()));
+        }
+        program.push(stmt(libc::BPF_RET | libc::BPF_K, self.default_action.ret()));
+        program
+    }
+}
+
+impl SeccompAction {
+    fn ret(self) -> u32 {
+        match self {
+            Self::Allow => libc::SECCOMP_RET_ALLOW,
+            Self::Log => libc::SECCOMP_RET_LOG,
+            Self::Errno(errno) => libc::SECCOMP_RET_ERRNO | u32::from(errno),
+            Self::Trap => libc::SECCOMP_RET_TRAP,
+            Self::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+/// Applies the sandbox settings which must be set before `exec`. Runs in the
+/// forked child, so it doesn't allocate.
+pub(super) fn apply_pre_exec(sandbox: &Sandbox) -> io::Result<()> {
+    for &(resource, limit) in &sandbox.rlimits {
+        setrlimit(resource, limit, limit)?;
+    }
+    // Other namespaces require `CAP_SYS_ADMIN`, which is lost when the IDs are
+    // changed, unless a new user namespace is entered at the same time. But
+    // the IDs can't be changed inside a new user namespace, where they are
+    // not mapped. So with changed IDs, the other namespaces are entered
+    // first, while the process still has the capabilities of the broker, and
+    // the new user namespace is entered last.
+    let (namespaces, late_namespaces) =
+        if sandbox.uid.is_some() || sandbox.gid.is_some() {
+            (
+                sandbox.namespaces.difference(CloneFlags::CLONE_NEWUSER),
+                sandbox.namespaces.intersection(CloneFlags::CLONE_NEWUSER),
+            )
+        } else {
+            (sandbox.namespaces, CloneFlags::empty())
+        };
+    unshare(namespaces)?;
+    if let Some(gid) = sandbox.gid {
+        setgroups(&[])?;
+        setgid(Gid::from_raw(gid))?;
+    }
+    if let Some(uid) = sandbox.uid {
+        setuid(Uid::from_raw(uid))?;
+    }
+    if !late_namespaces.is_empty() {
+        unshare(late_namespaces)?;
+    }
+    if sandbox.drop_capabilities {
+        drop_capabilities()?;
+    }
+    if sandbox.no_new_privs || sandbox.seccomp.is_some() {
+        prctl(libc::PR_SET_NO_NEW_PRIVS, 1)?;
+    }
+    Ok(())
+}
+
+/// Installs a compiled seccomp filter into the current process.
+pub(super) fn install_seccomp(program: &[libc::sock_filter]) -> io::Result<()> {
+    prctl(libc::PR_SET_NO_NEW_PRIVS, 1)?;
+    let prog = libc::sock_fprog {
+        len: program
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+        filter: program.as_ptr().cast_mut(),
+    };
+    let result = unsafe {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::c_ulong::from(libc::SECCOMP_MODE_FILTER),
+            ptr::addr_of!(prog),
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Encodes the compiled seccomp filter of the sandbox for passing it to the
+/// agent process in an environment variable.
+pub(super) fn encode_seccomp(sandbox: &Sandbox) -> Option<String> {
+    let program = sandbox.seccomp.as_ref()?.compile();
+    let instructions = program
+        .iter()
+        .map(|insn| format!("{}:{}:{}:{}", insn.code, insn.jt, insn.jf, insn.k))
+        .collect::<Vec<_>>();
+    Some(instructions.join(","))
+}
+
+/// Decodes a seccomp filter encoded by [`encode_seccomp`].
+pub(super) fn decode_seccomp(encoded: &str) -> Option<Vec<libc::sock_filter>> {
+    encoded
+        .split(',')
+        .map(|insn| {
+            let mut fields = insn.split(':');
+            let insn = libc::sock_filter {
+                code: fields.next()?.parse().ok()?,
+                jt: fields.next()?.parse().ok()?,
+                jf: fields.next()?.parse().ok()?,
+                k: fields.next()?.parse().ok()?,
+            };
+            fields.next().is_none().then_some(insn)
+        })
+        .collect()
+}
+
+fn drop_capabilities() -> io::Result<()> {
+    // Drop the bounding set until the kernel reports an unknown capability.
+    for cap in 0.. {
+        if let Err(err) = prctl(libc::PR_CAPBSET_DROP, cap) {
+            if err.raw_os_error() == Some(libc::EINVAL) {
+                break;
+            }
+            return Err(err);
+        }
+    }
+    prctl(
+        libc::PR_CAP_AMBIENT,
+        libc::PR_CAP_AMBIENT_CLEAR_ALL.try_into().unwrap(),
+    )?;
+    let header = CapHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data: [CapData; 2] = unsafe { mem::zeroed() };
+    let result = unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn prctl(option: libc::c_int, arg: libc::c_ulong) -> io::Result<()> {
+    if unsafe { libc::prctl(option, arg, 0, 0, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code.try_into().unwrap(),
+        jt,
+        jf,
+        k,
+    }
+}
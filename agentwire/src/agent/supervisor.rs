@@ -0,0 +1,194 @@
+use super::{Agent, ExitStrategy};
+use crate::{
+    metrics,
+    port::{self, Input},
+};
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+use std::{io, pin::pin, sync::Mutex, time::Duration};
+use tokio::{
+    task,
+    time::{self, Instant},
+};
+
+/// Restart policy for task-based and thread-based agents.
+///
+/// When a supervised agent terminates, either by returning from its `run`
+/// method or by panicking, the policy decides whether to close the port or to
+/// restart the agent from its initial state.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[derive(Broker)]
+/// #[broker(plan = Plan, error = Error)]
+/// struct MyBroker {
+///     #[agent(task, restart = RestartPolicy {
+///         max_restarts: Some(3),
+///         ..RestartPolicy::default()
+///     })]
+///     foo: agent::Cell<Foo>,
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    /// Exit strategy applied when the agent terminates.
+    pub strategy: ExitStrategy,
+    /// Maximum number of restarts, after which the port is closed. `None`
+    /// means no limit.
+    pub max_restarts: Option<u32>,
+    /// Delay before the first restart.
+    pub backoff: Duration,
+    /// Upper limit for the delay, which doubles after each restart. An agent
+    /// running longer than this is considered healthy, and both the restart
+    /// count and the delay are reset.
+    pub max_backoff: Duration,
+}
+
+enum Exit {
+    Broker,
+    Agent,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: ExitStrategy::default(),
+            max_restarts: None,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Puts `agent` behind a relay task, which restarts the agent with `respawn`
+/// according to the `policy`. Must be called within a tokio runtime.
+pub(super) fn supervise<T, F>(
+    policy: RestartPolicy,
+    mut agent: port::Outer<T>,
+    mut respawn: F,
+) -> port::Outer<T>
+where
+    T: Agent,
+    T::Input: Clone,
+    F: FnMut() -> io::Result<port::Outer<T>> + Send + 'static,
+{
+    let (mut inner, outer) = port::new();
+    task::spawn(async move {
+        let last_input = Mutex::new(None);
+        let mut restarts = 0;
+        let mut backoff = policy.backoff;
+        loop {
+            let started = Instant::now();
+            if let Exit::Broker = relay(&mut inner, &mut agent, &last_input).await {
+                break;
+            }
+            if started.elapsed() > policy.max_backoff {
+                restarts = 0;
+                backoff = policy.backoff;
+            }
+            if matches!(policy.strategy, ExitStrategy::Close)
+                || policy.max_restarts.is_some_and(|max| restarts >= max)
+            {
+                tracing::error!(
+                    "Agent {} terminated after {restarts} restarts, closing the port",
+                    T::NAME
+                );
+                break;
+            }
+            restarts += 1;
+            metrics::get().restart(T::NAME);
+            tracing::warn!(
+                "Agent {} terminated, restarting in {backoff:?} with {:?}",
+                T::NAME,
+                policy.strategy
+            );
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(policy.max_backoff);
+            agent = match respawn() {
+                Ok(agent) => agent,
+                Err(err) => {
+                    tracing::error!("Agent {} failed to restart: {err:#?}", T::NAME);
+                    break;
+                }
+            };
+            match policy.strategy {
+                ExitStrategy::Retry => {
+                    let input = last_input.lock().unwrap().clone();
+                    if let Some(input) = input {
+                        // A fresh channel always has a free slot for each sender.
+                        let _ = agent.send(input).await;
+                    }
+                }
+                ExitStrategy::Restart => *last_input.lock().unwrap() = None,
+                ExitStrategy::Close => unreachable!(),
+            }
+        }
+    });
+    outer
+}
+
+/// Relays messages between the broker and the agent until one of them exits.
+/// `last_input` holds the latest input until the agent handles it, that is
+/// until the agent emits an output after pulling it, or until the next input
+/// replaces it.
+async fn relay<T>(
+    inner: &mut port::Inner<T>,
+    agent: &mut port::Outer<T>,
+    last_input: &Mutex<Option<Input<T>>>,
+) -> Exit
+where
+    T: Agent,
+    T::Input: Clone,
+{
+    let port::Inner {
+        tx: inner_tx,
+        rx: inner_rx,
+        counters: inner_counters,
+    } = inner;
+    let port::Outer {
+        tx: agent_tx,
+        rx: agent_rx,
+        counters: agent_counters,
+        ..
+    } = agent;
+    let inputs = async {
+        while let Some(input) = inner_rx.next().await {
+            inner_counters.pop_input();
+            *last_input.lock().unwrap() = Some(input.clone());
+            agent_counters.push_input();
+            if agent_tx.send(input).await.is_err() {
+                return Exit::Agent;
+            }
+        }
+        // Let the agent drain its queue.
+        agent_tx.close_channel();
+        Exit::Broker
+    };
+    let outputs = async {
+        while let Some(output) = agent_rx.next().await {
+            if agent_counters.depth::<T>().input == 0 {
+                *last_input.lock().unwrap() = None;
+            }
+            agent_counters.pop_output();
+            inner_counters.push_output();
+            if inner_tx.send(output).await.is_err() {
+                return Exit::Broker;
+            }
+        }
+        Exit::Agent
+    };
+    match future::select(pin!(inputs), pin!(outputs)).await {
+        // The agent stopped receiving inputs, forward the rest of its outputs.
+        Either::Left((Exit::Agent, outputs)) => outputs.await,
+        // The broker closed the input, forward the rest of the outputs and
+        // don't restart the agent.
+        Either::Left((Exit::Broker, outputs)) => {
+            outputs.await;
+            Exit::Broker
+        }
+        Either::Right((exit, _)) => exit,
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Agent, Kill};
+use super::{supervisor, Agent, Kill, RestartPolicy};
 use crate::port;
 use futures::prelude::*;
 use std::fmt::Debug;
@@ -35,4 +35,21 @@ pub trait Task: Agent + Send {
         });
         (outer, future::pending().boxed())
     }
+
+    /// Spawns a new task running the agent event-loop under supervision and
+    /// returns a handle for bi-directional communication with the agent. When
+    /// the agent terminates, it is restarted from a copy of `self` according to
+    /// the `policy`.
+    fn spawn_task_supervised(self, policy: RestartPolicy) -> (port::Outer<Self>, Kill)
+    where
+        Self: Clone,
+        Self::Input: Clone,
+    {
+        let init_state = self.clone();
+        let (outer, kill) = self.spawn_task();
+        let outer = supervisor::supervise(policy, outer, move || {
+            Ok(init_state.clone().spawn_task().0)
+        });
+        (outer, kill)
+    }
 }
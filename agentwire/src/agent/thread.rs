@@ -1,4 +1,4 @@
-use super::{Agent, Kill};
+use super::{supervisor, Agent, Kill, RestartPolicy};
 use crate::{port, spawn_named_thread};
 use futures::prelude::*;
 use std::{fmt::Debug, future, io};
@@ -31,4 +31,29 @@ pub trait Thread: Agent + Send {
         });
         Ok((outer, future::pending().boxed()))
     }
+
+    /// Spawns a new thread running the agent event-loop under supervision and
+    /// returns a handle for bi-directional communication with the agent. When
+    /// the agent terminates, it is restarted from a copy of `self` according to
+    /// the `policy`.
+    ///
+    /// Must be called within a tokio runtime.
+    fn spawn_thread_supervised(
+        self,
+        policy: RestartPolicy,
+    ) -> io::Result<(port::Outer<Self>, Kill)>
+    where
+        Self: Clone,
+        Self::Input: Clone,
+    {
+        let init_state = self.clone();
+        let (outer, kill) = self.spawn_thread()?;
+        let outer = supervisor::supervise(policy, outer, move || {
+            init_state
+                .clone()
+                .spawn_thread()
+                .map(|(outer, _kill)| outer)
+        });
+        Ok((outer, kill))
+    }
 }
@@ -0,0 +1,129 @@
+use super::{Agent, Cell};
+use crate::port::{self, Port};
+use futures::prelude::*;
+use std::{
+    mem::replace,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{task, time};
+
+/// Watchdog for agents which stop making progress without exiting.
+///
+/// An agent makes progress when it consumes an input or produces an output. A
+/// watched agent with pending inputs, which doesn't make progress within the
+/// `deadline`, is considered hung. With `heartbeat` enabled, the agent is
+/// expected to make progress even without inputs, for example to produce
+/// periodic outputs.
+///
+/// Only a hung process-based agent is actually terminated. A hung task-based
+/// or thread-based agent is detached from the broker and left running.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[derive(Broker)]
+/// #[broker(plan = Plan, error = Error)]
+/// struct MyBroker {
+///     #[agent(process, watchdog = Watchdog {
+///         action: WatchdogAction::Restart,
+///         ..Watchdog::new(Duration::from_secs(5))
+///     })]
+///     foo: agent::Cell<Foo>,
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Watchdog {
+    /// Maximum time without progress.
+    pub deadline: Duration,
+    /// Expect progress even without pending inputs.
+    pub heartbeat: bool,
+    /// Action taken when the agent is hung.
+    pub action: WatchdogAction,
+}
+
+/// Action taken by a [`Watchdog`] when the agent is hung.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WatchdogAction {
+    /// Return [`BrokerError::AgentHung`](crate::BrokerError::AgentHung) from
+    /// the broker `run` method.
+    Report,
+    /// Kill the agent and return
+    /// [`BrokerError::AgentHung`](crate::BrokerError::AgentHung) from the
+    /// broker `run` method. The agent can be enabled again afterwards.
+    Kill,
+    /// Kill the agent and enable a new one in its place. The broker keeps
+    /// running.
+    Restart,
+}
+
+/// Deadline timer for a watched agent. Used by the generated broker `run`
+/// method.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct WatchdogTimer {
+    sleep: Option<Pin<Box<time::Sleep>>>,
+}
+
+impl Watchdog {
+    /// Creates a new watchdog with the given deadline, which reports hung
+    /// agents.
+    #[must_use]
+    pub fn new(deadline: Duration) -> Self {
+        Self {
+            deadline,
+            heartbeat: false,
+            action: WatchdogAction::Report,
+        }
+    }
+}
+
+impl WatchdogTimer {
+    /// Resolves to the time without progress when the agent is hung.
+    pub fn poll<T: Port>(
+        &mut self,
+        cx: &mut Context<'_>,
+        watchdog: &Watchdog,
+        port: &port::Outer<T>,
+    ) -> Poll<Duration> {
+        let Some(progress) = port.counters.progress(watchdog.heartbeat) else {
+            self.sleep = None;
+            return Poll::Pending;
+        };
+        let stalled = progress.elapsed();
+        if stalled >= watchdog.deadline {
+            self.sleep = None;
+            return Poll::Ready(stalled);
+        }
+        let deadline = time::Instant::from_std(progress + watchdog.deadline);
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(time::sleep_until(deadline)));
+        if sleep.deadline() != deadline {
+            sleep.as_mut().reset(deadline);
+        }
+        // The timer is only used for the wake-up, the state is checked above.
+        let _ = sleep.as_mut().poll(cx);
+        Poll::Pending
+    }
+
+    /// Takes the hung agent out of the `cell` and kills it in the background.
+    pub fn kill<T: Agent>(&mut self, cell: &mut Cell<T>, stalled: Duration) {
+        tracing::error!("Agent {} hung for {stalled:?}, killing", T::NAME);
+        self.sleep = None;
+        if let Cell::Enabled((_port, kill)) | Cell::Disabled((_port, kill)) =
+            replace(cell, Cell::Vacant)
+        {
+            let started = Instant::now();
+            task::spawn(async move {
+                kill.await;
+                tracing::info!(
+                    "Hung agent {} killed in {:?}",
+                    T::NAME,
+                    started.elapsed()
+                );
+            });
+        }
+    }
+}
@@ -118,11 +118,26 @@
 //! }
 //! ```
 //!
+//! A broker is shut down gracefully with the generated `shutdown` method. It
+//! stops accepting new inputs, lets the agents drain their queues for a bounded
+//! time while passing the remaining outputs to the plan, and then tears the
+//! agents down, the sources of `forward_to` before their targets.
+//!
+//! ```ignore
+//! broker.shutdown(&mut plan, Duration::from_secs(2)).await?;
+//! ```
+//!
 //! # Process-based agents
 //!
 //! Process-based agents are agents that run inside their own separate
 //! processes. They are isolated from the broker and other agents, and can be
 //! used to run untrusted or unreliable code.
+//! Additional hardening, like seccomp filters, capabilities, resource limits,
+//! and namespaces, is configured per agent with
+//! [`Initializer::sandbox`](agent::process::Initializer::sandbox).
+//! Agents implementing [`AsyncProcess`](agent::AsyncProcess) instead of
+//! [`Process`](agent::Process) run an asynchronous event-loop on a Tokio
+//! runtime inside the agent process.
 //!
 //! If process-based agents are used, a special initialization method should be
 //! called at the beginning of the program. It will branch the program into an
@@ -146,17 +161,33 @@
 //! }
 //! ```
 //!
+//! # Metrics
+//!
+//! The broker reports queue depths, message latencies, handler execution times,
+//! and agent restarts to a pluggable sink. See [`metrics`] module for more
+//! details.
+//!
+//! # Record and replay
+//!
+//! The messages of selected agents can be recorded into a file and replayed
+//! later in place of the real agents. See [`replay`] module for more details.
+//!
 //! # Testing
 //!
 //! The [`test`] macro is provided to simplify testing of brokers. See the macro
-//! documentation for more details.
+//! documentation for more details. Agents can be replaced with scripted mocks,
+//! see `mock` module (requires the `mock` feature).
 
 #![warn(missing_docs, unsafe_op_in_unsafe_fn)]
 #![warn(clippy::pedantic)]
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
 pub mod agent;
+pub mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod port;
+pub mod replay;
 pub mod testing_rt;
 
 pub use agent::Agent;
@@ -172,6 +203,13 @@ pub use agent::Agent;
 ///   init = init,
 ///   // Custom timeout in milliseconds. Defaults to 60000.
 ///   timeout = 10000,
+///   // Replay the agents with the `record` option from a recording. Relative
+///   // to the package root.
+///   replay = "tests/data/foo.rec",
+///   // Replace an agent with a scripted mock (requires the `mock` feature).
+///   // Can be repeated, the scripts for the same agent are used by its
+///   // successive spawns. The test fails if a script isn't finished.
+///   mock = agentwire::mock::Mock::<Foo>::new().expect(1).emit(2),
 /// )]
 /// async fn test_foo() {
 ///     let mut broker = new_broker!();
@@ -239,6 +277,20 @@ pub use agentwire_macros::test;
 ///       init_async,
 ///       // The process-agent has a custom logger
 ///       logger = self.process_logger().await,
+///       // The task-agent or thread-agent is restarted on termination
+///       // according to the policy (requires `Clone` for the agent and its
+///       // input)
+///       restart = agent::RestartPolicy::default(),
+///       // The agent outputs are forwarded to the inputs of other agents
+///       // (requires `port::Forward` implementations)
+///       forward_to = [bar_agent, baz_agent],
+///       // The agent is watched for hangs, the watchdog is evaluated once for
+///       // each new agent (not supported with `init_async`)
+///       watchdog = agent::Watchdog::new(Duration::from_secs(5)),
+///       // The agent messages can be recorded and replayed (requires `rkyv`
+///       // serialization for the agent input and output, and `check_bytes`
+///       // for the output, see `replay` module)
+///       record,
 ///     )]
 ///     foo: agent::Cell<Foo>,
 ///     // non-agent fields can be added as well
@@ -293,7 +345,7 @@ pub use agentwire_macros::test;
 /// ```
 pub use agentwire_macros::Broker;
 
-use std::{ffi::CString, fmt::Display, io, thread};
+use std::{ffi::CString, fmt::Display, io, thread, time::Duration};
 use thiserror::Error;
 
 /// Used to tell a broker whether it should exit early or go on as usual.
@@ -323,6 +375,9 @@ pub enum BrokerError<T: Display> {
     /// An agent has terminated.
     #[error("agent {0} terminated")]
     AgentTerminated(&'static str),
+    /// An agent hasn't made progress within its watchdog deadline.
+    #[error("agent {0} hung for {1:?}")]
+    AgentHung(&'static str, Duration),
 }
 
 fn spawn_named_thread<F, T>(name: impl Into<String>, f: F) -> thread::JoinHandle<T>
@@ -0,0 +1,110 @@
+//! Metrics of the message flow between a broker and its agents.
+//!
+//! The generated broker `run` method reports a set of metrics for each output
+//! message it handles, and the agent supervisors report restarts. By default
+//! the metrics are discarded. A custom sink can be installed once at the
+//! beginning of the program:
+//!
+//! ```
+//! use agentwire::{metrics, port::QueueDepth};
+//! use std::time::Duration;
+//!
+//! struct Statsd;
+//!
+//! impl metrics::Metrics for Statsd {
+//!     fn queue_depth(&self, agent: &'static str, depth: QueueDepth) {
+//!         // Send `depth.input` and `depth.output` as gauges.
+//!     }
+//!
+//!     fn handler_time(&self, agent: &'static str, duration: Duration) {
+//!         // Send `duration` as a timer.
+//!     }
+//! }
+//!
+//! assert!(metrics::set(Statsd).is_ok());
+//! ```
+//!
+//! [`TracingMetrics`] is provided to route the metrics to `tracing` events.
+
+use crate::port::QueueDepth;
+use std::{sync::OnceLock, time::Duration};
+
+static METRICS: OnceLock<Box<dyn Metrics>> = OnceLock::new();
+
+/// Sink for the message flow metrics. All methods do nothing by default.
+pub trait Metrics: Send + Sync {
+    /// Number of messages queued in the port of `agent`. Reported each time
+    /// the broker receives an output from the agent.
+    fn queue_depth(&self, _agent: &'static str, _depth: QueueDepth) {}
+
+    /// Time passed since `agent` took its latest input from the queue until
+    /// the broker handler invocation for an output. Unlike the source
+    /// timestamp, which is carried over along a chain of agents, it doesn't
+    /// include the time spent in the upstream agents. Not reported until the
+    /// agent takes an input.
+    fn latency(&self, _agent: &'static str, _latency: Duration) {}
+
+    /// Execution time of the broker handler for an output of `agent`.
+    fn handler_time(&self, _agent: &'static str, _duration: Duration) {}
+
+    /// The agent has been restarted after termination.
+    fn restart(&self, _agent: &'static str) {}
+}
+
+/// Metrics sink, which discards everything.
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// Metrics sink, which emits `tracing` events with the `agentwire::metrics`
+/// target.
+pub struct TracingMetrics;
+
+impl Metrics for TracingMetrics {
+    fn queue_depth(&self, agent: &'static str, depth: QueueDepth) {
+        tracing::debug!(
+            target: "agentwire::metrics",
+            agent,
+            input = depth.input,
+            input_capacity = depth.input_capacity,
+            output = depth.output,
+            output_capacity = depth.output_capacity,
+            "queue depth"
+        );
+    }
+
+    fn latency(&self, agent: &'static str, latency: Duration) {
+        tracing::debug!(
+            target: "agentwire::metrics",
+            agent,
+            latency_us = latency.as_micros(),
+            "message latency"
+        );
+    }
+
+    fn handler_time(&self, agent: &'static str, duration: Duration) {
+        tracing::debug!(
+            target: "agentwire::metrics",
+            agent,
+            duration_us = duration.as_micros(),
+            "handler time"
+        );
+    }
+
+    fn restart(&self, agent: &'static str) {
+        tracing::info!(target: "agentwire::metrics", agent, "agent restart");
+    }
+}
+
+/// Installs the global metrics sink. Returns the sink back if one is already
+/// installed.
+pub fn set<M: Metrics + 'static>(metrics: M) -> Result<(), M> {
+    let mut metrics = Some(metrics);
+    METRICS.get_or_init(|| Box::new(metrics.take().unwrap()));
+    metrics.map_or(Ok(()), Err)
+}
+
+/// Returns the global metrics sink.
+pub fn get() -> &'static dyn Metrics {
+    METRICS.get().map_or(&NoopMetrics, AsRef::as_ref)
+}
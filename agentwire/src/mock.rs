@@ -0,0 +1,261 @@
+//! Scripted mock agents for broker tests.
+//!
+//! A [`Mock`] replaces an agent of any kind with a task, which follows a
+//! script: it checks the inputs it receives and emits canned outputs. Mocks
+//! are installed with the `mock` option of the [`test`](crate::test) macro, or
+//! with [`install`]. Enabling an agent with an installed mock spawns the mock
+//! instead of the real agent, so a test doesn't need to construct the agent or
+//! to initialize process-based agents.
+//!
+//! ```ignore
+//! #[agentwire::test(mock = Mock::<Foo>::new().expect(1).emit(2))]
+//! async fn test_foo() {
+//!     let mut broker = new_broker!();
+//!     broker.enable_foo().unwrap();
+//!     // ...
+//! }
+//! ```
+//!
+//! An installed mock stays in place for the rest of the test process. Each
+//! spawn of the agent takes the next installed script, and once the scripts
+//! are used up, the agent is replaced with an empty mock. The real agent is
+//! never spawned.
+//!
+//! The [`test`](crate::test) macro calls [`verify`] at the end of the test,
+//! which fails the test if a mock panicked, e.g. on a failed expectation or an
+//! unexpected input, or if an installed script wasn't finished.
+//!
+//! This module requires the `mock` feature, which is meant to be enabled only
+//! for tests. Without it, enabling an agent doesn't look for mocks.
+
+use crate::{
+    agent::{Agent, Kill},
+    port::{self, Output, Port},
+};
+use futures::{future, prelude::*};
+use std::{
+    any::{Any, TypeId},
+    collections::VecDeque,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{task, time};
+
+static MOCKS: Mutex<Vec<(TypeId, VecDeque<Box<dyn Any + Send>>)>> =
+    Mutex::new(Vec::new());
+
+static PROGRESS: Mutex<Vec<Arc<Progress>>> = Mutex::new(Vec::new());
+
+// Progress of an installed script, checked by `verify`.
+struct Progress {
+    agent: &'static str,
+    remaining: AtomicUsize,
+    panic: Mutex<Option<String>>,
+}
+
+/// Script for a mock agent.
+pub struct Mock<T: Agent> {
+    steps: VecDeque<Step<T>>,
+}
+
+type Check<T> = Box<dyn FnMut(&<T as Port>::Input) + Send>;
+
+enum Step<T: Agent> {
+    Expect(Check<T>),
+    Emit(T::Output),
+    Sleep(Duration),
+}
+
+impl<T: Agent> Default for Mock<T> {
+    fn default() -> Self {
+        Self {
+            steps: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Agent> Mock<T> {
+    /// Creates an empty script. A mock with an empty script accepts no inputs
+    /// and emits no outputs.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits for the next input and asserts it is equal to `value`.
+    #[must_use]
+    pub fn expect(self, value: T::Input) -> Self
+    where
+        T::Input: PartialEq + Send,
+    {
+        self.expect_with(move |input| {
+            assert_eq!(*input, value, "unexpected input for mock agent {}", T::NAME);
+        })
+    }
+
+    /// Waits for the next input and passes it to `check`, which should panic
+    /// if the input is wrong.
+    #[must_use]
+    pub fn expect_with(
+        mut self,
+        check: impl FnMut(&T::Input) + Send + 'static,
+    ) -> Self {
+        self.steps.push_back(Step::Expect(Box::new(check)));
+        self
+    }
+
+    /// Emits `value`. The output is chained from the last received input, if
+    /// any.
+    #[must_use]
+    pub fn emit(mut self, value: T::Output) -> Self {
+        self.steps.push_back(Step::Emit(value));
+        self
+    }
+
+    /// Pauses the script for `duration`.
+    #[must_use]
+    pub fn sleep(mut self, duration: Duration) -> Self {
+        self.steps.push_back(Step::Sleep(duration));
+        self
+    }
+
+    async fn run(mut self, mut port: port::Inner<T>, progress: &Progress) {
+        let mut source_ts = None;
+        while let Some(step) = self.steps.pop_front() {
+            match step {
+                Step::Expect(mut check) => {
+                    let Some(input) = port.next().await else {
+                        tracing::warn!(
+                            "Mock agent {} closed with {} unfinished steps",
+                            T::NAME,
+                            self.steps.len() + 1
+                        );
+                        return;
+                    };
+                    check(&input.value);
+                    source_ts = Some(input.source_ts);
+                }
+                Step::Emit(value) => {
+                    let source_ts = source_ts.unwrap_or_else(Instant::now);
+                    if port.send(Output { value, source_ts }).await.is_err() {
+                        return;
+                    }
+                }
+                Step::Sleep(duration) => time::sleep(duration).await,
+            }
+            progress.remaining.fetch_sub(1, Ordering::Relaxed);
+        }
+        if let Some(input) = port.next().await {
+            panic!(
+                "unexpected input for mock agent {}: {:#?}",
+                T::NAME,
+                input.value
+            );
+        }
+    }
+}
+
+/// Installs a mock for the agent `T`. From now on the agent is always
+/// replaced with a mock. The scripts installed for the same agent are used by
+/// its spawns in the installation order.
+pub fn install<T: Agent>(mock: Mock<T>)
+where
+    T::Output: 'static,
+{
+    let progress = Arc::new(Progress {
+        agent: T::NAME,
+        remaining: AtomicUsize::new(mock.steps.len()),
+        panic: Mutex::new(None),
+    });
+    PROGRESS.lock().unwrap().push(Arc::clone(&progress));
+    let script: Box<dyn Any + Send> = Box::new((mock, progress));
+    let mut mocks = MOCKS.lock().unwrap();
+    match mocks
+        .iter_mut()
+        .find(|(type_id, _)| *type_id == TypeId::of::<T>())
+    {
+        Some((_, scripts)) => scripts.push_back(script),
+        None => mocks.push((TypeId::of::<T>(), VecDeque::from([script]))),
+    }
+}
+
+/// Panics if a mock panicked or if an installed script wasn't finished.
+/// Called by the [`test`](crate::test) macro at the end of the test.
+///
+/// # Panics
+///
+/// See above.
+pub fn verify() {
+    let failures = PROGRESS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|progress| {
+            if let Some(panic) = &*progress.panic.lock().unwrap() {
+                return Some(format!(
+                    "mock agent {} panicked: {panic}",
+                    progress.agent
+                ));
+            }
+            let remaining = progress.remaining.load(Ordering::Relaxed);
+            (remaining > 0).then(|| {
+                format!(
+                    "mock agent {} has {remaining} unfinished steps",
+                    progress.agent
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Spawns the installed mock for the agent, if there is one.
+#[doc(hidden)]
+#[must_use]
+pub fn mock_agent<T: Agent>() -> Option<(port::Outer<T>, Kill)> {
+    let script = {
+        let mut mocks = MOCKS.lock().unwrap();
+        let (_, scripts) = mocks
+            .iter_mut()
+            .find(|(type_id, _)| *type_id == TypeId::of::<T>())?;
+        scripts.pop_front()
+    };
+    let (mock, progress) = match script {
+        Some(script) => *script
+            .downcast::<(Mock<T>, Arc<Progress>)>()
+            .expect("mock registered for a wrong type"),
+        None => {
+            tracing::warn!(
+                "Mock scripts for agent {} are used up, spawning an empty mock",
+                T::NAME
+            );
+            let progress = Arc::new(Progress {
+                agent: T::NAME,
+                remaining: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+            });
+            PROGRESS.lock().unwrap().push(Arc::clone(&progress));
+            (Mock::new(), progress)
+        }
+    };
+    tracing::info!("Agent {} replaced with a mock", T::NAME);
+    let (inner, outer) = port::new();
+    task::spawn(async move {
+        if let Err(panic) = AssertUnwindSafe(mock.run(inner, &progress))
+            .catch_unwind()
+            .await
+        {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(ToString::to_string)
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            *progress.panic.lock().unwrap() = Some(message);
+        }
+    });
+    Some((outer, future::pending().boxed()))
+}
@@ -87,10 +87,57 @@
 //!     // ..
 //! }
 //! ```
+//!
+//! # Variable-size messages
+//!
+//! If the messages vary in size, e.g. camera frames or tensors, sizing the
+//! buffers for the worst case is wasteful. In this case a shared port can
+//! switch to [`SharedMode::Ring`]. Each direction then gets a ring buffer,
+//! which can hold several messages in flight. The input and output sizes become
+//! the expected message sizes, and a ring grows when a larger message arrives,
+//! up to messages of `max_size`.
+//!
+//! ```ignore
+//! impl SharedPort for Foo {
+//!     const SERIALIZED_INIT_SIZE: usize =
+//!         size_of::<usize>() + size_of::<<Foo as Archive>::Archived>();
+//!     const SERIALIZED_INPUT_SIZE: usize = 64 * 1024;
+//!     const SERIALIZED_OUTPUT_SIZE: usize = 1024;
+//!     const SHARED_MODE: SharedMode =
+//!         SharedMode::Ring { slots: 4, max_size: 64 * 1024 * 1024 };
+//! }
+//! ```
+//!
+//! # Forwarding
+//!
+//! Outputs of one agent can be routed to inputs of other agents directly by
+//! the broker with the `forward_to` option of the [`Broker`](crate::Broker)
+//! macro. The conversion is defined with the [`Forward`] trait, which can skip
+//! the outputs that shouldn't be forwarded. The forwarded inputs keep the
+//! source timestamp of the output.
+//!
+//! ```ignore
+//! impl port::Forward<Bar> for Foo {
+//!     fn forward(output: &Output) -> Option<BarInput> {
+//!         match output {
+//!             Output::Frame(frame) => Some(BarInput::Frame(frame.clone())),
+//!             _ => None,
+//!         }
+//!     }
+//! }
+//! ```
 
+mod async_remote;
+mod ring;
+
+pub use self::async_remote::AsyncRemoteInner;
+pub(crate) use self::ring::{serialize_message as serialize_to_vec, Scratch};
+
+use self::ring::Ring;
+use crate::agent::Watchdog;
 use futures::{
     channel::{
-        mpsc::{self, SendError},
+        mpsc::{self, SendError, TrySendError},
         oneshot,
     },
     future::{select, Either},
@@ -129,8 +176,12 @@ use std::{
     os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
     pin::Pin,
     ptr, slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
-    time::Instant,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 use tokio::task;
@@ -155,6 +206,9 @@ pub enum CreateSharedMemoryError {
     /// Error occured during semaphore initialization.
     #[error("sem_init: {0}")]
     SemInit(io::Error),
+    /// Error occured during `eventfd`.
+    #[error("eventfd: {0}")]
+    Eventfd(io::Error),
 }
 
 /// Error occured during shared memory destruction.
@@ -168,6 +222,15 @@ pub enum DestroySharedMemoryError {
     SemDestroy(io::Error),
 }
 
+/// Error returned when a message exceeds the `max_size` of a
+/// [`SharedMode::Ring`] port.
+#[derive(Error, Debug)]
+#[error("IPC message exceeds the limit of {max_size} bytes")]
+pub struct MessageTooLarge {
+    /// Message size limit of the port.
+    pub max_size: usize,
+}
+
 /// Error returned by [`Outer::send_unjam`].
 #[derive(Error, Debug)]
 pub enum SendUnjamError {
@@ -202,6 +265,27 @@ pub trait Port: 'static {
     const OUTPUT_CAPACITY: usize;
 }
 
+/// Layout of the shared memory of a [`SharedPort`].
+#[derive(Clone, Copy, Debug)]
+pub enum SharedMode {
+    /// One message at a time in fixed-size buffers. The serialized sizes are
+    /// hard limits.
+    Fixed,
+    /// Several messages in flight in growable ring buffers. The serialized
+    /// sizes are the expected message sizes used for the initial capacity.
+    Ring {
+        /// Number of expected-size messages the rings initially hold.
+        slots: usize,
+        /// Upper limit for a single message size in bytes. Sending a larger
+        /// output fails with [`MessageTooLarge`] (see
+        /// [`RemoteInner::send_ring`]), and a larger input closes
+        /// the input channel of the port. A ring grows up to twice this limit,
+        /// which is reserved in the address space of both processes, but the
+        /// memory is allocated only as the ring grows.
+        max_size: usize,
+    },
+}
+
 /// Shared memory serializer.
 pub type SharedSerializer<'a> = CompositeSerializer<
     BufferSerializer<&'a mut [u8]>,
@@ -229,6 +313,17 @@ where
     /// Buffer size for initial agent state. Must be at least
     /// `size_of::<usize>()` for a zero-sized state.
     const SERIALIZED_INIT_SIZE: usize;
+
+    /// Shared memory layout. See [`SharedMode`] for available options.
+    const SHARED_MODE: SharedMode = SharedMode::Fixed;
+}
+
+/// Conversion of outputs of the agent to inputs of the agent `T` for
+/// forwarding.
+pub trait Forward<T: Port>: Port {
+    /// Converts an output to an input of `T`. Returns `None` if the output
+    /// shouldn't be forwarded.
+    fn forward(output: &Self::Output) -> Option<T::Input>;
 }
 
 /// Input message.
@@ -263,21 +358,30 @@ pub struct Output<T: Port> {
 /// A handle for bi-directional communication for the outside of the computation
 /// unit. The type implements both [`Sink`] and [`Stream`] for the input and the
 /// output channels respectively.
+///
+/// Messages sent or received directly through the `tx` and `rx` channels are not
+/// counted in [`queue_depth`](Self::queue_depth).
 pub struct Outer<T: Port> {
     /// Sender channel for the computation unit input.
     pub tx: OuterTx<T>,
     /// Receiver channel for the computation unit output.
     pub rx: OuterRx<T>,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) watchdog: Option<Watchdog>,
 }
 
 /// A handle for bi-directional communication for the inside of the computation
 /// unit. The type implements both [`Sink`] and [`Stream`] for the input and the
 /// output channels respectively.
+///
+/// Messages sent or received directly through the `tx` and `rx` channels are not
+/// counted in [`queue_depth`](Self::queue_depth).
 pub struct Inner<T: Port> {
     /// Sender channel for the computation unit output.
     pub tx: InnerTx<T>,
     /// Receiver channel for the computation unit input.
     pub rx: InnerRx<T>,
+    pub(crate) counters: Arc<Counters>,
 }
 
 /// A handle for bi-directional communication for the inside of the computation
@@ -292,6 +396,34 @@ where
 {
     shared_memory: *mut SharedMemory<T>,
     scratch: Option<FallbackScratch<HeapScratch<SCRATCH_SIZE>, AllocScratch>>,
+    staging: Vec<u8>,
+    pending_release: usize,
+    pub(crate) closed: bool,
+}
+
+/// Number of messages queued in a port.
+#[derive(Clone, Copy, Debug)]
+pub struct QueueDepth {
+    /// Input messages sent but not yet received.
+    pub input: usize,
+    /// Input channel capacity.
+    pub input_capacity: usize,
+    /// Output messages sent but not yet received.
+    pub output: usize,
+    /// Output channel capacity.
+    pub output_capacity: usize,
+}
+
+pub(crate) struct Counters {
+    input: AtomicUsize,
+    output: AtomicUsize,
+    dropped_inputs: AtomicUsize,
+    epoch: Instant,
+    // Nanoseconds since `epoch` of the last consumed input or produced output,
+    // or of the input which arrived to an empty queue.
+    progress: AtomicU64,
+    // Nanoseconds since `epoch` of the last consumed input, zero if none.
+    dequeued: AtomicU64,
 }
 
 /// Sender channel for the computation unit input.
@@ -313,17 +445,107 @@ type InitialInputs = Vec<(Box<[u8]>, Instant)>;
 pub fn new<T: Port>() -> (Inner<T>, Outer<T>) {
     let (input_tx, input_rx) = mpsc::channel(T::INPUT_CAPACITY);
     let (output_tx, output_rx) = mpsc::channel(T::OUTPUT_CAPACITY);
+    let counters = Arc::new(Counters::default());
     let inner = Inner {
         tx: output_tx,
         rx: input_rx,
+        counters: Arc::clone(&counters),
     };
     let outer = Outer {
         tx: input_tx,
         rx: output_rx,
+        counters,
+        watchdog: None,
     };
     (inner, outer)
 }
 
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            input: AtomicUsize::new(0),
+            output: AtomicUsize::new(0),
+            dropped_inputs: AtomicUsize::new(0),
+            epoch: Instant::now(),
+            progress: AtomicU64::new(0),
+            dequeued: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Counters {
+    pub(crate) fn push_input(&self) {
+        if self.input.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.touch();
+        }
+    }
+
+    pub(crate) fn pop_input(&self) {
+        decrement(&self.input);
+        let nanos = self.touch();
+        self.dequeued.store(nanos.max(1), Ordering::Relaxed);
+    }
+
+    /// Rolls back [`push_input`](Self::push_input) of a message which wasn't
+    /// sent. Unlike [`pop_input`](Self::pop_input), it isn't a progress.
+    pub(crate) fn unpush_input(&self) {
+        decrement(&self.input);
+    }
+
+    pub(crate) fn drop_input(&self) {
+        self.dropped_inputs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn push_output(&self) {
+        self.output.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub(crate) fn pop_output(&self) {
+        decrement(&self.output);
+    }
+
+    /// Returns the time of the last progress if the agent is expected to make
+    /// progress, that is if it has pending inputs, or always with `heartbeat`.
+    pub(crate) fn progress(&self, heartbeat: bool) -> Option<Instant> {
+        (heartbeat || self.input.load(Ordering::Relaxed) > 0).then(|| {
+            self.epoch + Duration::from_nanos(self.progress.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Returns the time passed since the last consumed input.
+    pub(crate) fn since_dequeued(&self) -> Option<Duration> {
+        let nanos = self.dequeued.load(Ordering::Relaxed);
+        (nanos > 0).then(|| (self.epoch + Duration::from_nanos(nanos)).elapsed())
+    }
+
+    fn touch(&self) -> u64 {
+        let nanos = self
+            .epoch
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.progress.fetch_max(nanos, Ordering::Relaxed);
+        nanos
+    }
+
+    pub(crate) fn depth<T: Port>(&self) -> QueueDepth {
+        QueueDepth {
+            input: self.input.load(Ordering::Relaxed),
+            input_capacity: T::INPUT_CAPACITY,
+            output: self.output.load(Ordering::Relaxed),
+            output_capacity: T::OUTPUT_CAPACITY,
+        }
+    }
+}
+
+fn decrement(counter: &AtomicUsize) {
+    // Saturate in case a message was sent bypassing the counters.
+    let _ = counter
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+}
+
 impl<T: Port> Input<T> {
     /// Creates a new input value with the source timestamp of now.
     pub fn new(value: T::Input) -> Self {
@@ -358,6 +580,18 @@ impl<T: Port> Input<T> {
     }
 }
 
+impl<T: Port> Clone for Input<T>
+where
+    T::Input: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            source_ts: self.source_ts,
+        }
+    }
+}
+
 impl<T: Port> ArchivedInput<'_, T>
 where
     T::Input: Archive,
@@ -420,6 +654,54 @@ impl<T: Port> Output<T> {
 }
 
 impl<T: Port> Outer<T> {
+    /// Returns the number of messages queued in the port.
+    #[must_use]
+    pub fn queue_depth(&self) -> QueueDepth {
+        self.counters.depth::<T>()
+    }
+
+    /// Sets the watchdog of the agent. Used by the generated broker `enable_*`
+    /// methods.
+    #[doc(hidden)]
+    pub fn set_watchdog(&mut self, watchdog: Watchdog) {
+        self.watchdog = Some(watchdog);
+    }
+
+    /// Returns the watchdog of the agent. Used by the generated broker `run`
+    /// method.
+    #[doc(hidden)]
+    #[must_use]
+    pub fn watchdog(&self) -> Option<Watchdog> {
+        self.watchdog
+    }
+
+    /// Returns the time passed since the agent took its latest input from the
+    /// queue, or `None` if it hasn't taken any yet.
+    #[must_use]
+    pub fn since_dequeued(&self) -> Option<Duration> {
+        self.counters.since_dequeued()
+    }
+
+    /// Returns the number of messages forwarded by the broker but dropped
+    /// because the input queue was full.
+    #[must_use]
+    pub fn dropped_inputs(&self) -> usize {
+        self.counters.dropped_inputs.load(Ordering::Relaxed)
+    }
+
+    /// Sends a message if there is room in the input queue.
+    pub fn try_send(
+        &mut self,
+        message: Input<T>,
+    ) -> Result<(), TrySendError<Input<T>>> {
+        self.counters.push_input();
+        let result = self.tx.try_send(message);
+        if result.is_err() {
+            self.counters.unpush_input();
+        }
+        result
+    }
+
     /// Sends a message avoiding jams. Reading a message from the queue if
     /// necessary.
     ///
@@ -432,13 +714,22 @@ impl<T: Port> Outer<T> {
         &mut self,
         message: Input<T>,
     ) -> Result<(), SendUnjamError> {
+        self.counters.push_input();
         let mut send = self.tx.send(message).fuse();
         let mut recv = self.rx.next();
         loop {
             select_biased! {
-                result = send => break Ok(result?),
+                result = send => {
+                    if result.is_err() {
+                        self.counters.unpush_input();
+                    }
+                    break Ok(result?);
+                }
                 item = recv => match item {
-                    Some(item) => drop(item),
+                    Some(item) => {
+                        self.counters.pop_output();
+                        drop(item);
+                    }
                     None => break Err(SendUnjamError::Closed),
                 }
             }
@@ -446,6 +737,14 @@ impl<T: Port> Outer<T> {
     }
 }
 
+impl<T: Port> Inner<T> {
+    /// Returns the number of messages queued in the port.
+    #[must_use]
+    pub fn queue_depth(&self) -> QueueDepth {
+        self.counters.depth::<T>()
+    }
+}
+
 impl<T: Port> Stream for Outer<T> {
     type Item = Output<T>;
 
@@ -453,7 +752,11 @@ impl<T: Port> Stream for Outer<T> {
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.rx).poll_next(cx)
+        let poll = Pin::new(&mut self.rx).poll_next(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.counters.pop_output();
+        }
+        poll
     }
 }
 
@@ -474,7 +777,12 @@ impl<T: Port> Sink<Input<T>> for Outer<T> {
     }
 
     fn start_send(mut self: Pin<&mut Self>, item: Input<T>) -> Result<(), Self::Error> {
-        Pin::new(&mut self.tx).start_send(item)
+        self.counters.push_input();
+        let result = Pin::new(&mut self.tx).start_send(item);
+        if result.is_err() {
+            self.counters.unpush_input();
+        }
+        result
     }
 
     fn poll_flush(
@@ -499,7 +807,11 @@ impl<T: Port> Stream for Inner<T> {
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.rx).poll_next(cx)
+        let poll = Pin::new(&mut self.rx).poll_next(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.counters.pop_input();
+        }
+        poll
     }
 }
 
@@ -523,7 +835,12 @@ impl<T: Port> Sink<Output<T>> for Inner<T> {
         mut self: Pin<&mut Self>,
         item: Output<T>,
     ) -> Result<(), Self::Error> {
-        Pin::new(&mut self.tx).start_send(item)
+        self.counters.push_output();
+        let result = Pin::new(&mut self.tx).start_send(item);
+        if result.is_err() {
+            self.counters.pop_output();
+        }
+        result
     }
 
     fn poll_flush(
@@ -548,6 +865,9 @@ impl<T: Port> Sink<Output<T>> for Inner<T> {
 // 1. Input buffer 0
 // 2. Input buffer 1
 // 3. Output buffer
+//
+// In the ring mode the data buffer contains only the initial agent state, and
+// it is followed by the input ring and the output ring. See the `ring` module.
 struct SharedMemory<T>
 where
     T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
@@ -564,6 +884,19 @@ where
     output_ts: Instant,
     output_tx: sem_t,
     output_rx: sem_t,
+    // Eventfds signaled by the broker side after posting an input and after
+    // freeing the output space respectively, which the agent side may wait
+    // for. Used by `AsyncRemoteInner`.
+    input_doorbell: RawFd,
+    output_doorbell: RawFd,
+    // Close handshake. Once the input is closed and all inputs are posted, the
+    // broker side sets `input_closed` and posts the input without a message.
+    // The agent side acknowledges it after its last output by setting
+    // `output_closed` and posting the output without a message, then waits
+    // for `closed`, which the broker side posts after receiving all outputs.
+    input_closed: AtomicBool,
+    output_closed: AtomicBool,
+    closed: sem_t,
     _marker: PhantomData<T>,
 }
 
@@ -576,14 +909,31 @@ where
     <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
 {
     fn size_of() -> NonZeroUsize {
-        let size = mem::size_of::<Self>()
-            + max(
-                mem::size_of::<usize>() + mem::size_of::<T::Archived>(),
-                T::SERIALIZED_INPUT_SIZE * 2 + T::SERIALIZED_OUTPUT_SIZE,
-            );
+        let size = match T::SHARED_MODE {
+            SharedMode::Fixed => {
+                mem::size_of::<Self>()
+                    + max(
+                        mem::size_of::<usize>() + mem::size_of::<T::Archived>(),
+                        T::SERIALIZED_INPUT_SIZE * 2 + T::SERIALIZED_OUTPUT_SIZE,
+                    )
+            }
+            SharedMode::Ring { max_size, .. } => {
+                Self::rings_offset() + ring::reservation(max_size) * 2
+            }
+        };
         NonZeroUsize::new(size).expect("to always be positive")
     }
 
+    fn rings_offset() -> usize {
+        ring::align_up(
+            mem::size_of::<Self>()
+                + max(
+                    mem::size_of::<usize>() + mem::size_of::<T::Archived>(),
+                    T::SERIALIZED_INIT_SIZE,
+                ),
+        )
+    }
+
     unsafe fn create(
         name: &str,
     ) -> Result<(*mut Self, OwnedFd), CreateSharedMemoryError> {
@@ -619,8 +969,30 @@ where
                 .map_err(CreateSharedMemoryError::SemInit)?;
             sem_init(&mut (*ptr).output_rx, 1, 0)
                 .map_err(CreateSharedMemoryError::SemInit)?;
+            sem_init(&mut (*ptr).closed, 1, 0)
+                .map_err(CreateSharedMemoryError::SemInit)?;
+            ptr::write(&mut (*ptr).input_closed, AtomicBool::new(false));
+            ptr::write(&mut (*ptr).output_closed, AtomicBool::new(false));
             (*ptr).input_count = 0;
             (*ptr).input_index = 0;
+            (*ptr).input_doorbell =
+                eventfd().map_err(CreateSharedMemoryError::Eventfd)?;
+            (*ptr).output_doorbell =
+                eventfd().map_err(CreateSharedMemoryError::Eventfd)?;
+            if let SharedMode::Ring { slots, max_size } = T::SHARED_MODE {
+                Ring::init(
+                    (*ptr).input_ring(),
+                    T::SERIALIZED_INPUT_SIZE,
+                    slots,
+                    max_size,
+                )?;
+                Ring::init(
+                    (*ptr).output_ring(),
+                    T::SERIALIZED_OUTPUT_SIZE,
+                    slots,
+                    max_size,
+                )?;
+            }
         }
         Ok((ptr, fd))
     }
@@ -651,12 +1023,69 @@ where
                 .map_err(DestroySharedMemoryError::SemDestroy)?;
             sem_destroy(&mut (*ptr).output_rx)
                 .map_err(DestroySharedMemoryError::SemDestroy)?;
+            sem_destroy(&mut (*ptr).closed)
+                .map_err(DestroySharedMemoryError::SemDestroy)?;
+            if let SharedMode::Ring { .. } = T::SHARED_MODE {
+                Ring::destroy((*ptr).input_ring())?;
+                Ring::destroy((*ptr).output_ring())?;
+            }
+            drop(OwnedFd::from_raw_fd((*ptr).input_doorbell));
+            drop(OwnedFd::from_raw_fd((*ptr).output_doorbell));
             munmap(ptr.cast(), Self::size_of().get())
                 .map_err(DestroySharedMemoryError::Munmap)?;
         }
         Ok(())
     }
 
+    /// Tells the agent side that no more inputs follow. Called by the broker
+    /// side once the agent side has taken all the posted inputs.
+    unsafe fn close_input(&mut self) {
+        unsafe {
+            self.input_closed.store(true, Ordering::Release);
+            match T::SHARED_MODE {
+                SharedMode::Fixed => {
+                    sem_post(&mut self.input_rx).expect("semaphore failure");
+                }
+                SharedMode::Ring { .. } => Ring::wake(self.input_ring()),
+            }
+            self.ring_input_doorbell();
+        }
+    }
+
+    /// Returns `true` if the agent side was woken up by
+    /// [`close_input`](Self::close_input) rather than by an input.
+    unsafe fn is_input_closed(&mut self) -> bool {
+        unsafe {
+            self.input_closed.load(Ordering::Acquire)
+                && match T::SHARED_MODE {
+                    SharedMode::Fixed => true,
+                    SharedMode::Ring { .. } => Ring::is_empty(self.input_ring()),
+                }
+        }
+    }
+
+    /// Returns `true` if the broker side was woken up by the close
+    /// acknowledgement rather than by an output.
+    unsafe fn is_output_closed(&mut self) -> bool {
+        unsafe {
+            self.output_closed.load(Ordering::Acquire)
+                && match T::SHARED_MODE {
+                    SharedMode::Fixed => true,
+                    SharedMode::Ring { .. } => Ring::is_empty(self.output_ring()),
+                }
+        }
+    }
+
+    /// Wakes up the agent side waiting for an input.
+    unsafe fn ring_input_doorbell(&mut self) {
+        ring_doorbell(self.input_doorbell);
+    }
+
+    /// Wakes up the agent side waiting for the output space.
+    unsafe fn ring_output_doorbell(&mut self) {
+        ring_doorbell(self.output_doorbell);
+    }
+
     unsafe fn init_state(&mut self) -> &mut [u8] {
         unsafe {
             slice::from_raw_parts_mut(
@@ -678,6 +1107,27 @@ where
         }
     }
 
+    unsafe fn input_ring(&mut self) -> *mut Ring {
+        unsafe { self.ring(0) }
+    }
+
+    unsafe fn output_ring(&mut self) -> *mut Ring {
+        unsafe { self.ring(1) }
+    }
+
+    #[allow(clippy::cast_ptr_alignment)] // rings are aligned by `ring::align_up`
+    unsafe fn ring(&mut self, n: usize) -> *mut Ring {
+        let SharedMode::Ring { max_size, .. } = T::SHARED_MODE else {
+            unreachable!("rings are available only in the ring mode");
+        };
+        unsafe {
+            ptr::addr_of_mut!(*self)
+                .cast::<u8>()
+                .add(Self::rings_offset() + ring::reservation(max_size) * n)
+                .cast::<Ring>()
+        }
+    }
+
     unsafe fn output(&mut self) -> &mut [u8] {
         unsafe {
             slice::from_raw_parts_mut(
@@ -700,6 +1150,11 @@ where
     <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
 {
     /// Sets up shared memory for this channel.
+    ///
+    /// Returns the shared memory file descriptor, the input and output doorbell
+    /// file descriptors, which must be inherited by an asynchronous agent
+    /// process along with the shared memory, and a future to tear the shared
+    /// memory down.
     #[expect(clippy::type_complexity)]
     pub fn into_shared_memory(
         self,
@@ -709,18 +1164,41 @@ where
     ) -> Result<
         (
             OwnedFd,
+            [RawFd; 2],
             impl Future<Output = Result<(Self, InitialInputs), DestroySharedMemoryError>>,
         ),
         CreateSharedMemoryError,
     > {
-        let Self { tx, rx } = self;
+        let Self { tx, rx, counters } = self;
         let (ptr, fd) = unsafe { SharedMemory::<T>::create(name)? };
+        let doorbells = unsafe { [(*ptr).input_doorbell, (*ptr).output_doorbell] };
         let addr = ptr as usize;
         let (stop_tx_tx, stop_tx_rx) = oneshot::channel();
         let (stop_rx_tx, stop_rx_rx) = oneshot::channel();
         set_init_state(addr, init_state);
-        let tx_task = spawn_shared_tx_task(tx, addr, stop_tx_rx);
-        let rx_task = spawn_shared_rx_task(rx, addr, stop_rx_rx, initial_inputs);
+        let (tx_task, rx_task) = match T::SHARED_MODE {
+            SharedMode::Fixed => (
+                spawn_shared_tx_task(tx, Arc::clone(&counters), addr, stop_tx_rx),
+                spawn_shared_rx_task(
+                    rx,
+                    Arc::clone(&counters),
+                    addr,
+                    stop_rx_rx,
+                    initial_inputs,
+                ),
+            ),
+            SharedMode::Ring { max_size, .. } => (
+                ring::spawn_tx_task(tx, Arc::clone(&counters), addr, stop_tx_rx),
+                ring::spawn_rx_task(
+                    rx,
+                    Arc::clone(&counters),
+                    addr,
+                    stop_rx_rx,
+                    initial_inputs,
+                    max_size,
+                ),
+            ),
+        };
         let close = async move {
             let _ = stop_tx_tx.send(());
             let _ = stop_rx_tx.send(());
@@ -728,6 +1206,12 @@ where
             let (rx, mut inputs) = rx_task.await.unwrap();
             unsafe {
                 let shared_memory = addr as *mut SharedMemory<T>;
+                if let SharedMode::Ring { .. } = T::SHARED_MODE {
+                    // Initial inputs are taken from the end.
+                    inputs.extend(ring::unread_inputs::<T>(addr).into_iter().rev());
+                    SharedMemory::destroy(shared_memory)?;
+                    return Ok((Self { tx, rx, counters }, inputs));
+                }
                 assert!((*shared_memory).input_count <= 2);
                 for mut i in 0..(*shared_memory).input_count {
                     if (*shared_memory).input_count == 2
@@ -740,10 +1224,10 @@ where
                     inputs.push((input, input_ts));
                 }
                 SharedMemory::destroy(shared_memory)?;
-                Ok((Self { tx, rx }, inputs))
+                Ok((Self { tx, rx, counters }, inputs))
             }
         };
-        Ok((fd, close))
+        Ok((fd, doorbells, close))
     }
 }
 
@@ -760,6 +1244,9 @@ where
         Ok(RemoteInner {
             shared_memory: unsafe { SharedMemory::<T>::from_fd(shmem_fd)? },
             scratch: Some(FallbackScratch::default()),
+            staging: Vec::new(),
+            pending_release: 0,
+            closed: false,
         })
     }
 
@@ -775,30 +1262,54 @@ where
     }
 
     /// Waits for a value on the receiver half.
+    ///
+    /// Returns `None` once the broker closes the input and all inputs are
+    /// received. The agent should return from its event-loop then, and the
+    /// closed input is acknowledged when the channel is dropped.
     #[allow(clippy::missing_panics_doc)]
-    pub fn recv(&mut self) -> ArchivedInput<'_, T> {
+    pub fn recv(&mut self) -> Option<ArchivedInput<'_, T>> {
+        if self.closed {
+            return None;
+        }
+        if let SharedMode::Ring { .. } = T::SHARED_MODE {
+            return self.recv_ring();
+        }
         unsafe {
             sem_wait(&mut (*self.shared_memory).input_rx).expect("semaphore failure");
+            if (*self.shared_memory).is_input_closed() {
+                self.closed = true;
+                return None;
+            }
             let input_index = 1 - (*self.shared_memory).input_index;
             let value = deserialize_message::<T::Input>(
                 (*self.shared_memory).input(input_index),
             );
             let source_ts = (*self.shared_memory).input_ts[input_index];
             sem_post(&mut (*self.shared_memory).input_tx).expect("semaphore failure");
-            ArchivedInput { value, source_ts }
+            Some(ArchivedInput { value, source_ts })
         }
     }
 
     /// Tries to receive a value on the receiver half. This function doesn't
-    /// block and returns `None` if the channel is empty.
+    /// block and returns `None` if the channel is empty, or closed like in
+    /// [`recv`](Self::recv).
     #[allow(clippy::missing_panics_doc)]
     pub fn try_recv(&mut self) -> Option<ArchivedInput<'_, T>> {
+        if self.closed {
+            return None;
+        }
+        if let SharedMode::Ring { .. } = T::SHARED_MODE {
+            self.release_input();
+            let has_items =
+                unsafe { Ring::has_items((*self.shared_memory).input_ring()) };
+            return if has_items { self.recv_ring() } else { None };
+        }
         unsafe {
             if sem_getvalue(&mut (*self.shared_memory).input_rx)
                 .expect("semaphore failure")
                 > 0
             {
-                Some(self.recv())
+                self.recv()
             } else {
                 None
             }
@@ -806,8 +1317,17 @@ where
     }
 
     /// Sends a value on this channel.
-    #[allow(clippy::missing_panics_doc)]
+    ///
+    /// # Panics
+    ///
+    /// In the [`SharedMode::Ring`] mode, if the value exceeds `max_size`. Use
+    /// [`Self::send_ring`] to handle this case.
     pub fn send(&mut self, output: &Output<T>) {
+        if let SharedMode::Ring { .. } = T::SHARED_MODE {
+            self.send_ring(output)
+                .expect("failed to send an IPC message");
+            return;
+        }
         unsafe {
             sem_wait(&mut (*self.shared_memory).output_tx).expect("semaphore failure");
             serialize_message(
@@ -822,8 +1342,16 @@ where
 
     /// Tries to send a value on this channel. This function doesn't block and
     /// do nothing if the channel is full (in which case it returns `false`).
-    #[allow(clippy::missing_panics_doc)]
+    ///
+    /// # Panics
+    ///
+    /// Same as [`Self::send`]. Use [`Self::try_send_ring`] to handle this case.
     pub fn try_send(&mut self, output: &Output<T>) -> bool {
+        if let SharedMode::Ring { .. } = T::SHARED_MODE {
+            return self
+                .try_send_ring(output)
+                .expect("failed to send an IPC message");
+        }
         unsafe {
             if sem_getvalue(&mut (*self.shared_memory).output_tx)
                 .expect("semaphore failure")
@@ -836,6 +1364,129 @@ where
             }
         }
     }
+
+    /// Sends a value on this channel like [`Self::send`], but returns an error
+    /// instead of panicking if the value exceeds `max_size`. Never fails in the
+    /// [`SharedMode::Fixed`] mode.
+    ///
+    /// # Errors
+    ///
+    /// In the [`SharedMode::Ring`] mode, returns an error if the value exceeds
+    /// `max_size`. The value is not sent in this case.
+    pub fn send_ring(&mut self, output: &Output<T>) -> Result<(), MessageTooLarge> {
+        let SharedMode::Ring { max_size, .. } = T::SHARED_MODE else {
+            self.send(output);
+            return Ok(());
+        };
+        let size = ring::serialize_limited(
+            &mut self.staging,
+            &mut self.scratch,
+            &output.value,
+            max_size,
+        )?;
+        unsafe {
+            Ring::write(
+                (*self.shared_memory).output_ring(),
+                &self.staging[..size],
+                output.source_ts,
+                max_size,
+            );
+        }
+        Ok(())
+    }
+
+    /// Tries to send a value on this channel like [`Self::try_send`], but
+    /// returns an error instead of panicking if the value exceeds `max_size`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Self::send_ring`].
+    pub fn try_send_ring(
+        &mut self,
+        output: &Output<T>,
+    ) -> Result<bool, MessageTooLarge> {
+        let SharedMode::Ring { max_size, .. } = T::SHARED_MODE else {
+            return Ok(self.try_send(output));
+        };
+        let size = ring::serialize_limited(
+            &mut self.staging,
+            &mut self.scratch,
+            &output.value,
+            max_size,
+        )?;
+        Ok(unsafe {
+            Ring::try_write(
+                (*self.shared_memory).output_ring(),
+                &self.staging[..size],
+                output.source_ts,
+                max_size,
+            )
+        })
+    }
+
+    fn recv_ring(&mut self) -> Option<ArchivedInput<'_, T>> {
+        self.release_input();
+        unsafe {
+            let ring = (*self.shared_memory).input_ring();
+            Ring::wait(ring);
+            if (*self.shared_memory).is_input_closed() {
+                self.closed = true;
+                return None;
+            }
+            let (payload, source_ts, size) = Ring::peek(ring);
+            // The record stays in the ring while the archived value is borrowed.
+            self.pending_release = size;
+            let value = rkyv::archived_root::<T::Input>(payload);
+            Some(ArchivedInput { value, source_ts })
+        }
+    }
+
+    /// Acknowledges the closed input after the last output, and waits until
+    /// the broker side receives all the outputs.
+    fn ack_close(&mut self) {
+        unsafe {
+            let shared_memory = self.shared_memory;
+            if let SharedMode::Fixed = T::SHARED_MODE {
+                // The broker side has taken the last output once the slot is
+                // free, so it can tell the acknowledgement from an output.
+                sem_wait(&mut (*shared_memory).output_tx).expect("semaphore failure");
+            }
+            (*shared_memory)
+                .output_closed
+                .store(true, Ordering::Release);
+            match T::SHARED_MODE {
+                SharedMode::Fixed => {
+                    sem_post(&mut (*shared_memory).output_rx)
+                        .expect("semaphore failure");
+                }
+                SharedMode::Ring { .. } => Ring::wake((*shared_memory).output_ring()),
+            }
+            sem_wait(&mut (*shared_memory).closed).expect("semaphore failure");
+        }
+    }
+
+    fn release_input(&mut self) {
+        let size = mem::take(&mut self.pending_release);
+        if size > 0 {
+            unsafe { Ring::release((*self.shared_memory).input_ring(), size) };
+        }
+    }
+}
+
+impl<T> Drop for RemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    fn drop(&mut self) {
+        if self.closed {
+            self.ack_close();
+            tracing::info!("Agent input closed, exiting");
+        }
+    }
 }
 
 fn serialize_message<T>(
@@ -883,8 +1534,30 @@ where
     }
 }
 
+/// Ends the output stream if the agent side has acknowledged the closed input.
+/// All its outputs are already sent to `tx` at this point.
+unsafe fn close_output<T>(addr: usize, tx: &mut InnerTx<T>) -> bool
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    unsafe {
+        let shared_memory = addr as *mut SharedMemory<T>;
+        if !(*shared_memory).is_output_closed() {
+            return false;
+        }
+        tx.close_channel();
+        sem_post(&mut (*shared_memory).closed).expect("semaphore failure");
+        true
+    }
+}
+
 fn spawn_shared_tx_task<T>(
     mut tx: InnerTx<T>,
+    counters: Arc<Counters>,
     addr: usize,
     mut stop_tx_rx: oneshot::Receiver<()>,
 ) -> task::JoinHandle<InnerTx<T>>
@@ -914,6 +1587,9 @@ where
                 sem_wait.await.unwrap();
                 break;
             }
+            if unsafe { close_output::<T>(addr, &mut tx) } {
+                break;
+            }
             let (value, source_ts) = unsafe {
                 let shared_memory = addr as *mut SharedMemory<T>;
                 let archived =
@@ -924,11 +1600,16 @@ where
                     .unwrap();
                 let source_ts = (*shared_memory).output_ts;
                 sem_post(&mut (*shared_memory).output_tx).expect("semaphore failure");
+                (*shared_memory).ring_output_doorbell();
                 (value, source_ts)
             };
+            counters.push_output();
             let mut send = tx.feed(Output { value, source_ts });
             match select(&mut stop_tx_rx, &mut send).await {
-                Either::Left((_, _)) | Either::Right((Err(_), _)) => break,
+                Either::Left((_, _)) | Either::Right((Err(_), _)) => {
+                    counters.pop_output();
+                    break;
+                }
                 Either::Right((Ok(result), _)) => result,
             }
             sem_wait = spawn_sem_wait();
@@ -939,6 +1620,7 @@ where
 
 fn spawn_shared_rx_task<T>(
     mut rx: InnerRx<T>,
+    counters: Arc<Counters>,
     addr: usize,
     mut stop_rx_rx: oneshot::Receiver<()>,
     mut initial_inputs: InitialInputs,
@@ -974,8 +1656,19 @@ where
                 Either::Left((input, input_ts))
             } else {
                 match select(&mut stop_rx_rx, rx.next()).await {
-                    Either::Left((_, _)) | Either::Right((None, _)) => break,
-                    Either::Right((Some(input), _)) => Either::Right(input),
+                    Either::Left((_, _)) => break,
+                    Either::Right((None, _)) => {
+                        // The agent side has taken the last input.
+                        unsafe {
+                            let shared_memory = addr as *mut SharedMemory<T>;
+                            (*shared_memory).close_input();
+                        }
+                        break;
+                    }
+                    Either::Right((Some(input), _)) => {
+                        counters.pop_input();
+                        Either::Right(input)
+                    }
                 }
             };
             unsafe {
@@ -1003,6 +1696,7 @@ where
                     }
                 }
                 sem_post(&mut (*shared_memory).input_rx).expect("semaphore failure");
+                (*shared_memory).ring_input_doorbell();
             }
             sem_wait = spawn_sem_wait();
         }
@@ -1047,6 +1741,39 @@ unsafe fn sem_getvalue(sem: *mut sem_t) -> io::Result<c_int> {
     }
 }
 
+/// Decrements the semaphore if it's positive. Returns `false` if it's zero.
+unsafe fn sem_trywait(sem: *mut sem_t) -> io::Result<bool> {
+    let result = unsafe { libc::sem_trywait(sem) };
+    if result == -1 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EAGAIN) {
+            Ok(false)
+        } else {
+            Err(err)
+        }
+    } else {
+        Ok(true)
+    }
+}
+
+fn eventfd() -> io::Result<RawFd> {
+    let result = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK) };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn ring_doorbell(doorbell: RawFd) {
+    let value = 1_u64;
+    // The only possible error is the counter overflow, which means the agent
+    // side is already signaled.
+    let _ = unsafe {
+        libc::write(doorbell, ptr::addr_of!(value).cast(), mem::size_of::<u64>())
+    };
+}
+
 unsafe fn sem_wait(sem: *mut sem_t) -> io::Result<()> {
     let result = unsafe { libc::sem_wait(sem) };
     if result == -1 {
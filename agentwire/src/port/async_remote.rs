@@ -0,0 +1,335 @@
+// Asynchronous agent side of a shared memory channel.
+//
+// The agent side doesn't block on the semaphores. Instead it takes them with
+// `sem_trywait`, and when a semaphore is not available, it waits on a doorbell
+// eventfd. The broker side signals the input doorbell after posting an input,
+// and the output doorbell after freeing the output space. The doorbell is
+// drained before the semaphore is checked again, so a post is never missed.
+// The separate doorbells let the stream and the sink wait concurrently, e.g.
+// after `split`, without consuming each other's signals.
+
+use super::{
+    deserialize_message,
+    ring::{self, Ring},
+    sem_post, sem_trywait, serialize_message, Input, MessageTooLarge, Output,
+    RemoteInner, SharedMode, SharedPort, SharedSerializer,
+};
+use futures::{prelude::*, ready};
+use rkyv::{
+    de::deserializers::SharedDeserializeMap, Archive, Deserialize, Infallible,
+    Serialize,
+};
+use std::{
+    fmt::Debug,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    ptr,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::io::unix::AsyncFd;
+
+/// An asynchronous handle for bi-directional communication for the inside of
+/// the computation unit, which is located in another process. The type
+/// implements both [`Sink`] and [`Stream`] for the output and the input
+/// channels respectively.
+///
+/// Must be used inside a Tokio runtime. Unlike [`RemoteInner`], the received
+/// inputs are deserialized. The stream ends once the broker closes the input
+/// and all inputs are received. The broker still receives the outputs sent
+/// until the port is dropped.
+pub struct AsyncRemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    inner: RemoteInner<T>,
+    input_doorbell: AsyncFd<OwnedFd>,
+    output_doorbell: AsyncFd<OwnedFd>,
+    pending: Option<Pending<T>>,
+}
+
+// Output accepted by `start_send` but not yet written to the shared memory.
+enum Pending<T: SharedPort>
+where
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    // Serialized directly into the output buffer once it's free.
+    Value(Output<T>),
+    // Serialized into the staging buffer with the given size.
+    Staged(usize, Instant),
+}
+
+impl<T> RemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    /// Converts the channel into an asynchronous one. Must be called inside a
+    /// Tokio runtime, after [`init_state`](Self::init_state), in a process
+    /// which inherited the doorbells.
+    pub(crate) fn into_async(mut self) -> io::Result<AsyncRemoteInner<T>> {
+        self.release_input();
+        // The doorbell file descriptors are inherited from the broker process.
+        let (input_doorbell, output_doorbell) = unsafe {
+            (
+                OwnedFd::from_raw_fd((*self.shared_memory).input_doorbell),
+                OwnedFd::from_raw_fd((*self.shared_memory).output_doorbell),
+            )
+        };
+        Ok(AsyncRemoteInner {
+            inner: self,
+            input_doorbell: AsyncFd::new(input_doorbell)?,
+            output_doorbell: AsyncFd::new(output_doorbell)?,
+            pending: None,
+        })
+    }
+}
+
+impl<T> AsyncRemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Input as Archive>::Archived: Deserialize<T::Input, SharedDeserializeMap>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    // Returns `Ready(None)` once the input is closed.
+    fn try_recv(&mut self) -> Poll<Option<Input<T>>> {
+        let shared_memory = self.inner.shared_memory;
+        unsafe {
+            let (archived, source_ts, release) = match T::SHARED_MODE {
+                SharedMode::Fixed => {
+                    if !sem_trywait(&mut (*shared_memory).input_rx)
+                        .expect("semaphore failure")
+                    {
+                        return Poll::Pending;
+                    }
+                    if (*shared_memory).is_input_closed() {
+                        return Poll::Ready(None);
+                    }
+                    let input_index = 1 - (*shared_memory).input_index;
+                    let archived = deserialize_message::<T::Input>(
+                        (*shared_memory).input(input_index),
+                    );
+                    (archived, (*shared_memory).input_ts[input_index], None)
+                }
+                SharedMode::Ring { .. } => {
+                    let ring = (*shared_memory).input_ring();
+                    if !Ring::try_wait(ring) {
+                        return Poll::Pending;
+                    }
+                    if (*shared_memory).is_input_closed() {
+                        return Poll::Ready(None);
+                    }
+                    let (payload, source_ts, size) = Ring::peek(ring);
+                    let archived = rkyv::archived_root::<T::Input>(payload);
+                    (archived, source_ts, Some(size))
+                }
+            };
+            // Reuse of `SharedDeserializeMap` doesn't work
+            let value = archived
+                .deserialize(&mut SharedDeserializeMap::new())
+                .unwrap();
+            match release {
+                None => {
+                    sem_post(&mut (*shared_memory).input_tx)
+                        .expect("semaphore failure");
+                }
+                Some(size) => Ring::release((*shared_memory).input_ring(), size),
+            }
+            Poll::Ready(Some(Input { value, source_ts }))
+        }
+    }
+
+    fn try_write(&mut self, pending: Pending<T>) -> Result<(), Pending<T>> {
+        let shared_memory = self.inner.shared_memory;
+        unsafe {
+            match pending {
+                Pending::Value(output) => {
+                    if !sem_trywait(&mut (*shared_memory).output_tx)
+                        .expect("semaphore failure")
+                    {
+                        return Err(Pending::Value(output));
+                    }
+                    serialize_message(
+                        (*shared_memory).output(),
+                        &mut self.inner.scratch,
+                        &output.value,
+                    );
+                    (*shared_memory).output_ts = output.source_ts;
+                    sem_post(&mut (*shared_memory).output_rx)
+                        .expect("semaphore failure");
+                }
+                Pending::Staged(size, source_ts) => {
+                    let SharedMode::Ring { max_size, .. } = T::SHARED_MODE else {
+                        unreachable!("staged outputs are used only in the ring mode");
+                    };
+                    if !Ring::try_write(
+                        (*shared_memory).output_ring(),
+                        &self.inner.staging[..size],
+                        source_ts,
+                        max_size,
+                    ) {
+                        return Err(Pending::Staged(size, source_ts));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while let Some(pending) = self.pending.take() {
+            if let Err(pending) = self.try_write(pending) {
+                self.pending = Some(pending);
+                ready!(poll_doorbell(&self.output_doorbell, cx));
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+// Nothing is structurally pinned.
+impl<T> Unpin for AsyncRemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+}
+
+impl<T> Drop for AsyncRemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    fn drop(&mut self) {
+        // The inner channel acknowledges the closed input when dropped.
+        if self.inner.closed && self.pending.is_some() {
+            tracing::warn!("Dropping an unflushed output of a closed port");
+        }
+    }
+}
+
+impl<T> Stream for AsyncRemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Input as Archive>::Archived: Deserialize<T::Input, SharedDeserializeMap>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    type Item = Input<T>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.inner.closed {
+            return Poll::Ready(None);
+        }
+        loop {
+            if let Poll::Ready(input) = this.try_recv() {
+                this.inner.closed = input.is_none();
+                return Poll::Ready(input);
+            }
+            ready!(poll_doorbell(&this.input_doorbell, cx));
+        }
+    }
+}
+
+impl<T> Sink<Output<T>> for AsyncRemoteInner<T>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Input as Archive>::Archived: Deserialize<T::Input, SharedDeserializeMap>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    type Error = MessageTooLarge;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx).map(Ok)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Output<T>) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        assert!(this.pending.is_none(), "`poll_ready` must be called first");
+        this.pending = match T::SHARED_MODE {
+            SharedMode::Fixed => Some(Pending::Value(item)),
+            SharedMode::Ring { max_size, .. } => {
+                let size = ring::serialize_limited(
+                    &mut this.inner.staging,
+                    &mut this.inner.scratch,
+                    &item.value,
+                    max_size,
+                )?;
+                Some(Pending::Staged(size, item.source_ts))
+            }
+        };
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx).map(Ok)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx).map(Ok)
+    }
+}
+
+// Resolves when the broker side has signaled the doorbell since the last call.
+fn poll_doorbell(doorbell: &AsyncFd<OwnedFd>, cx: &mut Context<'_>) -> Poll<()> {
+    loop {
+        let mut guard = ready!(doorbell.poll_read_ready(cx)).expect("doorbell failure");
+        if let Ok(result) = guard.try_io(|doorbell| read_eventfd(doorbell.get_ref())) {
+            result.expect("doorbell failure");
+            return Poll::Ready(());
+        }
+    }
+}
+
+fn read_eventfd(fd: &OwnedFd) -> io::Result<()> {
+    let mut value = 0_u64;
+    let result = unsafe {
+        libc::read(
+            fd.as_raw_fd(),
+            ptr::addr_of_mut!(value).cast(),
+            mem::size_of::<u64>(),
+        )
+    };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
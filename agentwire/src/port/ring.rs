@@ -0,0 +1,553 @@
+// Growable ring buffer for variable-size messages in shared memory.
+//
+// A ring occupies a fixed reservation inside the shared memory file. The
+// reservation is never touched beyond the current ring capacity, so the pages
+// are allocated lazily as the ring grows. The ring starts with a header, and
+// right after the header there is a data buffer of `capacity` bytes, which
+// contains a sequence of records. Each record is a `RecordHeader` followed by
+// a serialized message. A record which doesn't fit before the end of the
+// buffer starts at the beginning of the buffer, and the skipped tail is marked
+// with a `WRAP` header, if there is enough room for it.
+//
+// `written` and `read` are monotonic byte counters owned by the writer and the
+// reader respectively. The capacity is changed only by the writer and only when
+// the ring is empty, so the reader never observes a capacity change in the
+// middle of a record.
+
+use super::{
+    close_output, sem_destroy, sem_getvalue, sem_init, sem_post, sem_trywait, sem_wait,
+    Counters, CreateSharedMemoryError, DestroySharedMemoryError, InitialInputs,
+    InnerRx, InnerTx, MessageTooLarge, Output, SharedMemory, SharedPort,
+    SharedSerializer, SCRATCH_SIZE,
+};
+use futures::{
+    channel::oneshot,
+    future::{select, Either},
+    prelude::*,
+};
+use libc::sem_t;
+use rkyv::{
+    de::deserializers::SharedDeserializeMap,
+    ser::{
+        serializers::{
+            AllocScratch, BufferSerializer, BufferSerializerError, CompositeSerializer,
+            CompositeSerializerError, FallbackScratch, HeapScratch, SharedSerializeMap,
+        },
+        Serializer,
+    },
+    Archive, Deserialize, Infallible, Serialize,
+};
+use std::{
+    fmt::Debug,
+    mem, ptr, slice,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::task;
+
+const ALIGN: usize = 16;
+const MIN_CAPACITY: usize = 256;
+const WRAP: usize = usize::MAX;
+const HEADER_SIZE: usize = align_up(mem::size_of::<RecordHeader>());
+const RING_HEADER_SIZE: usize = align_up(mem::size_of::<Ring>());
+
+pub(crate) type Scratch = FallbackScratch<HeapScratch<SCRATCH_SIZE>, AllocScratch>;
+
+pub(super) struct Ring {
+    capacity: AtomicUsize,
+    written: AtomicUsize,
+    read: AtomicUsize,
+    items: sem_t,
+    space: sem_t,
+}
+
+struct RecordHeader {
+    size: usize,
+    source_ts: Instant,
+}
+
+pub(super) const fn align_up(size: usize) -> usize {
+    size.div_ceil(ALIGN) * ALIGN
+}
+
+/// Size of the shared memory reserved for a ring with the given limit.
+pub(super) const fn reservation(max_size: usize) -> usize {
+    RING_HEADER_SIZE + capacity_limit(max_size)
+}
+
+// The ring can grow to twice the largest record, so that a record of any
+// admitted size fits into an empty ring regardless of the current position.
+const fn capacity_limit(max_size: usize) -> usize {
+    let limit = record_size(max_size).saturating_mul(2);
+    if limit < MIN_CAPACITY {
+        MIN_CAPACITY
+    } else {
+        limit
+    }
+}
+
+const fn record_size(payload_size: usize) -> usize {
+    HEADER_SIZE + align_up(payload_size)
+}
+
+/// Returns an error if a message of `payload_size` bytes exceeds `max_size`.
+/// Such messages must not be passed to [`Ring::try_write`] or [`Ring::write`].
+pub(super) fn check_size(
+    payload_size: usize,
+    max_size: usize,
+) -> Result<(), MessageTooLarge> {
+    if payload_size <= max_size {
+        Ok(())
+    } else {
+        Err(MessageTooLarge { max_size })
+    }
+}
+
+fn initial_capacity(message_size: usize, slots: usize, max_size: usize) -> usize {
+    record_size(message_size)
+        .saturating_mul(slots.max(1))
+        .next_power_of_two()
+        .clamp(MIN_CAPACITY, capacity_limit(max_size))
+}
+
+impl Ring {
+    pub(super) unsafe fn init(
+        ring: *mut Self,
+        message_size: usize,
+        slots: usize,
+        max_size: usize,
+    ) -> Result<(), CreateSharedMemoryError> {
+        unsafe {
+            sem_init(&mut (*ring).items, 1, 0)
+                .map_err(CreateSharedMemoryError::SemInit)?;
+            sem_init(&mut (*ring).space, 1, 0)
+                .map_err(CreateSharedMemoryError::SemInit)?;
+            ptr::write(
+                &mut (*ring).capacity,
+                AtomicUsize::new(initial_capacity(message_size, slots, max_size)),
+            );
+            ptr::write(&mut (*ring).written, AtomicUsize::new(0));
+            ptr::write(&mut (*ring).read, AtomicUsize::new(0));
+        }
+        Ok(())
+    }
+
+    pub(super) unsafe fn destroy(
+        ring: *mut Self,
+    ) -> Result<(), DestroySharedMemoryError> {
+        unsafe {
+            sem_destroy(&mut (*ring).items)
+                .map_err(DestroySharedMemoryError::SemDestroy)?;
+            sem_destroy(&mut (*ring).space)
+                .map_err(DestroySharedMemoryError::SemDestroy)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn data(ring: *mut Self) -> *mut u8 {
+        unsafe { ring.cast::<u8>().add(RING_HEADER_SIZE) }
+    }
+
+    #[allow(clippy::cast_ptr_alignment)] // records are aligned to `ALIGN`
+    unsafe fn header(data: *mut u8, position: usize) -> *mut RecordHeader {
+        unsafe { data.add(position).cast::<RecordHeader>() }
+    }
+
+    /// Writes a record if there is enough free space. Grows the ring if the
+    /// record is too large for the current capacity. Returns `false` if the
+    /// writer should wait for the reader to free some space.
+    pub(super) unsafe fn try_write(
+        ring: *mut Self,
+        payload: &[u8],
+        source_ts: Instant,
+        max_size: usize,
+    ) -> bool {
+        unsafe {
+            let size = record_size(payload.len());
+            let written = (*ring).written.load(Ordering::Acquire);
+            let used = written - (*ring).read.load(Ordering::Acquire);
+            let mut capacity = (*ring).capacity.load(Ordering::Relaxed);
+            // A record not larger than a half of the capacity always fits into
+            // an empty ring, regardless of the current position.
+            if size > capacity / 2 {
+                debug_assert!(check_size(payload.len(), max_size).is_ok());
+                let limit = capacity_limit(max_size);
+                if used > 0 {
+                    return false;
+                }
+                capacity = size.saturating_mul(2).next_power_of_two().min(limit);
+                (*ring).capacity.store(capacity, Ordering::Relaxed);
+                tracing::debug!("Shared ring grew to {capacity} bytes");
+            }
+            let position = written % capacity;
+            let tail = capacity - position;
+            let skip = if tail < size { tail } else { 0 };
+            if capacity - used < skip + size {
+                return false;
+            }
+            let data = Self::data(ring);
+            if skip >= HEADER_SIZE {
+                ptr::write(
+                    Self::header(data, position),
+                    RecordHeader {
+                        size: WRAP,
+                        source_ts,
+                    },
+                );
+            }
+            let start = (position + skip) % capacity;
+            ptr::write(
+                Self::header(data, start),
+                RecordHeader {
+                    size: payload.len(),
+                    source_ts,
+                },
+            );
+            ptr::copy_nonoverlapping(
+                payload.as_ptr(),
+                data.add(start + HEADER_SIZE),
+                payload.len(),
+            );
+            (*ring)
+                .written
+                .store(written + skip + size, Ordering::Release);
+            sem_post(&mut (*ring).items).expect("semaphore failure");
+            true
+        }
+    }
+
+    /// Writes a record, blocking until there is enough free space.
+    pub(super) unsafe fn write(
+        ring: *mut Self,
+        payload: &[u8],
+        source_ts: Instant,
+        max_size: usize,
+    ) {
+        unsafe {
+            while !Self::try_write(ring, payload, source_ts, max_size) {
+                sem_wait(&mut (*ring).space).expect("semaphore failure");
+            }
+        }
+    }
+
+    /// Returns the oldest unread record without releasing it, and the number
+    /// of bytes to release afterwards. The ring must be non-empty.
+    pub(super) unsafe fn peek<'a>(ring: *mut Self) -> (&'a [u8], Instant, usize) {
+        unsafe { Self::peek_at(ring, (*ring).read.load(Ordering::Acquire)) }
+    }
+
+    unsafe fn peek_at<'a>(ring: *mut Self, read: usize) -> (&'a [u8], Instant, usize) {
+        unsafe {
+            let capacity = (*ring).capacity.load(Ordering::Relaxed);
+            let data = Self::data(ring);
+            let mut position = read % capacity;
+            let mut skip = 0;
+            let tail = capacity - position;
+            if tail < HEADER_SIZE || (*Self::header(data, position)).size == WRAP {
+                skip = tail;
+                position = 0;
+            }
+            let header = &*Self::header(data, position);
+            let payload =
+                slice::from_raw_parts(data.add(position + HEADER_SIZE), header.size);
+            (payload, header.source_ts, skip + record_size(header.size))
+        }
+    }
+
+    /// Releases `size` bytes returned by [`Self::peek`].
+    pub(super) unsafe fn release(ring: *mut Self, size: usize) {
+        unsafe {
+            (*ring).read.fetch_add(size, Ordering::Release);
+            sem_post(&mut (*ring).space).expect("semaphore failure");
+        }
+    }
+
+    /// Waits for a new record.
+    pub(super) unsafe fn wait(ring: *mut Self) {
+        unsafe {
+            sem_wait(&mut (*ring).items).expect("semaphore failure");
+        }
+    }
+
+    /// Takes a new record if there is one. Returns `false` if the ring is
+    /// empty.
+    pub(super) unsafe fn try_wait(ring: *mut Self) -> bool {
+        unsafe { sem_trywait(&mut (*ring).items).expect("semaphore failure") }
+    }
+
+    /// Wakes up the reader without writing a record.
+    pub(super) unsafe fn wake(ring: *mut Self) {
+        unsafe {
+            sem_post(&mut (*ring).items).expect("semaphore failure");
+        }
+    }
+
+    /// Returns `true` if all written records are released.
+    pub(super) unsafe fn is_empty(ring: *mut Self) -> bool {
+        unsafe {
+            (*ring).read.load(Ordering::Acquire)
+                == (*ring).written.load(Ordering::Acquire)
+        }
+    }
+
+    /// Returns `true` if there is an unread record.
+    pub(super) unsafe fn has_items(ring: *mut Self) -> bool {
+        unsafe { sem_getvalue(&mut (*ring).items).expect("semaphore failure") > 0 }
+    }
+
+    /// Copies all unread records, including the one which was peeked but not
+    /// yet released, from the oldest to the newest.
+    unsafe fn unread(ring: *mut Self) -> InitialInputs {
+        let mut records = Vec::new();
+        unsafe {
+            let written = (*ring).written.load(Ordering::Acquire);
+            let mut read = (*ring).read.load(Ordering::Acquire);
+            while read < written {
+                let (payload, source_ts, size) = Self::peek_at(ring, read);
+                records.push((Box::from(payload), source_ts));
+                read += size;
+            }
+        }
+        records
+    }
+}
+
+/// Serializes `value` into `buf` growing it as needed. Returns the size of the
+/// serialized message.
+pub(crate) fn serialize_message<T>(
+    buf: &mut Vec<u8>,
+    scratch: &mut Option<Scratch>,
+    value: &T,
+) -> usize
+where
+    T: Archive + for<'a> Serialize<SharedSerializer<'a>> + Debug,
+{
+    serialize_limited(buf, scratch, value, usize::MAX)
+        .expect("unlimited serialization can't exceed the limit")
+}
+
+/// Same as [`serialize_message`], but doesn't grow `buf` beyond `max_size`
+/// and returns an error if the message doesn't fit.
+pub(super) fn serialize_limited<T>(
+    buf: &mut Vec<u8>,
+    scratch: &mut Option<Scratch>,
+    value: &T,
+    max_size: usize,
+) -> Result<usize, MessageTooLarge>
+where
+    T: Archive + for<'a> Serialize<SharedSerializer<'a>> + Debug,
+{
+    if buf.is_empty() {
+        buf.resize(MIN_CAPACITY, 0);
+    }
+    loop {
+        let mut serializer = CompositeSerializer::new(
+            BufferSerializer::new(&mut buf[..]),
+            scratch.take().unwrap(),
+            SharedSerializeMap::new(), // reuse of this map doesn't work
+        );
+        match serializer.serialize_value(value) {
+            Ok(_) => {
+                let size = serializer.pos();
+                let (_, c, _) = serializer.into_components();
+                *scratch = Some(c);
+                check_size(size, max_size)?;
+                break Ok(size);
+            }
+            Err(CompositeSerializerError::SerializerError(
+                BufferSerializerError::Overflow { .. },
+            )) => {
+                // The scratch space may be left in an inconsistent state.
+                *scratch = Some(FallbackScratch::default());
+                if buf.len() >= max_size {
+                    break Err(MessageTooLarge { max_size });
+                }
+                let len = buf.len().saturating_mul(2).min(max_size);
+                buf.resize(len, 0);
+            }
+            Err(err) => panic!("failed to serialize an IPC message: {err}"),
+        }
+    }
+}
+
+/// Collects the inputs left in the input ring of `addr`.
+pub(super) unsafe fn unread_inputs<T>(addr: usize) -> InitialInputs
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    unsafe {
+        let shared_memory = addr as *mut SharedMemory<T>;
+        Ring::unread((*shared_memory).input_ring())
+    }
+}
+
+pub(super) fn spawn_tx_task<T>(
+    mut tx: InnerTx<T>,
+    counters: Arc<Counters>,
+    addr: usize,
+    mut stop_tx_rx: oneshot::Receiver<()>,
+) -> task::JoinHandle<InnerTx<T>>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    task::spawn_local(async move {
+        let spawn_wait = || {
+            task::spawn_blocking(move || unsafe {
+                let shared_memory = addr as *mut SharedMemory<T>;
+                Ring::wait((*shared_memory).output_ring());
+            })
+        };
+        loop {
+            if let Either::Left((_, wait)) = select(&mut stop_tx_rx, spawn_wait()).await
+            {
+                unsafe {
+                    let shared_memory = addr as *mut SharedMemory<T>;
+                    sem_post(&mut (*(*shared_memory).output_ring()).items)
+                        .expect("semaphore failure");
+                }
+                wait.await.unwrap();
+                break;
+            }
+            if unsafe { close_output::<T>(addr, &mut tx) } {
+                break;
+            }
+            let (value, source_ts) = unsafe {
+                let shared_memory = addr as *mut SharedMemory<T>;
+                let ring = (*shared_memory).output_ring();
+                let (payload, source_ts, size) = Ring::peek(ring);
+                let archived = rkyv::archived_root::<T::Output>(payload);
+                // Reuse of `SharedDeserializeMap` doesn't work
+                let value = archived
+                    .deserialize(&mut SharedDeserializeMap::new())
+                    .unwrap();
+                Ring::release(ring, size);
+                (*shared_memory).ring_output_doorbell();
+                (value, source_ts)
+            };
+            counters.push_output();
+            let mut send = tx.feed(Output { value, source_ts });
+            match select(&mut stop_tx_rx, &mut send).await {
+                Either::Left((_, _)) | Either::Right((Err(_), _)) => {
+                    counters.pop_output();
+                    break;
+                }
+                Either::Right((Ok(result), _)) => result,
+            }
+        }
+        tx
+    })
+}
+
+pub(super) fn spawn_rx_task<T>(
+    mut rx: InnerRx<T>,
+    counters: Arc<Counters>,
+    addr: usize,
+    mut stop_rx_rx: oneshot::Receiver<()>,
+    mut initial_inputs: InitialInputs,
+    max_size: usize,
+) -> task::JoinHandle<(InnerRx<T>, InitialInputs)>
+where
+    T: SharedPort + Debug + Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T as Archive>::Archived: Deserialize<T, Infallible>,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>,
+{
+    task::spawn_local(async move {
+        let spawn_wait = || {
+            task::spawn_blocking(move || unsafe {
+                let shared_memory = addr as *mut SharedMemory<T>;
+                sem_wait(&mut (*(*shared_memory).input_ring()).space)
+                    .expect("semaphore failure");
+            })
+        };
+        let mut staging = Vec::new();
+        let mut scratch = Some(FallbackScratch::default());
+        'outer: loop {
+            let (size, source_ts) = if let Some((input, input_ts)) =
+                initial_inputs.pop()
+            {
+                staging.clear();
+                staging.extend_from_slice(&input);
+                (input.len(), input_ts)
+            } else {
+                match select(&mut stop_rx_rx, rx.next()).await {
+                    Either::Left((_, _)) => break,
+                    Either::Right((None, _)) => {
+                        // The agent side reads the ring in order, so it takes
+                        // the rest of the inputs first.
+                        unsafe {
+                            let shared_memory = addr as *mut SharedMemory<T>;
+                            (*shared_memory).close_input();
+                        }
+                        break;
+                    }
+                    Either::Right((Some(input), _)) => {
+                        counters.pop_input();
+                        let result = serialize_limited(
+                            &mut staging,
+                            &mut scratch,
+                            &input.value,
+                            max_size,
+                        );
+                        match result {
+                            Ok(size) => (size, input.source_ts),
+                            Err(err) => {
+                                // The sender can't be notified about this input,
+                                // so fail the following sends instead.
+                                tracing::error!("Closing the shared port input: {err}");
+                                rx.close();
+                                continue;
+                            }
+                        }
+                    }
+                }
+            };
+            loop {
+                let written = unsafe {
+                    let shared_memory = addr as *mut SharedMemory<T>;
+                    Ring::try_write(
+                        (*shared_memory).input_ring(),
+                        &staging[..size],
+                        source_ts,
+                        max_size,
+                    )
+                };
+                if written {
+                    unsafe {
+                        let shared_memory = addr as *mut SharedMemory<T>;
+                        (*shared_memory).ring_input_doorbell();
+                    }
+                    break;
+                }
+                if let Either::Left((_, wait)) =
+                    select(&mut stop_rx_rx, spawn_wait()).await
+                {
+                    unsafe {
+                        let shared_memory = addr as *mut SharedMemory<T>;
+                        sem_post(&mut (*(*shared_memory).input_ring()).space)
+                            .expect("semaphore failure");
+                    }
+                    wait.await.unwrap();
+                    // Initial inputs are taken from the end, and the pending input
+                    // is older than the rest of them. When it came from `rx`,
+                    // there are no initial inputs left.
+                    initial_inputs.push((Box::from(&staging[..size]), source_ts));
+                    break 'outer;
+                }
+            }
+        }
+        (rx, initial_inputs)
+    })
+}
@@ -0,0 +1,480 @@
+//! Record and replay of broker sessions.
+//!
+//! Agents marked with the `record` option of the [`Broker`](crate::Broker)
+//! macro can have their message flow captured into a file, and later replayed
+//! in place of the real agents. The input and output types of such agents must
+//! be serializable by `rkyv` in the same way as for process-based agents.
+//! Recording is opt-in per agent for this reason: the messages of agents
+//! without the `record` option are neither captured nor replayed, so a replay
+//! runs them as usual.
+//!
+//! ```ignore
+//! #[derive(Broker)]
+//! #[broker(plan = Plan, error = Error)]
+//! struct MyBroker {
+//!     #[agent(task, record)]
+//!     camera: agent::Cell<Camera>,
+//! }
+//! ```
+//!
+//! Recording is started with [`start_recording`]. Each input and output of the
+//! recorded agents crossing the broker is appended to the file together with its timestamps.
+//! The timestamps are stored relative to the start of the recording, so a
+//! recording captured on a device can be replayed on a development machine.
+//!
+//! When a replay is started with [`start_replay`], enabling a recorded agent
+//! spawns a replay task instead of the real agent. The task emits the recorded
+//! outputs in the original order and with the original delays. Each recorded
+//! input is awaited from the broker before the following outputs are emitted,
+//! and the outputs chained from that input get the `source_ts` of the actual
+//! input. A warning is logged if the actual input differs from the recorded
+//! one. Tests can use the `replay` option of the [`test`](crate::test) macro.
+//!
+//! The recorded outputs are validated before use, so the archived output types
+//! must implement `CheckBytes` (`#[archive(check_bytes)]`). A replay stops
+//! with an error if a recorded output is invalid, e.g. when the recording was
+//! made with a different version of the type.
+//!
+//! The messages are written to the file by a dedicated thread, which flushes
+//! the file whenever it catches up. [`stop_recording`] and the generated
+//! `shutdown` method of the broker wait for the pending messages to be
+//! written.
+
+use crate::{
+    agent::{Agent, Kill},
+    port::{self, Input, Output, Scratch, SharedSerializer},
+};
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+use rkyv::{
+    check_archived_root, de::deserializers::SharedDeserializeMap,
+    validation::validators::DefaultValidator, AlignedVec, Archive, CheckBytes,
+    Deserialize, Serialize,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    pin::pin,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{sync::oneshot, task, time};
+
+const MAGIC: &[u8; 8] = b"AWREC\0\0\x01";
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+static REPLAY: Mutex<Option<Arc<Session>>> = Mutex::new(None);
+
+type Session = HashMap<String, Vec<Event>>;
+
+/// Direction of a recorded message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// A message sent by the broker to the agent.
+    Input,
+    /// A message sent by the agent to the broker.
+    Output,
+}
+
+/// A recorded message.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Agent name.
+    pub agent: String,
+    /// Message direction.
+    pub direction: Direction,
+    /// Nanoseconds since the start of the recording when the message crossed
+    /// the broker.
+    pub timestamp: i64,
+    /// Source data timestamp of the message in nanoseconds since the start of
+    /// the recording. Negative if the source data predates the recording.
+    pub source_ts: i64,
+    /// Serialized message value.
+    pub payload: Vec<u8>,
+}
+
+struct Recorder {
+    tx: mpsc::Sender<Command>,
+    writer: thread::JoinHandle<io::Result<()>>,
+    epoch: Instant,
+}
+
+enum Command {
+    Write(Event),
+    Flush(oneshot::Sender<()>),
+}
+
+impl Recorder {
+    fn stop(self) -> io::Result<()> {
+        drop(self.tx);
+        self.writer.join().expect("recording thread panicked")
+    }
+}
+
+/// Starts recording the messages of agents with the `record` option into a
+/// new file at `path`. Replaces the current recording if there is one.
+///
+/// Only the agents enabled after this call are recorded.
+pub fn start_recording(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.flush()?;
+    let (tx, rx) = mpsc::channel();
+    let writer = thread::Builder::new()
+        .name("agentwire-recorder".to_string())
+        .spawn(move || write_events(file, &rx))?;
+    let recorder = Recorder {
+        tx,
+        writer,
+        epoch: Instant::now(),
+    };
+    let previous = RECORDER.lock().unwrap().replace(recorder);
+    previous.map_or(Ok(()), Recorder::stop)
+}
+
+/// Stops the current recording. Waits for the recorded messages to be written
+/// to the file.
+pub fn stop_recording() -> io::Result<()> {
+    let recorder = RECORDER.lock().unwrap().take();
+    recorder.map_or(Ok(()), Recorder::stop)
+}
+
+/// Waits for the messages recorded so far to be written to the file, if a
+/// recording is in progress.
+pub async fn flush_recording() {
+    let (tx, rx) = oneshot::channel();
+    let sent = RECORDER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|recorder| recorder.tx.send(Command::Flush(tx)).is_ok());
+    if sent {
+        // An error means the writer has stopped, which is logged by the writer.
+        let _ = rx.await;
+    }
+}
+
+/// Starts replaying the recording at `path`. Agents which have recorded
+/// messages are replayed when enabled after this call.
+pub fn start_replay(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut session = Session::new();
+    for event in read_events(path)? {
+        session.entry(event.agent.clone()).or_default().push(event);
+    }
+    *REPLAY.lock().unwrap() = Some(Arc::new(session));
+    Ok(())
+}
+
+/// Stops the current replay. Already spawned replay tasks are not affected.
+pub fn stop_replay() {
+    REPLAY.lock().unwrap().take();
+}
+
+/// Reads all messages from the recording at `path`.
+pub fn read_events(path: impl AsRef<Path>) -> io::Result<Vec<Event>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; MAGIC.len()];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an agentwire recording",
+        ));
+    }
+    let mut events = Vec::new();
+    while let Some(event) = read_event(&mut file)? {
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Returns a replay of the agent if the current replay has messages for it.
+#[doc(hidden)]
+pub fn replay_agent<T>() -> Option<(port::Outer<T>, Kill)>
+where
+    T: Agent,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>
+        + for<'a> CheckBytes<DefaultValidator<'a>>,
+{
+    let session = REPLAY.lock().unwrap().clone()?;
+    let events = session.get(T::NAME)?.clone();
+    tracing::info!("Agent {} replayed from a recording", T::NAME);
+    let (inner, outer) = port::new();
+    task::spawn(replay(inner, events));
+    Some((outer, future::pending().boxed()))
+}
+
+/// Puts the agent behind a relay task, which records its messages, if a
+/// recording is in progress.
+#[doc(hidden)]
+pub fn record_agent<T>(agent: (port::Outer<T>, Kill)) -> (port::Outer<T>, Kill)
+where
+    T: Agent,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+{
+    if RECORDER.lock().unwrap().is_none() {
+        return agent;
+    }
+    let (mut agent, kill) = agent;
+    let (mut inner, outer) = port::new();
+    task::spawn(async move { relay(&mut inner, &mut agent).await });
+    (outer, kill)
+}
+
+async fn relay<T>(inner: &mut port::Inner<T>, agent: &mut port::Outer<T>)
+where
+    T: Agent,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+{
+    let port::Inner {
+        tx: inner_tx,
+        rx: inner_rx,
+        counters: inner_counters,
+    } = inner;
+    let port::Outer {
+        tx: agent_tx,
+        rx: agent_rx,
+        counters: agent_counters,
+        ..
+    } = agent;
+    let inputs = async {
+        let mut buf = Vec::new();
+        let mut scratch = Some(Scratch::default());
+        while let Some(input) = inner_rx.next().await {
+            inner_counters.pop_input();
+            let size = port::serialize_to_vec(&mut buf, &mut scratch, &input.value);
+            record::<T>(Direction::Input, input.source_ts, &buf[..size]);
+            agent_counters.push_input();
+            if agent_tx.send(input).await.is_err() {
+                return false;
+            }
+        }
+        true
+    };
+    let outputs = async {
+        let mut buf = Vec::new();
+        let mut scratch = Some(Scratch::default());
+        while let Some(output) = agent_rx.next().await {
+            agent_counters.pop_output();
+            let size = port::serialize_to_vec(&mut buf, &mut scratch, &output.value);
+            record::<T>(Direction::Output, output.source_ts, &buf[..size]);
+            inner_counters.push_output();
+            if inner_tx.send(output).await.is_err() {
+                return;
+            }
+        }
+    };
+    match future::select(pin!(inputs), pin!(outputs)).await {
+        // The agent stopped receiving inputs, forward the rest of its outputs.
+        Either::Left((false, outputs)) => outputs.await,
+        Either::Left((true, _)) | Either::Right(((), _)) => {}
+    }
+}
+
+fn record<T: Agent>(direction: Direction, source_ts: Instant, payload: &[u8]) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(Recorder { tx, epoch, .. }) = recorder.as_ref() else {
+        return;
+    };
+    let event = Event {
+        agent: T::NAME.to_string(),
+        direction,
+        timestamp: offset(*epoch, Instant::now()),
+        source_ts: offset(*epoch, source_ts),
+        payload: payload.to_vec(),
+    };
+    if tx.send(Command::Write(event)).is_err() {
+        // The writer has failed and logged the error.
+        if let Some(recorder) = recorder.take() {
+            let _ = recorder.stop();
+        }
+    }
+}
+
+fn write_events(
+    mut file: BufWriter<File>,
+    rx: &mpsc::Receiver<Command>,
+) -> io::Result<()> {
+    let result = (|| {
+        while let Ok(mut command) = rx.recv() {
+            // Flush once the queue is drained to keep the recording usable
+            // after a crash.
+            loop {
+                match command {
+                    Command::Write(event) => write_event(&mut file, &event)?,
+                    Command::Flush(done) => {
+                        file.flush()?;
+                        let _ = done.send(());
+                    }
+                }
+                match rx.try_recv() {
+                    Ok(next) => command = next,
+                    Err(_) => break,
+                }
+            }
+            file.flush()?;
+        }
+        Ok(())
+    })();
+    if let Err(err) = &result {
+        tracing::error!("Recording failed, stopping: {err:#?}");
+    }
+    result
+}
+
+async fn replay<T>(mut inner: port::Inner<T>, events: Vec<Event>)
+where
+    T: Agent,
+    T::Input: Archive + for<'a> Serialize<SharedSerializer<'a>>,
+    T::Output: Archive,
+    <T::Output as Archive>::Archived: Deserialize<T::Output, SharedDeserializeMap>
+        + for<'a> CheckBytes<DefaultValidator<'a>>,
+{
+    let epoch = Instant::now();
+    let base = events.first().map_or(0, |event| event.timestamp);
+    let mut chain = HashMap::new();
+    let mut prev = base;
+    let mut buf = Vec::new();
+    let mut scratch = Some(Scratch::default());
+    let mut aligned = AlignedVec::new();
+    for event in events {
+        match event.direction {
+            Direction::Input => {
+                let Some(input) = inner.next().await else {
+                    return;
+                };
+                let size = port::serialize_to_vec(&mut buf, &mut scratch, &input.value);
+                if buf[..size] != event.payload {
+                    tracing::warn!(
+                        "Replay of agent {} diverged from the recording: unexpected input \
+                         {:#?}",
+                        T::NAME,
+                        input.value
+                    );
+                }
+                chain.insert(event.source_ts, input.source_ts);
+            }
+            Direction::Output => {
+                time::sleep(nanos(event.timestamp - prev)).await;
+                aligned.clear();
+                aligned.extend_from_slice(&event.payload);
+                let archived = match check_archived_root::<T::Output>(&aligned) {
+                    Ok(archived) => archived,
+                    Err(err) => {
+                        tracing::error!(
+                            "Replay of agent {} stopped at an invalid recorded output: \
+                             {err}",
+                            T::NAME
+                        );
+                        return;
+                    }
+                };
+                let Ok(value) = archived.deserialize(&mut SharedDeserializeMap::new())
+                else {
+                    tracing::error!(
+                        "Replay of agent {} stopped at an undeserializable recorded \
+                         output",
+                        T::NAME
+                    );
+                    return;
+                };
+                let source_ts = chain
+                    .get(&event.source_ts)
+                    .copied()
+                    .unwrap_or_else(|| epoch + nanos(event.source_ts - base));
+                if inner.send(Output { value, source_ts }).await.is_err() {
+                    return;
+                }
+            }
+        }
+        prev = event.timestamp;
+    }
+    tracing::info!("Agent {} replay finished", T::NAME);
+    while let Some(Input { .. }) = inner.next().await {}
+}
+
+fn write_event(file: &mut impl Write, event: &Event) -> io::Result<()> {
+    let agent = event.agent.as_bytes();
+    let agent_len = u16::try_from(agent.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "agent name too long")
+    })?;
+    file.write_all(&agent_len.to_le_bytes())?;
+    file.write_all(agent)?;
+    file.write_all(&[u8::from(event.direction == Direction::Output)])?;
+    file.write_all(&event.timestamp.to_le_bytes())?;
+    file.write_all(&event.source_ts.to_le_bytes())?;
+    file.write_all(&(event.payload.len() as u64).to_le_bytes())?;
+    file.write_all(&event.payload)
+}
+
+fn read_event(file: &mut impl Read) -> io::Result<Option<Event>> {
+    let mut agent_len = [0; 2];
+    match file.read_exact(&mut agent_len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut agent = vec![0; u16::from_le_bytes(agent_len).into()];
+    file.read_exact(&mut agent)?;
+    let agent = String::from_utf8(agent)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let mut direction = [0; 1];
+    file.read_exact(&mut direction)?;
+    let direction = match direction[0] {
+        0 => Direction::Input,
+        1 => Direction::Output,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid direction",
+            ))
+        }
+    };
+    let mut word = [0; 8];
+    file.read_exact(&mut word)?;
+    let timestamp = i64::from_le_bytes(word);
+    file.read_exact(&mut word)?;
+    let source_ts = i64::from_le_bytes(word);
+    file.read_exact(&mut word)?;
+    let payload_len = u64::from_le_bytes(word);
+    // The length is not trusted, so the buffer grows only as the payload is
+    // actually read.
+    let mut payload = Vec::new();
+    file.by_ref().take(payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != payload_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated payload",
+        ));
+    }
+    Ok(Some(Event {
+        agent,
+        direction,
+        timestamp,
+        source_ts,
+        payload,
+    }))
+}
+
+fn offset(epoch: Instant, instant: Instant) -> i64 {
+    let nanos =
+        |duration: Duration| i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX);
+    match instant.checked_duration_since(epoch) {
+        Some(duration) => nanos(duration),
+        None => -nanos(epoch - instant),
+    }
+}
+
+fn nanos(offset: i64) -> Duration {
+    Duration::from_nanos(offset.try_into().unwrap_or(0))
+}
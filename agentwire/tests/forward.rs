@@ -0,0 +1,334 @@
+use agentwire::{
+    agent,
+    port::{self, Forward, Port},
+    Agent, Broker, BrokerFlow,
+};
+use futures::{channel::mpsc::SendError, prelude::*};
+use std::time::Instant;
+use thiserror::Error;
+
+#[derive(Debug)]
+enum Event {
+    Number(u32),
+    Text,
+}
+
+#[derive(Default)]
+struct Source;
+
+impl Port for Source {
+    type Input = Event;
+    type Output = Event;
+
+    const INPUT_CAPACITY: usize = 2;
+    const OUTPUT_CAPACITY: usize = 2;
+}
+
+impl Agent for Source {
+    const NAME: &'static str = "source";
+}
+
+impl agent::Task for Source {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.next().await {
+            let output = input.chain_fn();
+            port.send(output(input.value)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Doubler;
+
+impl Port for Doubler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 1;
+    const OUTPUT_CAPACITY: usize = 1;
+}
+
+impl Agent for Doubler {
+    const NAME: &'static str = "doubler";
+}
+
+impl agent::Task for Doubler {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(x) = port.next().await {
+            port.send(x.chain(x.value * 2)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Tripler;
+
+impl Port for Tripler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 1;
+    const OUTPUT_CAPACITY: usize = 1;
+}
+
+impl Agent for Tripler {
+    const NAME: &'static str = "tripler";
+}
+
+impl agent::Task for Tripler {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(x) = port.next().await {
+            port.send(x.chain(x.value * 3)).await?;
+        }
+        Ok(())
+    }
+}
+
+// Never reads its input.
+#[derive(Default)]
+struct Stall;
+
+impl Port for Stall {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for Stall {
+    const NAME: &'static str = "stall";
+}
+
+impl agent::Task for Stall {
+    type Error = SendError;
+
+    async fn run(self, _port: port::Inner<Self>) -> Result<(), Self::Error> {
+        future::pending().await
+    }
+}
+
+impl Forward<Doubler> for Source {
+    fn forward(output: &Event) -> Option<u32> {
+        match output {
+            Event::Number(x) => Some(*x),
+            Event::Text => None,
+        }
+    }
+}
+
+impl Forward<Tripler> for Source {
+    fn forward(output: &Event) -> Option<u32> {
+        match output {
+            Event::Number(x) => Some(*x),
+            Event::Text => None,
+        }
+    }
+}
+
+impl Forward<Stall> for Source {
+    fn forward(output: &Event) -> Option<u32> {
+        match output {
+            Event::Number(x) => Some(*x),
+            Event::Text => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_source(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<Source>,
+    ) -> Result<BrokerFlow, Error> {
+        Ok(BrokerFlow::Continue)
+    }
+
+    fn handle_doubler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_tripler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Tripler>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_stall(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<Stall>,
+    ) -> Result<BrokerFlow, Error> {
+        Ok(BrokerFlow::Continue)
+    }
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(task, forward_to = [doubler, tripler, stall])]
+    source: agent::Cell<Source>,
+    #[agent(task)]
+    doubler: agent::Cell<Doubler>,
+    #[agent(task)]
+    tripler: agent::Cell<Tripler>,
+    #[agent(task)]
+    stall: agent::Cell<Stall>,
+}
+
+impl Broker {
+    fn handle_source(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Source>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_source(self, output)
+    }
+
+    fn handle_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_doubler(self, output)
+    }
+
+    fn handle_tripler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Tripler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_tripler(self, output)
+    }
+
+    fn handle_stall(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Stall>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_stall(self, output)
+    }
+}
+
+#[agentwire::test]
+async fn test_forward() {
+    struct TestPlan {
+        results: Vec<(u32, Instant)>,
+    }
+    impl Plan for TestPlan {
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push((output.value, output.source_ts));
+            Ok(self.flow())
+        }
+
+        fn handle_tripler(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Tripler>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push((output.value, output.source_ts));
+            Ok(self.flow())
+        }
+    }
+    impl TestPlan {
+        fn flow(&self) -> BrokerFlow {
+            if self.results.len() == 2 {
+                BrokerFlow::Break
+            } else {
+                BrokerFlow::Continue
+            }
+        }
+    }
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_source().unwrap();
+    broker.enable_doubler().unwrap();
+    broker.enable_tripler().unwrap();
+
+    let fence = Instant::now();
+    let text = port::Input::new(Event::Text);
+    let number = port::Input::new(Event::Number(5));
+    let source_ts = number.source_ts;
+    let port = broker.source.enabled().unwrap();
+    port.send(text).await.unwrap();
+    port.send(number).await.unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_agents();
+    plan.results.sort();
+    assert_eq!(plan.results, [(10, source_ts), (15, source_ts)]);
+}
+
+#[agentwire::test]
+async fn test_forward_full_queue() {
+    struct TestPlan {
+        forwarded: usize,
+    }
+    impl Plan for TestPlan {
+        fn handle_source(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Source>,
+        ) -> Result<BrokerFlow, Error> {
+            self.forwarded += 1;
+            if self.forwarded == 3 {
+                Ok(BrokerFlow::Break)
+            } else {
+                Ok(BrokerFlow::Continue)
+            }
+        }
+
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            Ok(BrokerFlow::Continue)
+        }
+
+        fn handle_tripler(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Tripler>,
+        ) -> Result<BrokerFlow, Error> {
+            Ok(BrokerFlow::Continue)
+        }
+    }
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { forwarded: 0 };
+    broker.enable_source().unwrap();
+    broker.enable_stall().unwrap();
+
+    let fence = Instant::now();
+    let port = broker.source.enabled().unwrap();
+    for x in 1..=3 {
+        port.send(port::Input::new(Event::Number(x))).await.unwrap();
+    }
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    // The input queue of the stalled agent holds a single message.
+    let stall = broker.stall.enabled().unwrap();
+    assert_eq!(stall.dropped_inputs(), 2);
+    broker.disable_agents();
+}
@@ -0,0 +1,145 @@
+use agentwire::{
+    agent, metrics,
+    port::{self, Port, QueueDepth},
+    Agent, Broker, BrokerFlow,
+};
+use futures::{channel::mpsc::SendError, prelude::*};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::time;
+
+#[derive(Default)]
+struct Doubler;
+
+impl Port for Doubler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 2;
+    const OUTPUT_CAPACITY: usize = 3;
+}
+
+impl Agent for Doubler {
+    const NAME: &'static str = "doubler";
+}
+
+impl agent::Task for Doubler {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(x) = port.next().await {
+            port.send(x.chain(x.value * 2)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    depths: Mutex<Vec<(&'static str, QueueDepth)>>,
+    latencies: Mutex<Vec<(&'static str, Duration)>>,
+    handler_times: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl metrics::Metrics for &'static Recorder {
+    fn queue_depth(&self, agent: &'static str, depth: QueueDepth) {
+        self.depths.lock().unwrap().push((agent, depth));
+    }
+
+    fn latency(&self, agent: &'static str, latency: Duration) {
+        self.latencies.lock().unwrap().push((agent, latency));
+    }
+
+    fn handler_time(&self, agent: &'static str, duration: Duration) {
+        self.handler_times.lock().unwrap().push((agent, duration));
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_doubler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(task)]
+    doubler: agent::Cell<Doubler>,
+}
+
+impl Broker {
+    fn handle_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_doubler(self, output)
+    }
+}
+
+#[agentwire::test]
+async fn test_metrics() {
+    struct TestPlan {
+        results: Vec<u32>,
+    }
+    impl Plan for TestPlan {
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push(output.value);
+            if self.results.len() == 2 {
+                Ok(BrokerFlow::Break)
+            } else {
+                Ok(BrokerFlow::Continue)
+            }
+        }
+    }
+
+    let recorder: &'static Recorder = Box::leak(Box::default());
+    assert!(metrics::set(recorder).is_ok());
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_doubler().unwrap();
+
+    let fence = Instant::now();
+    let inputs = [port::Input::new(1), port::Input::new(2)];
+    // The time before the inputs are sent doesn't count in the latency.
+    time::sleep(Duration::from_millis(100)).await;
+    let sent = Instant::now();
+    let port = broker.doubler.enabled().unwrap();
+    for input in inputs {
+        port.send(input).await.unwrap();
+    }
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_doubler();
+    assert_eq!(plan.results, [2, 4]);
+    let depths = recorder.depths.lock().unwrap();
+    assert_eq!(depths.len(), 2);
+    for (agent, depth) in depths.iter() {
+        assert_eq!(*agent, "doubler");
+        assert_eq!(depth.input_capacity, 2);
+        assert_eq!(depth.output_capacity, 3);
+        assert!(depth.input <= 2 && depth.output <= 2);
+    }
+    let latencies = recorder.latencies.lock().unwrap();
+    assert_eq!(latencies.len(), 2);
+    assert!(latencies
+        .iter()
+        .all(|(_, latency)| *latency <= sent.elapsed()));
+    assert_eq!(recorder.handler_times.lock().unwrap().len(), 2);
+}
@@ -0,0 +1,197 @@
+use agentwire::{
+    agent,
+    mock::{self, Mock},
+    port::{self, Port, SharedPort},
+    Agent, Broker, BrokerFlow,
+};
+use futures::prelude::*;
+use rkyv::{Archive, Deserialize, Serialize};
+use std::{
+    mem::size_of,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::time;
+
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Doubler;
+
+impl Port for Doubler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for Doubler {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Doubler as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<u32 as Archive>::Archived>();
+    const SERIALIZED_OUTPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<u32 as Archive>::Archived>();
+}
+
+impl Agent for Doubler {
+    const NAME: &'static str = "doubler";
+}
+
+#[derive(Error, Debug)]
+pub enum DoublerError {}
+
+impl agent::Process for Doubler {
+    type Error = DoublerError;
+
+    fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.recv() {
+            let output = input.chain(input.value * 2);
+            port.send(&output);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_doubler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(process)]
+    doubler: agent::Cell<Doubler>,
+}
+
+impl Broker {
+    fn handle_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_doubler(self, output)
+    }
+}
+
+struct TestPlan {
+    results: Vec<u32>,
+    count: usize,
+}
+
+impl Plan for TestPlan {
+    fn handle_doubler(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        if self.results.len() == self.count {
+            Ok(BrokerFlow::Break)
+        } else {
+            Ok(BrokerFlow::Continue)
+        }
+    }
+}
+
+// No `init` is needed, because the process agent is never spawned.
+#[agentwire::test(mock = Mock::<Doubler>::new().expect(1).emit(10).expect(2).emit(20))]
+async fn test_mock_process() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+        count: 1,
+    };
+    broker.enable_doubler().unwrap();
+
+    let fence = Instant::now();
+    for input in [1, 2] {
+        let port = broker.doubler.enabled().unwrap();
+        port.send(port::Input::new(input)).await.unwrap();
+        broker.run_with_fence(&mut plan, fence).await.unwrap();
+        plan.count += 1;
+    }
+
+    broker.disable_doubler();
+    assert_eq!(plan.results, [10, 20]);
+}
+
+#[agentwire::test]
+async fn test_mock_install() {
+    mock::install(
+        Mock::<Doubler>::new()
+            .emit(7)
+            .expect_with(|input| assert!(*input > 100))
+            .emit(8),
+    );
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+        count: 1,
+    };
+    let fence = Instant::now();
+    broker.enable_doubler().unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+    broker
+        .doubler
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(101))
+        .await
+        .unwrap();
+    plan.count = 2;
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_doubler();
+    assert_eq!(plan.results, [7, 8]);
+}
+
+// The mock stays installed after its script is used, so the process agent is
+// never spawned.
+#[agentwire::test(mock = Mock::<Doubler>::new().emit(1))]
+async fn test_mock_used_up() {
+    let mut plan = TestPlan {
+        results: Vec::new(),
+        count: 1,
+    };
+    let fence = Instant::now();
+    let mut broker = new_broker!();
+    broker.enable_doubler().unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+    broker.disable_doubler();
+    drop(broker);
+
+    let mut broker = new_broker!();
+    broker.enable_doubler().unwrap();
+    let _ = time::timeout(Duration::from_millis(100), broker.run(&mut plan)).await;
+    broker.disable_doubler();
+    assert_eq!(plan.results, [1]);
+}
+
+// The test fails if it ends before the mock script is finished.
+#[agentwire::test(mock = Mock::<Doubler>::new().expect(1).emit(2))]
+#[should_panic(expected = "test failed")]
+async fn test_mock_unfinished() {
+    let mut broker = new_broker!();
+    broker.enable_doubler().unwrap();
+    broker.disable_doubler();
+}
+
+// A failed expectation fails the test.
+#[agentwire::test(mock = Mock::<Doubler>::new().expect(1))]
+#[should_panic(expected = "test failed")]
+async fn test_mock_unexpected_input() {
+    let mut broker = new_broker!();
+    broker.enable_doubler().unwrap();
+    let port = broker.doubler.enabled().unwrap();
+    port.send(port::Input::new(2)).await.unwrap();
+    time::sleep(Duration::from_millis(100)).await;
+    broker.disable_doubler();
+}
@@ -5,7 +5,11 @@ use agentwire::{
 };
 use futures::prelude::*;
 use rkyv::{Archive, Deserialize, Serialize};
-use std::{mem::size_of, time::Instant};
+use std::{
+    mem::size_of,
+    thread,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 #[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
@@ -39,11 +43,49 @@ impl agent::Process for Doubler {
     type Error = DoublerError;
 
     fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
-        loop {
-            let input = port.recv();
+        while let Some(input) = port.recv() {
             let output = input.chain(input.value * 2);
             port.send(&output);
         }
+        Ok(())
+    }
+}
+
+// Keeps the inputs in progress when the broker starts draining.
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct SlowDoubler;
+
+impl Port for SlowDoubler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for SlowDoubler {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<SlowDoubler as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<u32 as Archive>::Archived>();
+    const SERIALIZED_OUTPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<u32 as Archive>::Archived>();
+}
+
+impl Agent for SlowDoubler {
+    const NAME: &'static str = "slow-doubler";
+}
+
+impl agent::Process for SlowDoubler {
+    type Error = DoublerError;
+
+    fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.recv() {
+            thread::sleep(Duration::from_millis(50));
+            let output = input.chain(input.value * 2);
+            port.send(&output);
+        }
+        Ok(())
     }
 }
 
@@ -56,6 +98,14 @@ trait Plan {
         broker: &mut Broker,
         output: port::Output<Doubler>,
     ) -> Result<BrokerFlow, Error>;
+
+    fn handle_slow_doubler(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<SlowDoubler>,
+    ) -> Result<BrokerFlow, Error> {
+        Ok(BrokerFlow::Continue)
+    }
 }
 
 #[derive(Broker)]
@@ -63,6 +113,8 @@ trait Plan {
 struct Broker {
     #[agent(process)]
     doubler: agent::Cell<Doubler>,
+    #[agent(process)]
+    slow_doubler: agent::Cell<SlowDoubler>,
 }
 
 impl Broker {
@@ -73,11 +125,20 @@ impl Broker {
     ) -> Result<BrokerFlow, Error> {
         plan.handle_doubler(self, output)
     }
+
+    fn handle_slow_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<SlowDoubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_slow_doubler(self, output)
+    }
 }
 
 fn init() {
     agent::process::init(|name, fd| match name {
         "doubler" => Ok(Doubler::call(fd)?),
+        "slow-doubler" => Ok(SlowDoubler::call(fd)?),
         _ => panic!("unregistered agent {name}"),
     });
 }
@@ -115,3 +176,47 @@ async fn test_process() {
     broker.disable_doubler();
     assert_eq!(plan.result, Some(6));
 }
+
+#[agentwire::test(init = init)]
+async fn test_process_drain() {
+    struct TestPlan {
+        results: Vec<u32>,
+    }
+    impl Plan for TestPlan {
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            Ok(BrokerFlow::Continue)
+        }
+
+        fn handle_slow_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<SlowDoubler>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push(output.value);
+            Ok(BrokerFlow::Continue)
+        }
+    }
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_slow_doubler().unwrap();
+
+    let port = broker.slow_doubler.enabled().unwrap();
+    for input in [1, 2, 3] {
+        port.send(port::Input::new(input)).await.unwrap();
+    }
+    let timeout = Duration::from_secs(10);
+    let started = Instant::now();
+    broker.shutdown(&mut plan, timeout).await.unwrap();
+
+    // Drained by the acknowledgement of the agent, not by the timeout.
+    assert!(started.elapsed() < timeout);
+    assert_eq!(plan.results, [2, 4, 6]);
+    assert!(!broker.slow_doubler.is_enabled());
+}
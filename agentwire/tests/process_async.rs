@@ -0,0 +1,338 @@
+use agentwire::{
+    agent::{self, Process as _},
+    port::{self, MessageTooLarge, Port, SharedMode, SharedPort},
+    Agent, Broker, BrokerFlow,
+};
+use futures::{channel::mpsc, prelude::*};
+use rkyv::{Archive, Deserialize, Serialize};
+use std::{
+    mem::size_of,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{task, time};
+
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Doubler;
+
+impl Port for Doubler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for Doubler {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Doubler as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<u32 as Archive>::Archived>();
+    const SERIALIZED_OUTPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<u32 as Archive>::Archived>();
+}
+
+impl Agent for Doubler {
+    const NAME: &'static str = "doubler";
+}
+
+impl agent::AsyncProcess for Doubler {
+    type Error = DoublerError;
+
+    async fn run(
+        self,
+        mut port: port::AsyncRemoteInner<Self>,
+    ) -> Result<(), Self::Error> {
+        while let Some(input) = port.next().await {
+            // Timers of the agent runtime are available.
+            time::sleep(Duration::from_millis(10)).await;
+            port.send(input.chain(input.value * 2)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Splitter;
+
+impl Port for Splitter {
+    type Input = Vec<u32>;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 3;
+    const OUTPUT_CAPACITY: usize = 3;
+}
+
+impl SharedPort for Splitter {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Splitter as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize = 64;
+    const SERIALIZED_OUTPUT_SIZE: usize = 64;
+    const SHARED_MODE: SharedMode = SharedMode::Ring {
+        slots: 2,
+        max_size: 1024 * 1024,
+    };
+}
+
+impl Agent for Splitter {
+    const NAME: &'static str = "splitter";
+}
+
+impl agent::AsyncProcess for Splitter {
+    type Error = DoublerError;
+
+    async fn run(
+        self,
+        mut port: port::AsyncRemoteInner<Self>,
+    ) -> Result<(), Self::Error> {
+        while let Some(input) = port.next().await {
+            let chain = input.chain_fn();
+            let mut outputs = stream::iter(input.value).map(|x| Ok(chain(x)));
+            port.send_all(&mut outputs).await?;
+        }
+        Ok(())
+    }
+}
+
+// Receives and sends in separate tasks, so the halves of the port wait on the
+// shared memory concurrently.
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Fanout;
+
+impl Port for Fanout {
+    type Input = Vec<u32>;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 3;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for Fanout {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Fanout as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize = 64;
+    const SERIALIZED_OUTPUT_SIZE: usize = 64;
+    const SHARED_MODE: SharedMode = SharedMode::Ring {
+        slots: 1,
+        max_size: 64 * 1024,
+    };
+}
+
+impl Agent for Fanout {
+    const NAME: &'static str = "fanout";
+}
+
+impl agent::AsyncProcess for Fanout {
+    type Error = DoublerError;
+
+    async fn run(self, port: port::AsyncRemoteInner<Self>) -> Result<(), Self::Error> {
+        let (mut sink, mut stream) = port.split();
+        let (tx, mut rx) = mpsc::unbounded();
+        task::LocalSet::new()
+            .run_until(async move {
+                let receiver = task::spawn_local(async move {
+                    while let Some(input) = stream.next().await {
+                        let chain = input.chain_fn();
+                        for x in input.value {
+                            tx.unbounded_send(chain(x)).unwrap();
+                        }
+                    }
+                });
+                while let Some(output) = rx.next().await {
+                    sink.send(output).await?;
+                }
+                receiver.await.unwrap();
+                Ok::<_, DoublerError>(())
+            })
+            .await
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DoublerError {
+    #[error(transparent)]
+    Send(#[from] MessageTooLarge),
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_doubler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_splitter(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Splitter>,
+    ) -> Result<BrokerFlow, Error>;
+    fn handle_fanout(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Fanout>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(process)]
+    doubler: agent::Cell<Doubler>,
+    #[agent(process)]
+    splitter: agent::Cell<Splitter>,
+    #[agent(process)]
+    fanout: agent::Cell<Fanout>,
+}
+
+impl Broker {
+    fn handle_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_doubler(self, output)
+    }
+
+    fn handle_splitter(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Splitter>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_splitter(self, output)
+    }
+
+    fn handle_fanout(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Fanout>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_fanout(self, output)
+    }
+}
+
+fn init() {
+    agent::process::init(|name, fd| match name {
+        "doubler" => Ok(Doubler::call(fd)?),
+        "splitter" => Ok(Splitter::call(fd)?),
+        "fanout" => Ok(Fanout::call(fd)?),
+        _ => panic!("unregistered agent {name}"),
+    });
+}
+
+struct TestPlan {
+    results: Vec<u32>,
+    expected: usize,
+}
+
+impl Plan for TestPlan {
+    fn handle_doubler(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        Ok(self.flow())
+    }
+
+    fn handle_splitter(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Splitter>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        Ok(self.flow())
+    }
+
+    fn handle_fanout(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Fanout>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        Ok(self.flow())
+    }
+}
+
+impl TestPlan {
+    fn flow(&self) -> BrokerFlow {
+        if self.results.len() == self.expected {
+            BrokerFlow::Break
+        } else {
+            BrokerFlow::Continue
+        }
+    }
+}
+
+#[agentwire::test(init = init)]
+async fn test_process_async() {
+    let mut broker = new_broker!();
+    broker.enable_doubler().unwrap();
+
+    for input in [3, 5] {
+        let mut plan = TestPlan {
+            results: Vec::new(),
+            expected: 1,
+        };
+        let fence = Instant::now();
+        broker
+            .doubler
+            .enabled()
+            .unwrap()
+            .send(port::Input::new(input))
+            .await
+            .unwrap();
+        broker.run_with_fence(&mut plan, fence).await.unwrap();
+        assert_eq!(plan.results, [input * 2]);
+    }
+
+    broker.disable_doubler();
+}
+
+#[agentwire::test(init = init)]
+async fn test_process_async_ring() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+        expected: 1_003,
+    };
+    broker.enable_splitter().unwrap();
+
+    // More outputs than the output ring holds, to make the agent wait.
+    let inputs = [(0..3).collect::<Vec<u32>>(), (0..1_000).collect()];
+    let fence = Instant::now();
+    let port = broker.splitter.enabled().unwrap();
+    for input in &inputs {
+        port.send(port::Input::new(input.clone())).await.unwrap();
+    }
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_splitter();
+    assert_eq!(plan.results, inputs.concat());
+}
+
+#[agentwire::test(init = init)]
+async fn test_process_async_split() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+        expected: 5_000,
+    };
+    broker.enable_fanout().unwrap();
+
+    // The output ring fills up while the agent keeps waiting for inputs.
+    let inputs = (0..5)
+        .map(|i| (i * 1_000..(i + 1) * 1_000).collect::<Vec<u32>>())
+        .collect::<Vec<_>>();
+    let fence = Instant::now();
+    let port = broker.fanout.enabled().unwrap();
+    for input in &inputs {
+        port.send(port::Input::new(input.clone())).await.unwrap();
+    }
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_fanout();
+    assert_eq!(plan.results, inputs.concat());
+}
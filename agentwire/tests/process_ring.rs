@@ -0,0 +1,327 @@
+use agentwire::{
+    agent::{self, Process as _},
+    port::{self, MessageTooLarge, Port, SharedMode, SharedPort},
+    Agent, Broker, BrokerFlow,
+};
+use futures::prelude::*;
+use rkyv::{Archive, Deserialize, Serialize};
+use std::{
+    env, fs,
+    mem::size_of,
+    os::unix::process::parent_id,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::time;
+
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Doubler;
+
+impl Port for Doubler {
+    type Input = Vec<u32>;
+    type Output = Vec<u32>;
+
+    const INPUT_CAPACITY: usize = 3;
+    const OUTPUT_CAPACITY: usize = 3;
+}
+
+impl SharedPort for Doubler {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Doubler as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize = 64;
+    const SERIALIZED_OUTPUT_SIZE: usize = 64;
+    const SHARED_MODE: SharedMode = SharedMode::Ring {
+        slots: 2,
+        max_size: 1024 * 1024,
+    };
+}
+
+impl Agent for Doubler {
+    const NAME: &'static str = "doubler";
+}
+
+#[derive(Error, Debug)]
+pub enum DoublerError {
+    #[error(transparent)]
+    Send(#[from] MessageTooLarge),
+}
+
+impl agent::Process for Doubler {
+    type Error = DoublerError;
+
+    fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.recv() {
+            let output = input.chain(input.value.iter().map(|x| x * 2).collect());
+            port.send_ring(&output)?;
+        }
+        Ok(())
+    }
+}
+
+// Crashes twice before reading any input, while the inputs fill up the ring.
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Sequencer;
+
+impl Port for Sequencer {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 20;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for Sequencer {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Sequencer as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize = 8;
+    const SERIALIZED_OUTPUT_SIZE: usize = 8;
+    const SHARED_MODE: SharedMode = SharedMode::Ring {
+        slots: 1,
+        max_size: 1024,
+    };
+}
+
+impl Agent for Sequencer {
+    const NAME: &'static str = "sequencer";
+}
+
+impl agent::Process for Sequencer {
+    type Error = DoublerError;
+
+    fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        let marker = crash_marker(parent_id());
+        let crashes = fs::read_to_string(&marker)
+            .ok()
+            .and_then(|crashes| crashes.parse::<u32>().ok())
+            .unwrap_or(0);
+        if crashes < 2 {
+            fs::write(&marker, (crashes + 1).to_string()).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            return Ok(());
+        }
+        while let Some(input) = port.recv() {
+            let output = input.chain(*input.value);
+            port.send_ring(&output)?;
+        }
+        Ok(())
+    }
+}
+
+fn crash_marker(broker_pid: u32) -> PathBuf {
+    env::temp_dir().join(format!("agentwire-sequencer-{broker_pid}"))
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_doubler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_sequencer(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Sequencer>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(process)]
+    doubler: agent::Cell<Doubler>,
+    #[agent(process)]
+    sequencer: agent::Cell<Sequencer>,
+}
+
+impl Broker {
+    fn handle_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_doubler(self, output)
+    }
+
+    fn handle_sequencer(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Sequencer>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_sequencer(self, output)
+    }
+}
+
+fn init() {
+    agent::process::init(|name, fd| match name {
+        "doubler" => Ok(Doubler::call(fd)?),
+        "sequencer" => Ok(Sequencer::call(fd)?),
+        _ => panic!("unregistered agent {name}"),
+    });
+}
+
+#[agentwire::test(init = init)]
+async fn test_process_ring() {
+    struct TestPlan {
+        results: Vec<Vec<u32>>,
+    }
+    impl Plan for TestPlan {
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push(output.value);
+            if self.results.len() == 3 {
+                Ok(BrokerFlow::Break)
+            } else {
+                Ok(BrokerFlow::Continue)
+            }
+        }
+
+        fn handle_sequencer(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Sequencer>,
+        ) -> Result<BrokerFlow, Error> {
+            unreachable!()
+        }
+    }
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_doubler().unwrap();
+
+    // Messages are much larger than the expected size to make the rings grow.
+    let inputs = [
+        (0..4).collect::<Vec<u32>>(),
+        (0..50_000).collect(),
+        (0..1_000).collect(),
+    ];
+    let fence = Instant::now();
+    let port = broker.doubler.enabled().unwrap();
+    for input in &inputs {
+        port.send(port::Input::new(input.clone())).await.unwrap();
+    }
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_doubler();
+    assert_eq!(plan.results.len(), inputs.len());
+    for (input, result) in inputs.iter().zip(&plan.results) {
+        assert_eq!(result, &input.iter().map(|x| x * 2).collect::<Vec<_>>());
+    }
+}
+
+#[agentwire::test(init = init)]
+async fn test_process_ring_oversized() {
+    struct TestPlan {
+        results: Vec<Vec<u32>>,
+    }
+    impl Plan for TestPlan {
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push(output.value);
+            Ok(BrokerFlow::Break)
+        }
+
+        fn handle_sequencer(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Sequencer>,
+        ) -> Result<BrokerFlow, Error> {
+            unreachable!()
+        }
+    }
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_doubler().unwrap();
+
+    // The first message exceeds a half of `max_size`, but still fits. The
+    // second one exceeds `max_size` and closes the input of the port.
+    let large = (0..200_000).collect::<Vec<u32>>();
+    let oversized = (0..300_000).collect::<Vec<u32>>();
+    let fence = Instant::now();
+    let port = broker.doubler.enabled().unwrap();
+    port.send(port::Input::new(large.clone())).await.unwrap();
+    port.send(port::Input::new(oversized)).await.unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    let port = broker.doubler.enabled().unwrap();
+    time::timeout(Duration::from_secs(10), async {
+        while !port.tx.is_closed() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the input wasn't closed");
+    assert!(port.send(port::Input::new(vec![1])).await.is_err());
+
+    broker.disable_doubler();
+    assert_eq!(
+        plan.results,
+        [large.iter().map(|x| x * 2).collect::<Vec<_>>()]
+    );
+}
+
+// The inputs left in the ring and in the broker queue are replayed in order
+// after each restart.
+#[agentwire::test(init = init)]
+async fn test_process_ring_restart_order() {
+    struct TestPlan {
+        results: Vec<u32>,
+    }
+    impl Plan for TestPlan {
+        fn handle_doubler(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Doubler>,
+        ) -> Result<BrokerFlow, Error> {
+            unreachable!()
+        }
+
+        fn handle_sequencer(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Sequencer>,
+        ) -> Result<BrokerFlow, Error> {
+            self.results.push(output.value);
+            if self.results.len() == 20 {
+                Ok(BrokerFlow::Break)
+            } else {
+                Ok(BrokerFlow::Continue)
+            }
+        }
+    }
+
+    let marker = crash_marker(process::id());
+    let _ = fs::remove_file(&marker);
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_sequencer().unwrap();
+
+    let fence = Instant::now();
+    let port = broker.sequencer.enabled().unwrap();
+    for input in 0..20 {
+        port.send(port::Input::new(input)).await.unwrap();
+    }
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_sequencer();
+    fs::remove_file(&marker).unwrap();
+    assert_eq!(plan.results, (0..20).collect::<Vec<_>>());
+}
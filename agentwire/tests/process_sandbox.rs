@@ -0,0 +1,324 @@
+use agentwire::{
+    agent::{
+        self,
+        process::{Initializer, Sandbox, SeccompFilter},
+        Process as _,
+    },
+    port::{self, Port, SharedPort},
+    Agent, Broker, BrokerFlow,
+};
+use futures::prelude::*;
+use nix::{
+    sched::CloneFlags,
+    sys::resource::Resource,
+    unistd::{geteuid, getgid, getuid},
+};
+use rkyv::{Archive, Deserialize, Serialize};
+use std::{
+    env, fs, io,
+    mem::size_of,
+    os::{fd::RawFd, unix::fs::PermissionsExt},
+    path::Path,
+    time::Instant,
+};
+use thiserror::Error;
+
+const NOFILE: u64 = 64;
+const NOBODY: u32 = 65534;
+
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct Inspector;
+
+/// Observed sandbox state: `no_new_privs` flag, `RLIMIT_NOFILE` hard limit,
+/// and `getppid` errno.
+type Report = [u64; 3];
+
+impl Port for Inspector {
+    type Input = ();
+    type Output = Report;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for Inspector {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Inspector as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<() as Archive>::Archived>();
+    const SERIALIZED_OUTPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Report as Archive>::Archived>();
+}
+
+impl Agent for Inspector {
+    const NAME: &'static str = "inspector";
+}
+
+#[derive(Error, Debug)]
+pub enum InspectorError {}
+
+struct SandboxInitializer;
+
+impl Initializer for SandboxInitializer {
+    fn keep_file_descriptors(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+
+    fn envs(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    fn sandbox(&self) -> Sandbox {
+        Sandbox {
+            drop_capabilities: true,
+            rlimits: vec![(Resource::RLIMIT_NOFILE, NOFILE)],
+            seccomp: Some(SeccompFilter::deny(&[libc::SYS_getppid])),
+            ..Sandbox::default()
+        }
+    }
+}
+
+impl agent::Process for Inspector {
+    type Error = InspectorError;
+
+    fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.recv() {
+            let chain = input.chain_fn();
+            let no_new_privs =
+                unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) };
+            let (_, nofile) =
+                nix::sys::resource::getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+            let getppid = unsafe { libc::syscall(libc::SYS_getppid) };
+            let errno = if getppid == -1 {
+                io::Error::last_os_error().raw_os_error()
+            } else {
+                None
+            };
+            let report = [
+                no_new_privs.try_into().unwrap(),
+                nofile,
+                errno.unwrap_or(0).try_into().unwrap(),
+            ];
+            port.send(&chain(report));
+        }
+        Ok(())
+    }
+
+    fn initializer() -> impl Initializer {
+        SandboxInitializer
+    }
+}
+
+// Switches the IDs and enters new namespaces without a new user namespace.
+#[derive(Clone, Default, Archive, Serialize, Deserialize, Debug)]
+struct IdInspector;
+
+/// Observed user and group IDs.
+type Ids = [u32; 2];
+
+impl Port for IdInspector {
+    type Input = ();
+    type Output = Ids;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl SharedPort for IdInspector {
+    const SERIALIZED_INIT_SIZE: usize =
+        size_of::<usize>() + size_of::<<IdInspector as Archive>::Archived>();
+    const SERIALIZED_INPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<() as Archive>::Archived>();
+    const SERIALIZED_OUTPUT_SIZE: usize =
+        size_of::<usize>() + size_of::<<Ids as Archive>::Archived>();
+}
+
+impl Agent for IdInspector {
+    const NAME: &'static str = "id-inspector";
+}
+
+struct IdsInitializer;
+
+impl Initializer for IdsInitializer {
+    fn keep_file_descriptors(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+
+    fn envs(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    fn sandbox(&self) -> Sandbox {
+        Sandbox {
+            namespaces: CloneFlags::CLONE_NEWIPC | CloneFlags::CLONE_NEWUTS,
+            uid: Some(NOBODY),
+            gid: Some(NOBODY),
+            ..Sandbox::default()
+        }
+    }
+}
+
+impl agent::Process for IdInspector {
+    type Error = InspectorError;
+
+    fn run(self, mut port: port::RemoteInner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.recv() {
+            let chain = input.chain_fn();
+            port.send(&chain([getuid().as_raw(), getgid().as_raw()]));
+        }
+        Ok(())
+    }
+
+    fn initializer() -> impl Initializer {
+        IdsInitializer
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_inspector(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Inspector>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_id_inspector(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<IdInspector>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(process)]
+    inspector: agent::Cell<Inspector>,
+    #[agent(process)]
+    id_inspector: agent::Cell<IdInspector>,
+}
+
+impl Broker {
+    fn handle_inspector(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Inspector>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_inspector(self, output)
+    }
+
+    fn handle_id_inspector(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<IdInspector>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_id_inspector(self, output)
+    }
+}
+
+fn init() {
+    agent::process::init(|name, fd| match name {
+        "inspector" => Ok(Inspector::call(fd)?),
+        "id-inspector" => Ok(IdInspector::call(fd)?),
+        _ => panic!("unregistered agent {name}"),
+    });
+}
+
+#[agentwire::test(init = init)]
+async fn test_process_sandbox() {
+    struct TestPlan {
+        result: Option<Report>,
+    }
+    impl Plan for TestPlan {
+        fn handle_inspector(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<Inspector>,
+        ) -> Result<BrokerFlow, Error> {
+            self.result = Some(output.value);
+            Ok(BrokerFlow::Break)
+        }
+
+        fn handle_id_inspector(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<IdInspector>,
+        ) -> Result<BrokerFlow, Error> {
+            unreachable!()
+        }
+    }
+
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+    broker.enable_inspector().unwrap();
+
+    let fence = Instant::now();
+    broker
+        .inspector
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(()))
+        .await
+        .unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_inspector();
+    let errno = libc::EPERM.try_into().unwrap();
+    assert_eq!(plan.result, Some([1, NOFILE, errno]));
+}
+
+// Switching the IDs and entering the namespaces require root in the broker, and
+// the agent must be able to execute the test binary after the switch.
+#[agentwire::test(init = init)]
+async fn test_process_sandbox_ids() {
+    struct TestPlan {
+        result: Option<Ids>,
+    }
+    impl Plan for TestPlan {
+        fn handle_inspector(
+            &mut self,
+            _broker: &mut Broker,
+            _output: port::Output<Inspector>,
+        ) -> Result<BrokerFlow, Error> {
+            unreachable!()
+        }
+
+        fn handle_id_inspector(
+            &mut self,
+            _broker: &mut Broker,
+            output: port::Output<IdInspector>,
+        ) -> Result<BrokerFlow, Error> {
+            self.result = Some(output.value);
+            Ok(BrokerFlow::Break)
+        }
+    }
+
+    if !geteuid().is_root() || !executable_by_others(&env::current_exe().unwrap()) {
+        return;
+    }
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+    broker.enable_id_inspector().unwrap();
+
+    let fence = Instant::now();
+    broker
+        .id_inspector
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(()))
+        .await
+        .unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_id_inspector();
+    assert_eq!(plan.result, Some([NOBODY, NOBODY]));
+}
+
+fn executable_by_others(path: &Path) -> bool {
+    path.ancestors().all(|path| {
+        fs::metadata(path)
+            .is_ok_and(|metadata| metadata.permissions().mode() & 0o001 != 0)
+    })
+}
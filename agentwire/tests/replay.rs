@@ -0,0 +1,196 @@
+use agentwire::{
+    agent,
+    port::{self, Port},
+    replay::{self, Direction},
+    Agent, Broker, BrokerFlow,
+};
+use futures::{channel::mpsc::SendError, prelude::*};
+use std::{
+    env,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::time;
+
+static FACTOR: AtomicU32 = AtomicU32::new(2);
+
+#[derive(Default)]
+struct Multiplier;
+
+impl Port for Multiplier {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 2;
+    const OUTPUT_CAPACITY: usize = 2;
+}
+
+impl Agent for Multiplier {
+    const NAME: &'static str = "multiplier";
+}
+
+impl agent::Task for Multiplier {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(x) = port.next().await {
+            let factor = FACTOR.load(Ordering::Relaxed);
+            port.send(x.chain(x.value * factor)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_multiplier(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Multiplier>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(task, record)]
+    multiplier: agent::Cell<Multiplier>,
+}
+
+impl Broker {
+    fn handle_multiplier(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Multiplier>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_multiplier(self, output)
+    }
+}
+
+struct TestPlan {
+    results: Vec<u32>,
+}
+
+impl Plan for TestPlan {
+    fn handle_multiplier(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Multiplier>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        if self.results.len() == 2 {
+            Ok(BrokerFlow::Break)
+        } else {
+            Ok(BrokerFlow::Continue)
+        }
+    }
+}
+
+async fn run_session() -> Vec<u32> {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_multiplier().unwrap();
+
+    let fence = Instant::now();
+    let port = broker.multiplier.enabled().unwrap();
+    port.send(port::Input::new(1)).await.unwrap();
+    port.send(port::Input::new(2)).await.unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_multiplier();
+    plan.results
+}
+
+#[agentwire::test]
+async fn test_record_and_replay() {
+    let path =
+        env::temp_dir().join(format!("agentwire-replay-{}.rec", std::process::id()));
+
+    replay::start_recording(&path).unwrap();
+    assert_eq!(run_session().await, [2, 4]);
+    replay::stop_recording().unwrap();
+
+    let events = replay::read_events(&path).unwrap();
+    assert_eq!(events.len(), 4);
+    assert!(events.iter().all(|event| event.agent == "multiplier"));
+    let inputs = events
+        .iter()
+        .filter(|event| event.direction == Direction::Input);
+    let outputs = events
+        .iter()
+        .filter(|event| event.direction == Direction::Output);
+    for (input, output) in inputs.zip(outputs) {
+        assert_eq!(input.source_ts, output.source_ts);
+        assert!(input.timestamp <= output.timestamp);
+    }
+
+    FACTOR.store(3, Ordering::Relaxed);
+    assert_eq!(run_session().await, [3, 6]);
+    replay::start_replay(&path).unwrap();
+    assert_eq!(run_session().await, [2, 4]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[agentwire::test(replay = "tests/data/multiplier.rec")]
+async fn test_replay_fixture() {
+    // The recording was made with a factor of 10.
+    assert_eq!(run_session().await, [10, 20]);
+}
+
+#[agentwire::test]
+async fn test_replay_invalid_output() {
+    let path = env::temp_dir().join(format!(
+        "agentwire-replay-invalid-{}.rec",
+        std::process::id()
+    ));
+    // A recording with a single output, truncated to a byte.
+    let mut recording = b"AWREC\0\0\x01".to_vec();
+    recording.extend_from_slice(&10_u16.to_le_bytes());
+    recording.extend_from_slice(b"multiplier");
+    recording.push(1);
+    recording.extend_from_slice(&0_i64.to_le_bytes());
+    recording.extend_from_slice(&0_i64.to_le_bytes());
+    recording.extend_from_slice(&1_u64.to_le_bytes());
+    recording.push(0xFF);
+    std::fs::write(&path, recording).unwrap();
+
+    replay::start_replay(&path).unwrap();
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_multiplier().unwrap();
+    let _ = time::timeout(Duration::from_millis(100), broker.run(&mut plan)).await;
+    broker.disable_multiplier();
+    replay::stop_replay();
+    assert!(plan.results.is_empty());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_read_corrupt_recording() {
+    let path =
+        env::temp_dir().join(format!("agentwire-corrupt-{}.rec", std::process::id()));
+    let mut data = b"AWREC\0\0\x01".to_vec();
+    data.extend_from_slice(&1_u16.to_le_bytes());
+    data.extend_from_slice(b"x");
+    data.push(1);
+    data.extend_from_slice(&0_i64.to_le_bytes());
+    data.extend_from_slice(&0_i64.to_le_bytes());
+    // The payload length is far beyond the file length.
+    data.extend_from_slice(&u64::MAX.to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    std::fs::write(&path, data).unwrap();
+
+    let err = replay::read_events(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,304 @@
+use agentwire::{
+    agent::{self, ExitStrategy, RestartPolicy},
+    port::{self, Port},
+    Agent, Broker, BrokerError, BrokerFlow,
+};
+use futures::{channel::mpsc::SendError, prelude::*};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{runtime, time};
+
+static TASK_PANICKED: AtomicBool = AtomicBool::new(false);
+static THREAD_PANICKED: AtomicBool = AtomicBool::new(false);
+static IDLE_PANICKED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Default)]
+struct FlakyTask;
+
+impl Port for FlakyTask {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for FlakyTask {
+    const NAME: &'static str = "flaky-task";
+}
+
+impl agent::Task for FlakyTask {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(x) = port.next().await {
+            assert!(TASK_PANICKED.swap(true, Ordering::Relaxed), "first input");
+            port.send(x.chain(x.value * 2)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct FlakyThread;
+
+impl Port for FlakyThread {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for FlakyThread {
+    const NAME: &'static str = "flaky-thread";
+}
+
+impl agent::Thread for FlakyThread {
+    type Error = SendError;
+
+    fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        while let Some(x) = rt.block_on(port.next()) {
+            assert!(THREAD_PANICKED.swap(true, Ordering::Relaxed), "first input");
+            rt.block_on(port.send(x.chain(x.value * 3)))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct IdleCrash;
+
+impl Port for IdleCrash {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for IdleCrash {
+    const NAME: &'static str = "idle-crash";
+}
+
+impl agent::Task for IdleCrash {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        loop {
+            match time::timeout(Duration::from_millis(20), port.next()).await {
+                Ok(Some(x)) => port.send(x.chain(x.value + 1)).await?,
+                Ok(None) => return Ok(()),
+                Err(_) => {
+                    assert!(IDLE_PANICKED.swap(true, Ordering::Relaxed), "idle");
+                }
+            }
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_task(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<FlakyTask>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_thread(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<FlakyThread>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_idle_crash(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<IdleCrash>,
+    ) -> Result<BrokerFlow, Error>;
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(task, restart = RestartPolicy {
+        backoff: Duration::from_millis(10),
+        ..RestartPolicy::default()
+    })]
+    task: agent::Cell<FlakyTask>,
+    #[agent(thread, restart = RestartPolicy {
+        strategy: ExitStrategy::Close,
+        ..RestartPolicy::default()
+    })]
+    thread: agent::Cell<FlakyThread>,
+    #[agent(task, restart = RestartPolicy {
+        backoff: Duration::from_millis(10),
+        ..RestartPolicy::default()
+    })]
+    idle_crash: agent::Cell<IdleCrash>,
+}
+
+impl Broker {
+    fn handle_task(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<FlakyTask>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_task(self, output)
+    }
+
+    fn handle_thread(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<FlakyThread>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_thread(self, output)
+    }
+
+    fn handle_idle_crash(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<IdleCrash>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_idle_crash(self, output)
+    }
+}
+
+struct TestPlan {
+    result: Option<u32>,
+}
+
+struct CountPlan {
+    results: Vec<u32>,
+}
+
+impl Plan for TestPlan {
+    fn handle_task(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<FlakyTask>,
+    ) -> Result<BrokerFlow, Error> {
+        self.result = Some(output.value);
+        Ok(BrokerFlow::Break)
+    }
+
+    fn handle_thread(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<FlakyThread>,
+    ) -> Result<BrokerFlow, Error> {
+        self.result = Some(output.value);
+        Ok(BrokerFlow::Break)
+    }
+
+    fn handle_idle_crash(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<IdleCrash>,
+    ) -> Result<BrokerFlow, Error> {
+        unreachable!()
+    }
+}
+
+impl Plan for CountPlan {
+    fn handle_task(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<FlakyTask>,
+    ) -> Result<BrokerFlow, Error> {
+        unreachable!()
+    }
+
+    fn handle_thread(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<FlakyThread>,
+    ) -> Result<BrokerFlow, Error> {
+        unreachable!()
+    }
+
+    fn handle_idle_crash(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<IdleCrash>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        Ok(BrokerFlow::Continue)
+    }
+}
+
+#[agentwire::test]
+async fn test_task_retry() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+    broker.enable_task().unwrap();
+
+    let fence = Instant::now();
+    broker
+        .task
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(3))
+        .await
+        .unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_task();
+    assert_eq!(plan.result, Some(6));
+}
+
+#[agentwire::test]
+async fn test_thread_close() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+    broker.enable_thread().unwrap();
+
+    let fence = Instant::now();
+    broker
+        .thread
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(3))
+        .await
+        .unwrap();
+    let result = broker.run_with_fence(&mut plan, fence).await;
+
+    broker.disable_thread();
+    assert!(matches!(
+        result,
+        Err(BrokerError::AgentTerminated("thread"))
+    ));
+    assert_eq!(plan.result, None);
+}
+
+// A crash long after the input was handled must not process it again.
+#[agentwire::test]
+async fn test_task_retry_handled_input() {
+    let mut broker = new_broker!();
+    let mut plan = CountPlan {
+        results: Vec::new(),
+    };
+    broker.enable_idle_crash().unwrap();
+
+    broker
+        .idle_crash
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(3))
+        .await
+        .unwrap();
+    let _ = time::timeout(Duration::from_millis(200), broker.run(&mut plan)).await;
+
+    broker.disable_idle_crash();
+    assert!(IDLE_PANICKED.load(Ordering::Relaxed));
+    assert_eq!(plan.results, [4]);
+}
@@ -0,0 +1,225 @@
+use agentwire::{
+    agent,
+    port::{self, Forward, Port},
+    Agent, Broker, BrokerFlow,
+};
+use futures::{channel::mpsc::SendError, prelude::*};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time;
+
+#[derive(Default)]
+struct Source;
+
+impl Port for Source {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 3;
+    const OUTPUT_CAPACITY: usize = 3;
+}
+
+impl Agent for Source {
+    const NAME: &'static str = "source";
+}
+
+impl agent::Task for Source {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.next().await {
+            time::sleep(Duration::from_millis(10)).await;
+            port.send(input.chain(input.value + 1)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct Doubler;
+
+impl Port for Doubler {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 3;
+    const OUTPUT_CAPACITY: usize = 3;
+}
+
+impl Agent for Doubler {
+    const NAME: &'static str = "doubler";
+}
+
+impl agent::Task for Doubler {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        while let Some(input) = port.next().await {
+            port.send(input.chain(input.value * 2)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Forward<Doubler> for Source {
+    fn forward(output: &u32) -> Option<u32> {
+        Some(*output)
+    }
+}
+
+#[derive(Default)]
+struct Ticker;
+
+impl Port for Ticker {
+    type Input = ();
+    type Output = ();
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for Ticker {
+    const NAME: &'static str = "ticker";
+}
+
+impl agent::Task for Ticker {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        // Ignores the closed input and never drains.
+        loop {
+            time::sleep(Duration::from_millis(10)).await;
+            port.send(port::Output::new(())).await?;
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_source(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<Source>,
+    ) -> Result<BrokerFlow, Error> {
+        Ok(BrokerFlow::Continue)
+    }
+
+    fn handle_doubler(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_ticker(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<Ticker>,
+    ) -> Result<BrokerFlow, Error> {
+        Ok(BrokerFlow::Continue)
+    }
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(task, restart = agent::RestartPolicy::default())]
+    doubler: agent::Cell<Doubler>,
+    #[agent(task, forward_to = [doubler])]
+    source: agent::Cell<Source>,
+    #[agent(task)]
+    ticker: agent::Cell<Ticker>,
+}
+
+impl Broker {
+    fn handle_source(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Source>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_source(self, output)
+    }
+
+    fn handle_doubler(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_doubler(self, output)
+    }
+
+    fn handle_ticker(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Ticker>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_ticker(self, output)
+    }
+}
+
+struct TestPlan {
+    results: Vec<u32>,
+}
+
+impl Plan for TestPlan {
+    fn handle_doubler(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Doubler>,
+    ) -> Result<BrokerFlow, Error> {
+        self.results.push(output.value);
+        // Ignored while draining.
+        Ok(BrokerFlow::Break)
+    }
+}
+
+#[agentwire::test]
+async fn test_shutdown_drain() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_doubler().unwrap();
+    broker.enable_source().unwrap();
+
+    let port = broker.source.enabled().unwrap();
+    for input in [1, 2, 3] {
+        port.send(port::Input::new(input)).await.unwrap();
+    }
+    broker
+        .shutdown(&mut plan, Duration::from_secs(10))
+        .await
+        .unwrap();
+
+    assert_eq!(plan.results, [4, 6, 8]);
+    assert!(!broker.source.is_enabled());
+    assert!(!broker.doubler.is_enabled());
+    assert!(broker.source.enabled().is_none());
+}
+
+#[agentwire::test]
+async fn test_shutdown_timeout() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan {
+        results: Vec::new(),
+    };
+    broker.enable_ticker().unwrap();
+    broker.enable_doubler().unwrap();
+
+    broker
+        .doubler
+        .enabled()
+        .unwrap()
+        .send(port::Input::new(5))
+        .await
+        .unwrap();
+    let timeout = Duration::from_millis(200);
+    let started = time::Instant::now();
+    broker.shutdown(&mut plan, timeout).await.unwrap();
+
+    assert!(started.elapsed() >= timeout);
+    assert_eq!(plan.results, [10]);
+    assert!(!broker.ticker.is_enabled());
+    assert!(!broker.doubler.is_enabled());
+}
@@ -0,0 +1,270 @@
+use agentwire::{
+    agent::{self, Watchdog, WatchdogAction},
+    port::{self, Forward, Port},
+    Agent, Broker, BrokerError, BrokerFlow,
+};
+use futures::{channel::mpsc::SendError, prelude::*};
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{runtime, time};
+
+const DEADLINE: Duration = Duration::from_millis(100);
+
+static TICKER_SPAWNS: AtomicU32 = AtomicU32::new(0);
+static TICKER_WATCHDOGS: AtomicU32 = AtomicU32::new(0);
+
+fn ticker_watchdog() -> Watchdog {
+    TICKER_WATCHDOGS.fetch_add(1, Ordering::Relaxed);
+    Watchdog {
+        heartbeat: true,
+        action: WatchdogAction::Restart,
+        ..Watchdog::new(DEADLINE)
+    }
+}
+
+#[derive(Default)]
+struct Stuck;
+
+impl Port for Stuck {
+    type Input = u32;
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 1;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for Stuck {
+    const NAME: &'static str = "stuck";
+}
+
+impl agent::Thread for Stuck {
+    type Error = SendError;
+
+    fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        while let Some(x) = rt.block_on(port.next()) {
+            if x.value == 0 {
+                // Hang without exiting.
+                loop {
+                    thread::park();
+                }
+            }
+            rt.block_on(port.send(x.chain(x.value)))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Ticker;
+
+impl Port for Ticker {
+    type Input = ();
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for Ticker {
+    const NAME: &'static str = "ticker";
+}
+
+impl agent::Task for Ticker {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        let spawn = TICKER_SPAWNS.fetch_add(1, Ordering::Relaxed) + 1;
+        if spawn == 1 {
+            future::pending::<()>().await;
+        }
+        loop {
+            port.send(port::Output::new(spawn)).await?;
+            time::sleep(DEADLINE / 10).await;
+        }
+    }
+}
+
+// Keeps forwarding to `stuck` after its input queue fills up.
+#[derive(Default)]
+struct Pulse;
+
+impl Port for Pulse {
+    type Input = ();
+    type Output = u32;
+
+    const INPUT_CAPACITY: usize = 0;
+    const OUTPUT_CAPACITY: usize = 0;
+}
+
+impl Agent for Pulse {
+    const NAME: &'static str = "pulse";
+}
+
+impl agent::Task for Pulse {
+    type Error = SendError;
+
+    async fn run(self, mut port: port::Inner<Self>) -> Result<(), Self::Error> {
+        loop {
+            port.send(port::Output::new(1)).await?;
+            time::sleep(DEADLINE / 10).await;
+        }
+    }
+}
+
+impl Forward<Stuck> for Pulse {
+    fn forward(output: &u32) -> Option<u32> {
+        Some(*output)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {}
+
+trait Plan {
+    fn handle_stuck(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Stuck>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_ticker(
+        &mut self,
+        broker: &mut Broker,
+        output: port::Output<Ticker>,
+    ) -> Result<BrokerFlow, Error>;
+
+    fn handle_pulse(
+        &mut self,
+        _broker: &mut Broker,
+        _output: port::Output<Pulse>,
+    ) -> Result<BrokerFlow, Error> {
+        Ok(BrokerFlow::Continue)
+    }
+}
+
+#[derive(Broker)]
+#[broker(plan = Plan, error = Error)]
+struct Broker {
+    #[agent(thread, watchdog = Watchdog::new(DEADLINE))]
+    stuck: agent::Cell<Stuck>,
+    #[agent(task, watchdog = ticker_watchdog())]
+    ticker: agent::Cell<Ticker>,
+    #[agent(task, forward_to = [stuck])]
+    pulse: agent::Cell<Pulse>,
+}
+
+impl Broker {
+    fn handle_stuck(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Stuck>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_stuck(self, output)
+    }
+
+    fn handle_ticker(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Ticker>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_ticker(self, output)
+    }
+
+    fn handle_pulse(
+        &mut self,
+        plan: &mut dyn Plan,
+        output: port::Output<Pulse>,
+    ) -> Result<BrokerFlow, Error> {
+        plan.handle_pulse(self, output)
+    }
+}
+
+struct TestPlan {
+    result: Option<u32>,
+}
+
+impl Plan for TestPlan {
+    fn handle_stuck(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Stuck>,
+    ) -> Result<BrokerFlow, Error> {
+        self.result = Some(output.value);
+        Ok(BrokerFlow::Break)
+    }
+
+    fn handle_ticker(
+        &mut self,
+        _broker: &mut Broker,
+        output: port::Output<Ticker>,
+    ) -> Result<BrokerFlow, Error> {
+        self.result = Some(output.value);
+        Ok(BrokerFlow::Break)
+    }
+}
+
+#[agentwire::test]
+async fn test_watchdog_report() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+    broker.enable_stuck().unwrap();
+
+    let fence = Instant::now();
+    let port = broker.stuck.enabled().unwrap();
+    port.send(port::Input::new(0)).await.unwrap();
+    port.send(port::Input::new(1)).await.unwrap();
+    let result = broker.run_with_fence(&mut plan, fence).await;
+
+    broker.disable_stuck();
+    assert!(matches!(
+        result,
+        Err(BrokerError::AgentHung("stuck", stalled)) if stalled >= DEADLINE
+    ));
+    assert_eq!(plan.result, None);
+}
+
+// Messages dropped on the full input queue don't count as a progress.
+#[agentwire::test]
+async fn test_watchdog_full_queue() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+    broker.enable_stuck().unwrap();
+
+    let fence = Instant::now();
+    let port = broker.stuck.enabled().unwrap();
+    port.send(port::Input::new(0)).await.unwrap();
+    broker.enable_pulse().unwrap();
+    let result = broker.run_with_fence(&mut plan, fence).await;
+
+    broker.disable_pulse();
+    broker.disable_stuck();
+    assert!(matches!(
+        result,
+        Err(BrokerError::AgentHung("stuck", stalled)) if stalled >= DEADLINE
+    ));
+    assert_eq!(plan.result, None);
+}
+
+#[agentwire::test]
+async fn test_watchdog_restart() {
+    let mut broker = new_broker!();
+    let mut plan = TestPlan { result: None };
+
+    let fence = Instant::now();
+    broker.enable_ticker().unwrap();
+    broker.run_with_fence(&mut plan, fence).await.unwrap();
+
+    broker.disable_ticker();
+    assert_eq!(plan.result, Some(2));
+    assert_eq!(TICKER_SPAWNS.load(Ordering::Relaxed), 2);
+    // Evaluated once for each spawned agent.
+    assert_eq!(TICKER_WATCHDOGS.load(Ordering::Relaxed), 2);
+}
@@ -16,8 +16,12 @@ pub struct Protocol(pub(crate) libc::c_int);
 impl Protocol {
     pub const ISOTP: Protocol = Protocol(libc::CAN_ISOTP);
     pub const RAW: Protocol = Protocol(libc::CAN_RAW);
-    pub const _BCM: Protocol = Protocol(libc::CAN_BCM);
-    pub const _J1939: Protocol = Protocol(libc::CAN_J1939);
+    pub const BCM: Protocol = Protocol(libc::CAN_BCM);
+    pub const J1939: Protocol = Protocol(libc::CAN_J1939);
+    #[deprecated(note = "use `Protocol::BCM`")]
+    pub const _BCM: Protocol = Protocol::BCM;
+    #[deprecated(note = "use `Protocol::J1939`")]
+    pub const _J1939: Protocol = Protocol::J1939;
     pub const _MCNET: Protocol = Protocol(libc::CAN_MCNET);
     pub const _NPROTO: Protocol = Protocol(libc::CAN_NPROTO);
     pub const _TP16: Protocol = Protocol(libc::CAN_TP16);
@@ -0,0 +1,313 @@
+//! SocketCAN broadcast manager (`CAN_BCM`)
+//!
+//! The broadcast manager moves timing-sensitive work into the kernel: cyclic transmission of
+//! frames at a fixed interval, receive filtering that only reports changed frame contents, and
+//! notifications when an expected cyclic frame stops arriving.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use can_rs::{bcm::BcmSocket, Frame, Id, CAN_DATA_LEN};
+//!
+//! let bcm = BcmSocket::<CAN_DATA_LEN>::new("can0".parse().unwrap())
+//!     .expect("failed to connect can0 broadcast manager");
+//!
+//! // Heartbeat every 100ms until the socket is dropped or the job is stopped.
+//! let heartbeat = Frame {
+//!     id: Id::Standard(0x100),
+//!     flags: 0,
+//!     len: 1,
+//!     data: [0u8; 8],
+//! };
+//! bcm.start_cyclic(&heartbeat, Duration::from_millis(100)).unwrap();
+//! ```
+
+use std::{
+    io, mem,
+    os::{
+        fd::OwnedFd,
+        unix::io::{AsRawFd, IntoRawFd, RawFd},
+    },
+    time::Duration,
+};
+
+use crate::{
+    addr::CanAddr,
+    socket,
+    stream::{imp::RawFrame, AllowedToBind},
+    Error, Frame, Id, Protocol, Type, CANFD_DATA_LEN,
+};
+
+const TX_SETUP: u32 = 1;
+const TX_DELETE: u32 = 2;
+const RX_SETUP: u32 = 5;
+const RX_DELETE: u32 = 6;
+const TX_EXPIRED: u32 = 9;
+const RX_TIMEOUT: u32 = 11;
+const RX_CHANGED: u32 = 12;
+
+const SETTIMER: u32 = 0x0001;
+const STARTTIMER: u32 = 0x0002;
+const RX_FILTER_ID: u32 = 0x0020;
+const RX_CHECK_DLC: u32 = 0x0040;
+const RX_ANNOUNCE_RESUME: u32 = 0x0100;
+const CAN_FD_FRAME: u32 = 0x0800;
+
+/// A broadcast manager socket connected to a CAN interface
+///
+/// Every transmission and reception job is identified by its CAN ID, and is removed when the
+/// socket is dropped.
+pub struct BcmSocket<const N: usize> {
+    pub(crate) fd: OwnedFd,
+    pub(crate) addr: CanAddr,
+}
+
+/// Receive job options for [`BcmSocket::rx_setup`]
+#[derive(Clone, Copy, Debug)]
+pub struct RxOptions<const N: usize> {
+    /// Only report frames whose bits selected by the mask changed since the last report. `None`
+    /// reports every received frame.
+    pub mask: Option<[u8; N]>,
+    /// Report [`BcmEvent::Timeout`] when no frame was received for this long.
+    pub timeout: Option<Duration>,
+    /// Report at most one changed frame per this interval.
+    pub throttle: Option<Duration>,
+    /// Also report data length changes.
+    pub check_dlc: bool,
+    /// Report the first frame received after a timeout even if it didn't change.
+    pub announce_resume: bool,
+}
+
+impl<const N: usize> Default for RxOptions<N> {
+    fn default() -> Self {
+        Self {
+            mask: None,
+            timeout: None,
+            throttle: None,
+            check_dlc: false,
+            announce_resume: false,
+        }
+    }
+}
+
+/// Notification received from the broadcast manager
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BcmEvent<const N: usize> {
+    /// A frame of a receive job changed, or was received for the first time.
+    Changed(Frame<N>),
+    /// No frame was received within the timeout of the receive job.
+    Timeout(Id),
+    /// A transmission job with a limited count finished.
+    TxExpired(Id),
+    /// Any other message, identified by its opcode.
+    Other { opcode: u32, id: Id },
+}
+
+impl<const N: usize> BcmSocket<N>
+where
+    [(); N]: AllowedToBind,
+{
+    pub fn new(addr: CanAddr) -> Result<Self, Error> {
+        let socket = Self {
+            fd: socket::new(Type::DGRAM, Protocol::BCM)?,
+            addr,
+        };
+        socket::connect(socket.fd.as_raw_fd(), &socket.addr)?;
+        Ok(socket)
+    }
+}
+
+impl<const N: usize> BcmSocket<N> {
+    /// Starts sending `frame` every `interval`. Calling it again for the same CAN ID updates the
+    /// frame contents and the interval of the running job.
+    pub fn start_cyclic(
+        &self,
+        frame: &Frame<N>,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        let head = MsgHead {
+            flags: SETTIMER | STARTTIMER,
+            ival2: interval.into(),
+            ..MsgHead::new(TX_SETUP, frame.id)
+        };
+        self.write(head, Some(frame), "TX_SETUP")
+    }
+
+    /// Stops the cyclic transmission of the frame with the given CAN ID.
+    pub fn stop_cyclic(&self, id: Id) -> Result<(), Error> {
+        self.write(MsgHead::new(TX_DELETE, id), None, "TX_DELETE")
+    }
+
+    /// Subscribes to the frames with the given CAN ID. The notifications are read with
+    /// [`BcmSocket::recv`].
+    pub fn rx_setup(&self, id: Id, options: &RxOptions<N>) -> Result<(), Error> {
+        let mut head = MsgHead::new(RX_SETUP, id);
+        if options.timeout.is_some() || options.throttle.is_some() {
+            head.flags |= SETTIMER | STARTTIMER;
+            head.ival1 = options.timeout.unwrap_or_default().into();
+            head.ival2 = options.throttle.unwrap_or_default().into();
+        }
+        if options.check_dlc {
+            head.flags |= RX_CHECK_DLC;
+        }
+        if options.announce_resume {
+            head.flags |= RX_ANNOUNCE_RESUME;
+        }
+        let frame = options.mask.map(|mask| Frame {
+            id,
+            len: N as u8,
+            flags: 0,
+            data: mask,
+        });
+        if frame.is_none() {
+            head.flags |= RX_FILTER_ID;
+        }
+        self.write(head, frame.as_ref(), "RX_SETUP")
+    }
+
+    /// Removes the receive job for the given CAN ID.
+    pub fn rx_delete(&self, id: Id) -> Result<(), Error> {
+        self.write(MsgHead::new(RX_DELETE, id), None, "RX_DELETE")
+    }
+
+    /// Blocks until the next notification from the broadcast manager.
+    pub fn recv(&self) -> Result<BcmEvent<N>, Error> {
+        let mut buf = vec![0u8; FRAMES_OFFSET + mem::size_of::<RawFrame<N>>()];
+        let ret = unsafe {
+            libc::read(
+                self.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let len = ret as usize;
+        if len < mem::size_of::<MsgHead>() {
+            return Err(short_read(len).into());
+        }
+        let head: MsgHead = unsafe { std::ptr::read_unaligned(buf.as_ptr().cast()) };
+        let id = Id::from(head.can_id);
+        Ok(match head.opcode {
+            RX_CHANGED => {
+                if head.nframes < 1 || len < buf.len() {
+                    return Err(short_read(len).into());
+                }
+                let raw: RawFrame<N> = unsafe {
+                    std::ptr::read_unaligned(buf[FRAMES_OFFSET..].as_ptr().cast())
+                };
+                BcmEvent::Changed(raw.into())
+            }
+            RX_TIMEOUT => BcmEvent::Timeout(id),
+            TX_EXPIRED => BcmEvent::TxExpired(id),
+            opcode => BcmEvent::Other { opcode, id },
+        })
+    }
+
+    fn write(
+        &self,
+        mut head: MsgHead,
+        frame: Option<&Frame<N>>,
+        context: &str,
+    ) -> Result<(), Error> {
+        if N == CANFD_DATA_LEN {
+            head.flags |= CAN_FD_FRAME;
+        }
+        head.nframes = u32::from(frame.is_some());
+        let mut buf = vec![0u8; FRAMES_OFFSET];
+        unsafe { std::ptr::write_unaligned(buf.as_mut_ptr().cast(), head) };
+        if let Some(frame) = frame {
+            let raw = RawFrame::from(*frame);
+            buf.extend_from_slice(unsafe {
+                std::slice::from_raw_parts(
+                    (&raw as *const RawFrame<N>).cast::<u8>(),
+                    mem::size_of::<RawFrame<N>>(),
+                )
+            });
+        }
+        let ret = unsafe {
+            libc::write(
+                self.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "write(2)".to_string(),
+                context: Some(format!("sending {context} to {}", self.addr.name)),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> AsRawFd for BcmSocket<N> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl<const N: usize> IntoRawFd for BcmSocket<N> {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+fn short_read(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("short read of {len} bytes from CAN_BCM socket"),
+    )
+}
+
+/// `struct bcm_timeval` from `linux/can/bcm.h`
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct Timeval {
+    tv_sec: libc::c_long,
+    tv_usec: libc::c_long,
+}
+
+impl From<Duration> for Timeval {
+    fn from(duration: Duration) -> Self {
+        Self {
+            tv_sec: duration.as_secs() as libc::c_long,
+            tv_usec: duration.subsec_micros() as libc::c_long,
+        }
+    }
+}
+
+/// `struct bcm_msg_head` from `linux/can/bcm.h`, without the trailing frames
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct MsgHead {
+    opcode: u32,
+    flags: u32,
+    count: u32,
+    ival1: Timeval,
+    ival2: Timeval,
+    can_id: u32,
+    nframes: u32,
+}
+
+impl MsgHead {
+    fn new(opcode: u32, id: Id) -> Self {
+        Self {
+            opcode,
+            flags: 0,
+            count: 0,
+            ival1: Timeval::default(),
+            ival2: Timeval::default(),
+            can_id: id.wire_value(),
+            nframes: 0,
+        }
+    }
+}
+
+/// The frames following the head are 8 bytes aligned.
+const FRAMES_OFFSET: usize = (mem::size_of::<MsgHead>() + 7) & !7;
@@ -0,0 +1,410 @@
+//! Reading and writing `candump -l` log files
+//!
+//! Every line of a log holds one frame in the compact `candump` notation, prefixed by the
+//! reception timestamp and the interface name:
+//!
+//! ```text
+//! (1436509052.249713) can0 123#DEADBEEF
+//! (1436509052.250101) can0 12345678#0011
+//! (1436509052.250420) can0 123##1AABBCCDDEEFF0011
+//! ```
+//!
+//! The logs can be replayed into an interface at their original timing with [`replay`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::{fs::File, io::BufReader};
+//!
+//! use can_rs::candump::{self, LogReader};
+//!
+//! let log = LogReader::new(BufReader::new(File::open("trace.log").unwrap()));
+//! let entries = log.collect::<Result<Vec<_>, _>>().unwrap();
+//! for entry in candump::replay(entries, "vcan0".parse().unwrap()) {
+//!     println!("{}", entry.unwrap());
+//! }
+//! ```
+
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    addr::CanAddr, convert_dlc_to_len, convert_len_to_dlc, stream::FrameStream, Error,
+    Frame, Id, Length, CANFD_DATA_LEN, CAN_DATA_LEN,
+};
+
+/// A frame of either size, as found in a log
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LogFrame {
+    Can(Frame<CAN_DATA_LEN>),
+    CanFd(Frame<CANFD_DATA_LEN>),
+}
+
+/// A single line of a `candump -l` log
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    /// Time since the UNIX epoch at which the frame was received.
+    pub timestamp: Duration,
+    pub interface: String,
+    pub frame: LogFrame,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({}.{:06}) {} ",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros(),
+            self.interface
+        )?;
+        match &self.frame {
+            LogFrame::Can(frame) => {
+                write_id(f, frame.id)?;
+                f.write_str("#")?;
+                write_data(f, &frame.data[..usize::from(frame.len).min(CAN_DATA_LEN)])
+            }
+            LogFrame::CanFd(frame) => {
+                write_id(f, frame.id)?;
+                write!(f, "##{:X}", frame.flags & 0x0F)?;
+                write_data(f, &frame.data[..usize::from(frame.len).min(CANFD_DATA_LEN)])
+            }
+        }
+    }
+}
+
+fn write_id(f: &mut fmt::Formatter<'_>, id: Id) -> fmt::Result {
+    match id {
+        Id::Standard(_) => write!(f, "{:03X}", id.value()),
+        Id::Extended(_) => write!(f, "{:08X}", id.value()),
+        Id::Error(_) => write!(f, "{:08X}", id.value() | libc::CAN_ERR_FLAG),
+    }
+}
+
+fn write_data(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    data.iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+}
+
+impl FromStr for LogEntry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| Error::CandumpParse {
+            line: line.to_string(),
+            reason,
+        };
+        let mut fields = line.split_whitespace();
+        let timestamp = fields
+            .next()
+            .and_then(|field| field.strip_prefix('(')?.strip_suffix(')'))
+            .ok_or_else(|| invalid("missing timestamp"))?;
+        let interface = fields.next().ok_or_else(|| invalid("missing interface"))?;
+        let frame = fields.next().ok_or_else(|| invalid("missing frame"))?;
+
+        let timestamp =
+            parse_timestamp(timestamp).ok_or_else(|| invalid("malformed timestamp"))?;
+
+        let (id, data) = frame
+            .split_once('#')
+            .ok_or_else(|| invalid("missing `#` separator"))?;
+        let id = parse_id(id).ok_or_else(|| invalid("malformed CAN ID"))?;
+        let frame = if let Some(data) = data.strip_prefix('#') {
+            let mut chars = data.chars();
+            let flags = chars
+                .next()
+                .and_then(|flags| flags.to_digit(16))
+                .ok_or_else(|| invalid("missing CAN FD flags"))?;
+            let mut frame = Frame::<CANFD_DATA_LEN>::empty();
+            frame.id = id;
+            frame.flags = flags as u8;
+            frame.len = parse_data(chars.as_str(), &mut frame.data)
+                .ok_or_else(|| invalid("malformed data"))?;
+            if !is_fd_len(frame.len) {
+                return Err(invalid("CAN FD data length doesn't match a DLC"));
+            }
+            LogFrame::CanFd(frame)
+        } else {
+            if data.starts_with('R') {
+                return Err(invalid("remote frames are not supported"));
+            }
+            // Drop the optional `_<dlc>` suffix of classical frames longer than 8 bytes.
+            let data = data.split_once('_').map_or(data, |(data, _)| data);
+            let mut frame = Frame::<CAN_DATA_LEN>::empty();
+            frame.id = id;
+            frame.len = parse_data(data, &mut frame.data)
+                .ok_or_else(|| invalid("malformed data"))?;
+            LogFrame::Can(frame)
+        };
+
+        Ok(Self {
+            timestamp,
+            interface: interface.to_string(),
+            frame,
+        })
+    }
+}
+
+/// Parses `<secs>.<fraction>`, where the fraction has at most nanosecond precision.
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let (secs, fraction) = timestamp.split_once('.')?;
+    if fraction.is_empty()
+        || fraction.len() > 9
+        || !fraction.bytes().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let nanos = fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32);
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+fn parse_id(id: &str) -> Option<Id> {
+    let value = u32::from_str_radix(id, 16).ok()?;
+    match id.len() {
+        3 if value <= libc::CAN_SFF_MASK => Some(Id::Standard(value)),
+        8 if value & libc::CAN_ERR_FLAG != 0 => Some(Id::from(value)),
+        8 => Some(Id::Extended(value)),
+        _ => None,
+    }
+}
+
+/// Parses hex encoded bytes, optionally separated by dots, into `buf`. Returns the number of
+/// bytes.
+fn parse_data(data: &str, buf: &mut [u8]) -> Option<u8> {
+    let digits = data.bytes().filter(|&c| c != b'.').collect::<Vec<_>>();
+    if digits.len() % 2 != 0 || digits.len() / 2 > buf.len() {
+        return None;
+    }
+    for (byte, pair) in buf.iter_mut().zip(digits.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some((digits.len() / 2) as u8)
+}
+
+/// Whether a CAN FD frame can carry exactly `len` bytes.
+fn is_fd_len(len: u8) -> bool {
+    u8::from(convert_dlc_to_len(convert_len_to_dlc(Length::Bytes(len)))) == len
+}
+
+/// An iterator over the entries of a `candump -l` log
+///
+/// Empty lines and lines starting with `#` are skipped.
+pub struct LogReader<R> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> LogReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<LogEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(err.into())),
+            }
+            let line = self.line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                return Some(line.parse());
+            }
+        }
+    }
+}
+
+/// Writes entries in the `candump -l` format
+pub struct LogWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> LogWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, entry: &LogEntry) -> io::Result<()> {
+        writeln!(self.writer, "{entry}")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Sends the entries to the interface `addr` at their original relative timing, ignoring the
+/// interfaces recorded in the log. Each entry is yielded once it was sent.
+pub fn replay<I>(entries: I, addr: CanAddr) -> Replay<I::IntoIter>
+where
+    I: IntoIterator<Item = LogEntry>,
+{
+    Replay {
+        entries: entries.into_iter(),
+        addr,
+        can: None,
+        canfd: None,
+        start: None,
+    }
+}
+
+/// Iterator returned by [`replay`]
+pub struct Replay<I> {
+    entries: I,
+    addr: CanAddr,
+    can: Option<FrameStream<CAN_DATA_LEN>>,
+    canfd: Option<FrameStream<CANFD_DATA_LEN>>,
+    start: Option<(Instant, Duration)>,
+}
+
+impl<I: Iterator<Item = LogEntry>> Iterator for Replay<I> {
+    type Item = Result<LogEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        let (start, first) = *self
+            .start
+            .get_or_insert_with(|| (Instant::now(), entry.timestamp));
+        let offset = entry.timestamp.saturating_sub(first);
+        if let Some(delay) = (start + offset).checked_duration_since(Instant::now()) {
+            thread::sleep(delay);
+        }
+        Some(self.send(&entry.frame).map(|()| entry))
+    }
+}
+
+impl<I> Replay<I> {
+    fn send(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        match frame {
+            LogFrame::Can(frame) => {
+                let stream = match &mut self.can {
+                    Some(stream) => stream,
+                    None => self
+                        .can
+                        .insert(FrameStream::<CAN_DATA_LEN>::new(self.addr.clone())?),
+                };
+                stream.send(frame, 0)?;
+            }
+            LogFrame::CanFd(frame) => {
+                let stream = match &mut self.canfd {
+                    Some(stream) => stream,
+                    None => self
+                        .canfd
+                        .insert(FrameStream::<CANFD_DATA_LEN>::new(self.addr.clone())?),
+                };
+                stream.send(frame, 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+(1436509052.249713) can0 123#DEADBEEF
+(1436509052.250101) can0 12345678#0011
+(1436509052.250420) can1 123##1AABBCCDDEEFF0011
+(1436509052.260000) can0 20000080#0000000000000000
+";
+
+    #[test]
+    fn roundtrip_log() {
+        let entries = LogReader::new(LOG.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].timestamp, Duration::new(1436509052, 249_713_000));
+        let LogFrame::Can(frame) = entries[0].frame else {
+            panic!("expected a classical frame");
+        };
+        assert_eq!(frame.id, Id::Standard(0x123));
+        assert_eq!(
+            &frame.data[..usize::from(frame.len)],
+            [0xDE, 0xAD, 0xBE, 0xEF]
+        );
+        let LogFrame::Can(frame) = entries[1].frame else {
+            panic!("expected a classical frame");
+        };
+        assert_eq!(frame.id, Id::Extended(0x12345678));
+        let LogFrame::CanFd(frame) = entries[2].frame else {
+            panic!("expected a CAN FD frame");
+        };
+        assert_eq!(frame.len, 8);
+        assert_eq!(frame.flags & crate::CANFD_BRS_FLAG, crate::CANFD_BRS_FLAG);
+        assert_eq!(entries[2].interface, "can1");
+        let LogFrame::Can(frame) = entries[3].frame else {
+            panic!("expected a classical frame");
+        };
+        assert_eq!(frame.id, Id::Error(0x80));
+
+        let mut writer = LogWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write(entry).unwrap();
+        }
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), LOG);
+    }
+
+    #[test]
+    fn scale_timestamp_fraction() {
+        for (timestamp, expected) in [
+            ("(1.5)", Duration::new(1, 500_000_000)),
+            ("(1.249713)", Duration::new(1, 249_713_000)),
+            ("(1.000000001)", Duration::new(1, 1)),
+        ] {
+            let entry = format!("{timestamp} can0 123#00")
+                .parse::<LogEntry>()
+                .unwrap();
+            assert_eq!(entry.timestamp, expected, "{timestamp}");
+        }
+    }
+
+    #[test]
+    fn accept_fd_data_lengths() {
+        for len in [0, 8, 12, 16, 20, 24, 32, 48, 64] {
+            let entry = format!("(1.0) can0 123##0{}", "00".repeat(len))
+                .parse::<LogEntry>()
+                .unwrap();
+            let LogFrame::CanFd(frame) = entry.frame else {
+                panic!("expected a CAN FD frame");
+            };
+            assert_eq!(usize::from(frame.len), len);
+        }
+    }
+
+    #[test]
+    fn reject_malformed_lines() {
+        for line in [
+            "can0 123#00",
+            "(1.0) can0",
+            "(1.0) can0 1234#00",
+            "(1.0) can0 800#00",
+            "(1.0) can0 FFF#00",
+            "(1.0) can0 123##1000000000000000000",
+            "(1.0) can0 123##100000000000000000000",
+            "(1.0) can0 123##10000000000000000000000",
+            "(1.0) can0 123#0",
+            "(1.0) can0 123#R",
+            "(1.0) can0 123##",
+            "(1.) can0 123#00",
+            "(1.+5) can0 123#00",
+            "(1.0000000001) can0 123#00",
+        ] {
+            assert!(line.parse::<LogEntry>().is_err(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Decoding of the error frames generated by the CAN drivers
+//!
+//! Error frames are delivered like regular frames with an [`Id::Error`] identifier, but only to
+//! sockets that subscribed to them with [`FrameStreamBuilder::error_filter`]. The layout is
+//! described in `linux/can/error.h`.
+//!
+//! [`FrameStreamBuilder::error_filter`]: crate::stream::FrameStreamBuilder::error_filter
+
+use crate::{Frame, Id};
+
+/// TX timeout (by netdevice driver)
+pub const CAN_ERR_TX_TIMEOUT: u32 = 0x0000_0001;
+/// Lost arbitration, see `data[0]`
+pub const CAN_ERR_LOSTARB: u32 = 0x0000_0002;
+/// Controller problems, see `data[1]`
+pub const CAN_ERR_CRTL: u32 = 0x0000_0004;
+/// Protocol violations, see `data[2..4]`
+pub const CAN_ERR_PROT: u32 = 0x0000_0008;
+/// Transceiver status, see `data[4]`
+pub const CAN_ERR_TRX: u32 = 0x0000_0010;
+/// Received no ACK on transmission
+pub const CAN_ERR_ACK: u32 = 0x0000_0020;
+/// Bus off
+pub const CAN_ERR_BUSOFF: u32 = 0x0000_0040;
+/// Bus error (may flood!)
+pub const CAN_ERR_BUSERROR: u32 = 0x0000_0080;
+/// Controller restarted
+pub const CAN_ERR_RESTARTED: u32 = 0x0000_0100;
+/// TX and RX error counters are present, see `data[6..8]`
+pub const CAN_ERR_CNT: u32 = 0x0000_0200;
+/// All the error classes, for use with [`FrameStreamBuilder::error_filter`]
+///
+/// [`FrameStreamBuilder::error_filter`]: crate::stream::FrameStreamBuilder::error_filter
+pub const CAN_ERR_MASK_ALL: u32 = libc::CAN_ERR_MASK;
+
+/// A decoded error frame
+///
+/// A single error frame may report several error classes at once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorFrame {
+    pub tx_timeout: bool,
+    /// Arbitration was lost at the given bit, `0` if unspecified.
+    pub lost_arbitration: Option<u8>,
+    pub controller: Option<ControllerStatus>,
+    pub protocol: Option<ProtocolError>,
+    /// Transceiver status as defined by the `CAN_ERR_TRX_*` values of `linux/can/error.h`.
+    pub transceiver: Option<u8>,
+    pub no_ack: bool,
+    pub bus_off: bool,
+    pub bus_error: bool,
+    pub restarted: bool,
+    pub counters: Option<ErrorCounters>,
+}
+
+/// Status flags of the CAN controller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControllerStatus {
+    pub rx_overflow: bool,
+    pub tx_overflow: bool,
+    /// Reached the warning level for RX errors.
+    pub rx_warning: bool,
+    /// Reached the warning level for TX errors.
+    pub tx_warning: bool,
+    /// Reached the error passive status for RX errors.
+    pub rx_passive: bool,
+    /// Reached the error passive status for TX errors.
+    pub tx_passive: bool,
+    /// Recovered to the error active state.
+    pub active: bool,
+}
+
+/// A protocol violation detected on the bus
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProtocolError {
+    /// Single bit error.
+    pub bit: bool,
+    /// Frame format error.
+    pub form: bool,
+    /// Bit stuffing error.
+    pub stuff: bool,
+    /// Unable to send a dominant bit.
+    pub bit0: bool,
+    /// Unable to send a recessive bit.
+    pub bit1: bool,
+    /// Bus overload.
+    pub overload: bool,
+    /// Active error announcement.
+    pub active: bool,
+    /// The error occurred on transmission.
+    pub tx: bool,
+    /// Location of the error in the frame as defined by the `CAN_ERR_PROT_LOC_*` values of
+    /// `linux/can/error.h`.
+    pub location: u8,
+}
+
+/// TX and RX error counters of the CAN controller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCounters {
+    pub tx: u16,
+    pub rx: u16,
+}
+
+impl ErrorFrame {
+    /// Decodes an error frame. Returns `None` if the frame is not an error frame.
+    pub fn from_frame<const N: usize>(frame: &Frame<N>) -> Option<Self> {
+        let Id::Error(class) = frame.id else {
+            return None;
+        };
+        let mut data = [0; 8];
+        let len = N.min(data.len());
+        data[..len].copy_from_slice(&frame.data[..len]);
+        Some(Self::decode(class, data))
+    }
+
+    fn decode(class: u32, data: [u8; 8]) -> Self {
+        let has = |flag: u32| class & flag != 0;
+        let bit = |byte: u8, flag: u8| byte & flag != 0;
+        Self {
+            tx_timeout: has(CAN_ERR_TX_TIMEOUT),
+            lost_arbitration: has(CAN_ERR_LOSTARB).then_some(data[0]),
+            controller: has(CAN_ERR_CRTL).then_some(ControllerStatus {
+                rx_overflow: bit(data[1], 0x01),
+                tx_overflow: bit(data[1], 0x02),
+                rx_warning: bit(data[1], 0x04),
+                tx_warning: bit(data[1], 0x08),
+                rx_passive: bit(data[1], 0x10),
+                tx_passive: bit(data[1], 0x20),
+                active: bit(data[1], 0x40),
+            }),
+            protocol: has(CAN_ERR_PROT).then_some(ProtocolError {
+                bit: bit(data[2], 0x01),
+                form: bit(data[2], 0x02),
+                stuff: bit(data[2], 0x04),
+                bit0: bit(data[2], 0x08),
+                bit1: bit(data[2], 0x10),
+                overload: bit(data[2], 0x20),
+                active: bit(data[2], 0x40),
+                tx: bit(data[2], 0x80),
+                location: data[3],
+            }),
+            transceiver: has(CAN_ERR_TRX).then_some(data[4]),
+            no_ack: has(CAN_ERR_ACK),
+            bus_off: has(CAN_ERR_BUSOFF),
+            bus_error: has(CAN_ERR_BUSERROR),
+            restarted: has(CAN_ERR_RESTARTED),
+            counters: has(CAN_ERR_CNT).then_some(ErrorCounters {
+                tx: data[6].into(),
+                rx: data[7].into(),
+            }),
+        }
+    }
+
+    /// Whether the controller reported reaching the error passive status.
+    pub fn is_error_passive(&self) -> bool {
+        self.controller
+            .is_some_and(|status| status.rx_passive || status.tx_passive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CAN_DATA_LEN;
+
+    #[test]
+    fn decode_error_frames() {
+        let frame = Frame::<CAN_DATA_LEN> {
+            id: Id::from(libc::CAN_ERR_FLAG | CAN_ERR_CRTL | CAN_ERR_CNT),
+            len: 8,
+            flags: 0,
+            data: [0, 0x20, 0, 0, 0, 0, 128, 3],
+        };
+        let error = ErrorFrame::from_frame(&frame).unwrap();
+        assert!(error.is_error_passive());
+        assert!(!error.bus_off);
+        assert_eq!(error.counters, Some(ErrorCounters { tx: 128, rx: 3 }));
+
+        let frame = Frame::<CAN_DATA_LEN> {
+            id: Id::Error(CAN_ERR_BUSOFF | CAN_ERR_LOSTARB),
+            len: 8,
+            flags: 0,
+            data: [5, 0, 0, 0, 0, 0, 0, 0],
+        };
+        let error = ErrorFrame::from_frame(&frame).unwrap();
+        assert!(error.bus_off);
+        assert_eq!(error.lost_arbitration, Some(5));
+        assert_eq!(error.controller, None);
+
+        assert_eq!(
+            ErrorFrame::from_frame(&Frame::<CAN_DATA_LEN>::empty()),
+            None
+        );
+    }
+}
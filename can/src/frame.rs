@@ -33,6 +33,9 @@ impl<const N: usize> Frame<N> {
 pub enum Id {
     Standard(u32),
     Extended(u32),
+    /// Error frame generated by the CAN driver, the value holds the error class bits. See
+    /// [`ErrorFrame`](crate::error_frame::ErrorFrame).
+    Error(u32),
 }
 
 impl Ord for Id {
@@ -82,6 +85,9 @@ impl PartialOrd for Id {
 
 impl From<u32> for Id {
     fn from(id: u32) -> Id {
+        if id & libc::CAN_ERR_FLAG != 0 {
+            return Id::Error(id & libc::CAN_ERR_MASK);
+        }
         match id & libc::CAN_EFF_FLAG {
             0 => Id::Standard(id & libc::CAN_SFF_MASK),
             _ => Id::Extended(id & libc::CAN_EFF_MASK),
@@ -94,6 +100,7 @@ impl Id {
         match self {
             Id::Standard(id) => id & libc::CAN_SFF_MASK,
             Id::Extended(id) => (id & libc::CAN_EFF_MASK) | libc::CAN_EFF_FLAG,
+            Id::Error(class) => (class & libc::CAN_ERR_MASK) | libc::CAN_ERR_FLAG,
         }
     }
 
@@ -101,6 +108,7 @@ impl Id {
         match self {
             Id::Standard(id) => id & libc::CAN_SFF_MASK,
             Id::Extended(id) => id & libc::CAN_EFF_MASK,
+            Id::Error(class) => class & libc::CAN_ERR_MASK,
         }
     }
 }
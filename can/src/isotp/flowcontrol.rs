@@ -71,8 +71,8 @@ pub(crate) mod imp {
     #[repr(C)]
     #[derive(Default)]
     pub(crate) struct RawFlowControlOptions {
-        bs: u8,
-        stmin: u8,
+        pub(crate) bs: u8,
+        pub(crate) stmin: u8,
         wftmax: u8,
     }
 
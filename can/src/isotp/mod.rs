@@ -1,8 +1,11 @@
+use std::time::Duration;
+
 pub mod addr;
 pub mod flowcontrol;
 pub mod linklayer;
 pub mod socket_isotp;
 pub mod stream;
+pub mod userspace;
 
 /// Defined in the Kernel as SOL_CAN_BASE + CAN_ISOTP, which comes out to 106
 pub const SOL_CAN_ISOTP: libc::c_int = 106;
@@ -35,6 +38,51 @@ impl Default for IsotpOptions {
     }
 }
 
+impl IsotpOptions {
+    pub fn set_flag(&mut self, flag: IsotpFlags) -> &mut Self {
+        self.flags |= flag as u32;
+        self
+    }
+
+    /// Enables extended addressing, prefixing every frame with `address`. Frames are received
+    /// with the same prefix unless [`IsotpOptions::set_rx_extended_address`] is set.
+    pub fn set_extended_address(&mut self, address: u8) -> &mut Self {
+        self.extended_address = address;
+        self.set_flag(IsotpFlags::ExtendAddr)
+    }
+
+    /// Receives frames prefixed with `address` instead of the TX extended address.
+    pub fn set_rx_extended_address(&mut self, address: u8) -> &mut Self {
+        self.rx_extended_address = address;
+        self.set_flag(IsotpFlags::ExtendAddr)
+            .set_flag(IsotpFlags::RxExtendAddr)
+    }
+
+    /// Pads the transmitted frames to their full length with `content`.
+    pub fn set_tx_padding(&mut self, content: u8) -> &mut Self {
+        self.tx_padding_content = content;
+        self.set_flag(IsotpFlags::TxPadding)
+    }
+
+    /// Expects the received frames to be padded with `content`. The padding is only checked with
+    /// [`IsotpFlags::CheckPadLength`] and [`IsotpFlags::CheckPadData`].
+    pub fn set_rx_padding(&mut self, content: u8) -> &mut Self {
+        self.rx_padding_content = content;
+        self.set_flag(IsotpFlags::RxPadding)
+    }
+
+    /// Minimum time between two transmitted frames (`N_As`).
+    pub fn set_transmission_time(&mut self, time: Duration) -> &mut Self {
+        self.transmission_time_nano = time.as_nanos().min(u32::MAX.into()) as u32;
+        self
+    }
+
+    fn has_flag(&self, flag: IsotpFlags) -> bool {
+        self.flags & flag as u32 != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 #[repr(u32)]
 pub enum IsotpFlags {
     /// Disables sending of Flow Control frames
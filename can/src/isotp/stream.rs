@@ -8,9 +8,24 @@ use std::{
 
 use super::{
     addr::CanIsotpAddr, flowcontrol::FlowControlOptions, linklayer::LinkLayerOptions,
-    IsotpOptions,
+    userspace::UserIsotpStream, IsotpOptions,
 };
-use crate::{socket, Error, Protocol, Type, CANFD_DATA_LEN, CAN_DATA_LEN};
+use crate::{
+    socket,
+    stream::{self as frame_stream, FrameIo, FrameStream},
+    Error, Id, Protocol, Type, CANFD_DATA_LEN, CAN_DATA_LEN,
+};
+
+#[cfg(feature = "tokio")]
+mod async_stream;
+
+#[cfg(feature = "tokio")]
+pub use self::async_stream::AsyncIsotpStream;
+
+/// Upper bound on the size of a single ISO-TP message, matching the kernel's default
+/// `max_pdu_size`. Used as the receive buffer size of `AsyncIsotpStream`, and as the largest
+/// message accepted by [`UserIsotpStream`](super::userspace::UserIsotpStream).
+pub const MAX_MESSAGE_LEN: usize = 8300;
 
 pub struct IsotpStream<const N: usize> {
     pub(crate) fd: OwnedFd,
@@ -53,6 +68,27 @@ impl<const N: usize> IsotpStreamBuilder<N> {
         self.link_layer_opts = opts;
         self
     }
+
+    /// Runs ISO-TP in user space over `io` with the options of the builder, sending to `tx_id`
+    /// and receiving from `rx_id`. The `nonblocking` option is ignored.
+    pub fn bind_userspace_io<S: FrameIo<N>>(
+        &self,
+        io: S,
+        tx_id: Id,
+        rx_id: Id,
+    ) -> Result<UserIsotpStream<S, N>, Error>
+    where
+        [(); N]: frame_stream::AllowedToBind,
+    {
+        UserIsotpStream::with_options(
+            io,
+            tx_id,
+            rx_id,
+            self.isotp_opts,
+            self.flow_control_opts,
+            self.link_layer_opts,
+        )
+    }
 }
 
 impl IsotpStreamBuilder<CAN_DATA_LEN> {
@@ -65,6 +101,16 @@ impl IsotpStreamBuilder<CAN_DATA_LEN> {
             self.link_layer_opts,
         )
     }
+
+    /// Like [`IsotpStreamBuilder::bind`], but runs ISO-TP in user space over a raw
+    /// [`FrameStream`], for kernels without the `CAN_ISOTP` module.
+    pub fn bind_userspace(
+        &self,
+        addr: CanIsotpAddr,
+    ) -> Result<UserIsotpStream<FrameStream<CAN_DATA_LEN>, CAN_DATA_LEN>, Error> {
+        let io = FrameStream::<CAN_DATA_LEN>::new(addr.name.parse()?)?;
+        self.bind_userspace_io(io, addr.tx_id, addr.rx_id)
+    }
 }
 
 impl<const N: usize> Default for IsotpStreamBuilder<N> {
@@ -86,6 +132,17 @@ impl IsotpStreamBuilder<CANFD_DATA_LEN> {
             self.link_layer_opts,
         )
     }
+
+    /// Like [`IsotpStreamBuilder::bind`], but runs ISO-TP in user space over a raw
+    /// [`FrameStream`], for kernels without the `CAN_ISOTP` module.
+    pub fn bind_userspace(
+        &self,
+        addr: CanIsotpAddr,
+    ) -> Result<UserIsotpStream<FrameStream<CANFD_DATA_LEN>, CANFD_DATA_LEN>, Error>
+    {
+        let io = FrameStream::<CANFD_DATA_LEN>::new(addr.name.parse()?)?;
+        self.bind_userspace_io(io, addr.tx_id, addr.rx_id)
+    }
 }
 
 impl IsotpStream<CAN_DATA_LEN> {
@@ -128,17 +185,7 @@ impl<const N: usize> IsotpStream<N> {
 
 impl<const N: usize> Write for IsotpStream<N> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let ret = unsafe {
-            libc::write(
-                self.as_raw_fd(),
-                buf.as_ptr() as *const libc::c_void,
-                buf.len(),
-            )
-        };
-        if ret < 0 {
-            return Err(io::Error::last_os_error());
-        }
-        Ok(ret as usize)
+        imp::write(self, buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -148,17 +195,7 @@ impl<const N: usize> Write for IsotpStream<N> {
 
 impl<const N: usize> Read for IsotpStream<N> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let ret = unsafe {
-            libc::read(
-                self.as_raw_fd(),
-                buf.as_mut_ptr() as *mut libc::c_void,
-                buf.len(),
-            )
-        };
-        if ret < 0 {
-            return Err(io::Error::last_os_error());
-        }
-        Ok(ret as usize)
+        imp::read(self, buf)
     }
 }
 
@@ -175,7 +212,7 @@ impl<const N: usize> IntoRawFd for IsotpStream<N> {
 }
 
 mod imp {
-    use std::os::unix::prelude::AsRawFd;
+    use std::{io, os::unix::prelude::AsRawFd};
 
     use super::IsotpStream;
     use crate::{
@@ -241,4 +278,32 @@ mod imp {
         socket::bind(fd.as_raw_fd(), addr)?;
         Ok(())
     }
+
+    pub(super) fn write<T: AsRawFd>(fd: &T, buf: &[u8]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::write(
+                fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    pub(super) fn read<T: AsRawFd>(fd: &T, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::read(
+                fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
 }
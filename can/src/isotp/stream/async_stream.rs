@@ -0,0 +1,155 @@
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::{Sink, Stream};
+use tokio::io::unix::AsyncFd;
+
+use super::{imp, IsotpStream, MAX_MESSAGE_LEN};
+use crate::socket;
+
+/// An asynchronous [`IsotpStream`] driven by the Tokio reactor
+///
+/// The stream implements [`Stream`] for the received messages and [`Sink`] for the messages to
+/// send. The underlying socket is switched to non-blocking mode on creation.
+///
+/// Receiving is cancellation safe: the kernel reassembles the whole message and hands it over in
+/// a single `read(2)` call, so dropping a pending [`recv`] future or [`Stream::poll_next`] never
+/// loses a message.
+///
+/// [`recv`]: AsyncIsotpStream::recv
+pub struct AsyncIsotpStream<const N: usize> {
+    inner: AsyncFd<IsotpStream<N>>,
+    pending: Option<Vec<u8>>,
+}
+
+impl<const N: usize> IsotpStream<N> {
+    /// Converts the stream into an [`AsyncIsotpStream`]. Must be called inside a Tokio runtime.
+    pub fn into_async(self) -> io::Result<AsyncIsotpStream<N>> {
+        AsyncIsotpStream::new(self)
+    }
+}
+
+impl<const N: usize> AsyncIsotpStream<N> {
+    /// Registers the stream with the Tokio reactor. Must be called inside a Tokio runtime.
+    pub fn new(stream: IsotpStream<N>) -> io::Result<Self> {
+        socket::set_nonblocking(&stream, true).map_err(io::Error::other)?;
+        Ok(Self {
+            inner: AsyncFd::new(stream)?,
+            pending: None,
+        })
+    }
+
+    /// Returns a reference to the underlying [`IsotpStream`].
+    pub fn get_ref(&self) -> &IsotpStream<N> {
+        self.inner.get_ref()
+    }
+
+    /// Deregisters the stream from the Tokio reactor. The socket is left in non-blocking mode.
+    pub fn into_inner(self) -> IsotpStream<N> {
+        self.inner.into_inner()
+    }
+
+    /// Waits for the next message and copies it into `buf`, returning its length. A message
+    /// longer than `buf` is truncated. Cancellation safe.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            if let Ok(result) = guard.try_io(|stream| imp::read(stream, buf)) {
+                return result;
+            }
+        }
+    }
+
+    /// Waits until the socket is ready and sends `buf` as a single message.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.inner.writable().await?;
+            if let Ok(result) = guard.try_io(|stream| imp::write(stream, buf)) {
+                return result;
+            }
+        }
+    }
+
+    /// Polls for the next message like [`recv`](Self::recv), registering the waker of `cx` if
+    /// there is none yet.
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+            if let Ok(result) = guard.try_io(|stream| imp::read(stream, buf)) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+
+    fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(message) = &self.pending {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+            if let Ok(result) = guard.try_io(|stream| imp::write(stream, message)) {
+                self.pending = None;
+                result?;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<const N: usize> Stream for AsyncIsotpStream<N> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        loop {
+            let mut guard = match ready!(self.inner.poll_read_ready(cx)) {
+                Ok(guard) => guard,
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            };
+            // Allocated only once the socket is readable.
+            let mut buf = vec![0; MAX_MESSAGE_LEN];
+            if let Ok(result) = guard.try_io(|stream| imp::read(stream, &mut buf)) {
+                return Poll::Ready(Some(result.map(|len| {
+                    buf.truncate(len);
+                    buf
+                })));
+            }
+        }
+    }
+}
+
+impl<const N: usize> Sink<Vec<u8>> for AsyncIsotpStream<N> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> io::Result<()> {
+        let this = self.get_mut();
+        assert!(this.pending.is_none(), "`poll_ready` must be called first");
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+}
+
+impl<const N: usize> AsRawFd for AsyncIsotpStream<N> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
@@ -0,0 +1,678 @@
+//! ISO-TP (ISO 15765-2) segmentation and reassembly in user space
+//!
+//! [`UserIsotpStream`] runs the transport protocol on top of any [`FrameIo`], such as a
+//! [`FrameStream`](crate::stream::FrameStream) or a [`VirtualSocket`], without requiring the
+//! `CAN_ISOTP` kernel module. Only one transfer can be in flight at a time, in either direction.
+//!
+//! Streams with custom options are created with [`IsotpStreamBuilder::bind_userspace`] and
+//! [`IsotpStreamBuilder::bind_userspace_io`], the user-space counterparts of
+//! [`IsotpStreamBuilder::bind`].
+//!
+//! [`IsotpStreamBuilder::bind_userspace`]: super::stream::IsotpStreamBuilder::bind_userspace
+//! [`IsotpStreamBuilder::bind_userspace_io`]: super::stream::IsotpStreamBuilder::bind_userspace_io
+//! [`IsotpStreamBuilder::bind`]: super::stream::IsotpStreamBuilder::bind
+//! [`VirtualSocket`]: crate::vbus::VirtualSocket
+//!
+//! # Examples
+//!
+//! ```
+//! use std::thread;
+//!
+//! use can_rs::{isotp::userspace::UserIsotpStream, vbus::VirtualBus, Id, CAN_DATA_LEN};
+//!
+//! let bus = VirtualBus::<CAN_DATA_LEN>::new();
+//! let tester =
+//!     UserIsotpStream::new(bus.open(), Id::Standard(0x7E0), Id::Standard(0x7E8)).unwrap();
+//! let ecu =
+//!     UserIsotpStream::new(bus.open(), Id::Standard(0x7E8), Id::Standard(0x7E0)).unwrap();
+//!
+//! let ecu = thread::spawn(move || ecu.recv().unwrap());
+//! tester.send(&[0x42; 100]).unwrap();
+//! assert_eq!(ecu.join().unwrap(), [0x42; 100]);
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    flowcontrol::{imp::RawFlowControlOptions, FlowControlOptions},
+    linklayer::LinkLayerOptions,
+    stream::MAX_MESSAGE_LEN,
+    IsotpFlags, IsotpOptions,
+};
+use crate::{
+    filter::Filter,
+    frame::{convert_dlc_to_len, convert_len_to_dlc, Length},
+    stream::{AllowedToBind, FrameIo},
+    Error, Frame, Id, CANFD_DATA_LEN, CAN_DATA_LEN,
+};
+
+const SINGLE_FRAME: u8 = 0x0;
+const FIRST_FRAME: u8 = 0x1;
+const CONSECUTIVE_FRAME: u8 = 0x2;
+const FLOW_CONTROL: u8 = 0x3;
+
+const FLOW_STATUS_CONTINUE: u8 = 0x0;
+const FLOW_STATUS_WAIT: u8 = 0x1;
+const FLOW_STATUS_OVERFLOW: u8 = 0x2;
+
+/// Time to wait for a flow control frame after sending a first frame or a block (`N_Bs`).
+const FLOW_CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time to wait for the next consecutive frame (`N_Cr`).
+const CONSECUTIVE_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest first frame data length that fits the 12 bits length field.
+const FIRST_FRAME_MAX_SHORT_LEN: usize = 0xFFF;
+
+/// An ISO-TP connection between `tx_id` and `rx_id`, running in user space
+///
+/// The [`IsotpOptions`], [`FlowControlOptions`] and [`LinkLayerOptions`] behave like for the
+/// kernel implementation, with the following exceptions:
+///
+/// + [`IsotpFlags::HalfDuplex`] has no effect, transfers are always half duplex.
+/// + [`IsotpFlags::ForceTxSeparationTimeMin`] ignores the `STmin` of received flow control frames
+///   and only waits for the transmission time between frames.
+/// + [`IsotpFlags::ForceRxSeparationTimeMin`] and the flow control `wftmax` have no effect, as
+///   no flow control wait frames are ever sent.
+pub struct UserIsotpStream<S, const N: usize> {
+    io: S,
+    tx_id: Id,
+    rx_id: Id,
+    isotp_opts: IsotpOptions,
+    flow_control_opts: FlowControlOptions,
+    link_layer_opts: LinkLayerOptions<N>,
+}
+
+impl<S: FrameIo<N>, const N: usize> UserIsotpStream<S, N>
+where
+    [(); N]: AllowedToBind,
+{
+    /// Sends to `tx_id` and receives from `rx_id` with the default options. The filters of `io`
+    /// are replaced to only receive `rx_id`.
+    ///
+    /// Use [`IsotpStreamBuilder::bind_userspace_io`] to set the options.
+    ///
+    /// [`IsotpStreamBuilder::bind_userspace_io`]:
+    ///     super::stream::IsotpStreamBuilder::bind_userspace_io
+    pub fn new(io: S, tx_id: Id, rx_id: Id) -> Result<Self, Error> {
+        Self::with_options(
+            io,
+            tx_id,
+            rx_id,
+            IsotpOptions::default(),
+            FlowControlOptions::default(),
+            LinkLayerOptions::default(),
+        )
+    }
+}
+
+impl<S: FrameIo<N>, const N: usize> UserIsotpStream<S, N> {
+    pub(crate) fn with_options(
+        io: S,
+        tx_id: Id,
+        rx_id: Id,
+        isotp_opts: IsotpOptions,
+        flow_control_opts: FlowControlOptions,
+        link_layer_opts: LinkLayerOptions<N>,
+    ) -> Result<Self, Error> {
+        io.set_filters(&[Filter {
+            id: rx_id,
+            mask: libc::CAN_EFF_FLAG | libc::CAN_EFF_MASK,
+        }])?;
+        Ok(Self {
+            io,
+            tx_id,
+            rx_id,
+            isotp_opts,
+            flow_control_opts,
+            link_layer_opts,
+        })
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.io
+    }
+
+    pub fn into_inner(self) -> S {
+        self.io
+    }
+
+    pub fn tx_id(&self) -> Id {
+        self.tx_id
+    }
+
+    pub fn rx_id(&self) -> Id {
+        self.rx_id
+    }
+
+    /// Sends a message, segmenting it as needed. Blocks until the last frame is sent.
+    pub fn send(&self, data: &[u8]) -> io::Result<()> {
+        // Room left in a frame by the extended address.
+        let frame_len = N - usize::from(self.tx_address().is_some());
+        if data.len() <= single_frame_max_len(frame_len) {
+            let mut payload = Vec::with_capacity(data.len() + 2);
+            if data.len() < CAN_DATA_LEN - (N - frame_len) {
+                payload.push(SINGLE_FRAME << 4 | data.len() as u8);
+            } else {
+                payload.extend([SINGLE_FRAME << 4, data.len() as u8]);
+            }
+            payload.extend_from_slice(data);
+            return self.io.transmit(&self.frame(&payload));
+        }
+
+        let len = u32::try_from(data.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ISO-TP messages are limited to 4GiB",
+            )
+        })?;
+        let mut payload = Vec::with_capacity(N);
+        if data.len() <= FIRST_FRAME_MAX_SHORT_LEN {
+            payload.extend([FIRST_FRAME << 4 | (len >> 8) as u8, len as u8]);
+        } else {
+            payload.extend([FIRST_FRAME << 4, 0]);
+            payload.extend(len.to_be_bytes());
+        }
+        let mut offset = frame_len - payload.len();
+        payload.extend_from_slice(&data[..offset]);
+        self.io.transmit(&self.frame(&payload))?;
+
+        let transmission_time =
+            Duration::from_nanos(self.isotp_opts.transmission_time_nano.into());
+        let mut sequence_number = 1u8;
+        loop {
+            let (block_size, separation_time) = self.wait_flow_control()?;
+            let gap = if self
+                .isotp_opts
+                .has_flag(IsotpFlags::ForceTxSeparationTimeMin)
+            {
+                transmission_time
+            } else {
+                transmission_time.max(separation_time)
+            };
+            let mut sent = 0usize;
+            while offset < data.len()
+                && (block_size == 0 || sent < usize::from(block_size))
+            {
+                if sent > 0 && !gap.is_zero() {
+                    thread::sleep(gap);
+                }
+                let end = data.len().min(offset + frame_len - 1);
+                payload.clear();
+                payload.push(CONSECUTIVE_FRAME << 4 | sequence_number);
+                payload.extend_from_slice(&data[offset..end]);
+                self.io.transmit(&self.frame(&payload))?;
+                offset = end;
+                sequence_number = (sequence_number + 1) & 0x0F;
+                sent += 1;
+            }
+            if offset == data.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Blocks until a complete message is received.
+    ///
+    /// Frames that don't start a message are skipped. Fails with [`io::ErrorKind::TimedOut`] if
+    /// the sender stalls in the middle of a message, and with [`io::ErrorKind::InvalidData`] on
+    /// a sequence number mismatch, invalid padding, or a message longer than
+    /// [`MAX_MESSAGE_LEN`], which the sender is told about with an overflow flow control frame.
+    pub fn recv(&self) -> io::Result<Vec<u8>> {
+        loop {
+            let frame = self.io.receive(None)?;
+            let Some(data) = self.frame_data(&frame) else {
+                continue;
+            };
+            match data.first().map(|byte| byte >> 4) {
+                Some(SINGLE_FRAME) => {
+                    let (len, start) = match data[0] & 0x0F {
+                        0 if frame.len as usize > CAN_DATA_LEN => {
+                            (usize::from(data[1]), 2)
+                        }
+                        len => (usize::from(len), 1),
+                    };
+                    if len > 0 && start + len <= data.len() {
+                        self.check_padding(&frame, &data[start + len..])?;
+                        return Ok(data[start..start + len].to_vec());
+                    }
+                }
+                Some(FIRST_FRAME) if data.len() >= 2 => {
+                    let len = usize::from(data[0] & 0x0F) << 8 | usize::from(data[1]);
+                    let (len, start) = match len {
+                        0 if data.len() >= 6 => (
+                            u32::from_be_bytes([data[2], data[3], data[4], data[5]])
+                                as usize,
+                            6,
+                        ),
+                        0 => continue,
+                        len => (len, 2),
+                    };
+                    return self.recv_segmented(len, &data[start..]);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn recv_segmented(&self, len: usize, first: &[u8]) -> io::Result<Vec<u8>> {
+        let flow_control = RawFlowControlOptions::from(self.flow_control_opts);
+        if len > MAX_MESSAGE_LEN {
+            self.send_flow_control(FLOW_STATUS_OVERFLOW, &flow_control)?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "ISO-TP message of {len} bytes exceeds {MAX_MESSAGE_LEN} bytes"
+                ),
+            ));
+        }
+        let mut message = Vec::with_capacity(len);
+        message.extend_from_slice(&first[..first.len().min(len)]);
+        self.send_flow_control(FLOW_STATUS_CONTINUE, &flow_control)?;
+
+        let mut sequence_number = 1u8;
+        let mut received = 0usize;
+        while message.len() < len {
+            if flow_control.bs != 0 && received == usize::from(flow_control.bs) {
+                self.send_flow_control(FLOW_STATUS_CONTINUE, &flow_control)?;
+                received = 0;
+            }
+            let deadline = Instant::now() + CONSECUTIVE_FRAME_TIMEOUT;
+            let frame = loop {
+                let frame = self.receive_until(deadline)?;
+                if self
+                    .frame_data(&frame)
+                    .and_then(|data| data.first())
+                    .is_some_and(|byte| byte >> 4 == CONSECUTIVE_FRAME)
+                {
+                    break frame;
+                }
+            };
+            let data = self.frame_data(&frame).unwrap_or_default();
+            if data[0] & 0x0F != sequence_number {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "expected ISO-TP sequence number {sequence_number}, got {}",
+                        data[0] & 0x0F
+                    ),
+                ));
+            }
+            let end = data.len().min(len - message.len() + 1);
+            self.check_padding(&frame, &data[end..])?;
+            message.extend_from_slice(&data[1..end]);
+            sequence_number = (sequence_number + 1) & 0x0F;
+            received += 1;
+        }
+        Ok(message)
+    }
+
+    /// Waits for a flow control frame allowing to continue, and returns its block size and
+    /// minimum separation time.
+    fn wait_flow_control(&self) -> io::Result<(u8, Duration)> {
+        let mut deadline = Instant::now() + FLOW_CONTROL_TIMEOUT;
+        loop {
+            let frame = self.receive_until(deadline)?;
+            let Some(data) = self.frame_data(&frame) else {
+                continue;
+            };
+            if data.len() < 3 || data[0] >> 4 != FLOW_CONTROL {
+                continue;
+            }
+            match data[0] & 0x0F {
+                FLOW_STATUS_CONTINUE => {
+                    return Ok((data[1], separation_time(data[2])));
+                }
+                FLOW_STATUS_WAIT => deadline = Instant::now() + FLOW_CONTROL_TIMEOUT,
+                FLOW_STATUS_OVERFLOW => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::OutOfMemory,
+                        "ISO-TP receiver reported a buffer overflow",
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends a flow control frame with the given status, unless in listen mode.
+    fn send_flow_control(
+        &self,
+        status: u8,
+        options: &RawFlowControlOptions,
+    ) -> io::Result<()> {
+        if self.isotp_opts.has_flag(IsotpFlags::ListenMode) {
+            return Ok(());
+        }
+        self.io.transmit(&self.frame(&[
+            FLOW_CONTROL << 4 | status,
+            options.bs,
+            options.stmin,
+        ]))
+    }
+
+    fn receive_until(&self, deadline: Instant) -> io::Result<Frame<N>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for an ISO-TP frame",
+            ));
+        }
+        self.io.receive(Some(timeout))
+    }
+
+    fn tx_address(&self) -> Option<u8> {
+        self.isotp_opts
+            .has_flag(IsotpFlags::ExtendAddr)
+            .then_some(self.isotp_opts.extended_address)
+    }
+
+    fn rx_address(&self) -> Option<u8> {
+        if self.isotp_opts.has_flag(IsotpFlags::RxExtendAddr) {
+            Some(self.isotp_opts.rx_extended_address)
+        } else {
+            self.tx_address()
+        }
+    }
+
+    /// Returns the data of a received frame following the extended address, or `None` if the
+    /// frame is addressed to another node.
+    fn frame_data<'a>(&self, frame: &'a Frame<N>) -> Option<&'a [u8]> {
+        let data = &frame.data[..usize::from(frame.len).min(N)];
+        match self.rx_address() {
+            Some(address) => data
+                .split_first()
+                .and_then(|(&first, data)| (first == address).then_some(data)),
+            None => Some(data),
+        }
+    }
+
+    /// Checks the padding following the protocol data of a received frame.
+    fn check_padding(&self, frame: &Frame<N>, padding: &[u8]) -> io::Result<()> {
+        let options = &self.isotp_opts;
+        if !options.has_flag(IsotpFlags::RxPadding) {
+            return Ok(());
+        }
+        if options.has_flag(IsotpFlags::CheckPadLength)
+            && usize::from(frame.len) < CAN_DATA_LEN
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "received an unpadded ISO-TP frame",
+            ));
+        }
+        if options.has_flag(IsotpFlags::CheckPadData)
+            && padding
+                .iter()
+                .any(|&byte| byte != options.rx_padding_content)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "received an ISO-TP frame with invalid padding",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builds a frame to `tx_id`, prefixed with the extended address. Frames are padded to
+    /// their full length with [`IsotpFlags::TxPadding`], CAN FD frames are always padded up to
+    /// the next valid data length.
+    fn frame(&self, payload: &[u8]) -> Frame<N> {
+        let mut frame = Frame::empty();
+        frame.id = self.tx_id;
+        let mut len = 0;
+        if let Some(address) = self.tx_address() {
+            frame.data[0] = address;
+            len = 1;
+        }
+        frame.data[len..len + payload.len()].copy_from_slice(payload);
+        len += payload.len();
+        let padded_len = if self.isotp_opts.has_flag(IsotpFlags::TxPadding) {
+            len.max(CAN_DATA_LEN)
+        } else {
+            len
+        };
+        let padded_len = if padded_len > CAN_DATA_LEN {
+            u8::from(convert_dlc_to_len(convert_len_to_dlc(Length::Bytes(
+                padded_len as u8,
+            ))))
+        } else {
+            padded_len as u8
+        };
+        frame.data[len..usize::from(padded_len)]
+            .fill(self.isotp_opts.tx_padding_content);
+        frame.len = padded_len;
+        if N == CANFD_DATA_LEN {
+            frame.flags = self.link_layer_opts.flags;
+        }
+        frame
+    }
+}
+
+impl<S: FrameIo<N>, const N: usize> Read for UserIsotpStream<S, N> {
+    /// Reads the next message. It is truncated if it doesn't fit `buf`.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let message = self.recv()?;
+        let len = message.len().min(buf.len());
+        buf[..len].copy_from_slice(&message[..len]);
+        Ok(len)
+    }
+}
+
+impl<S: FrameIo<N>, const N: usize> Write for UserIsotpStream<S, N> {
+    /// Sends `buf` as a single message.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf).map(|()| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Largest message sent in a single frame of `frame_len` bytes. CAN FD frames use the escaped
+/// length field for messages that don't fit a classical frame.
+fn single_frame_max_len(frame_len: usize) -> usize {
+    if frame_len <= CAN_DATA_LEN {
+        frame_len - 1
+    } else {
+        frame_len - 2
+    }
+}
+
+/// Decodes the `STmin` parameter of a flow control frame. Reserved values are treated as the
+/// maximum of 127ms, as mandated by the standard.
+fn separation_time(stmin: u8) -> Duration {
+    match stmin {
+        0x00..=0x7F => Duration::from_millis(stmin.into()),
+        0xF1..=0xF9 => Duration::from_micros(u64::from(stmin - 0xF0) * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        isotp::{flowcontrol::Blocksize, stream::IsotpStreamBuilder},
+        vbus::VirtualBus,
+    };
+
+    fn pair<const N: usize>(
+        bus: &VirtualBus<N>,
+    ) -> (
+        UserIsotpStream<crate::vbus::VirtualSocket<N>, N>,
+        UserIsotpStream<crate::vbus::VirtualSocket<N>, N>,
+    )
+    where
+        [(); N]: AllowedToBind,
+    {
+        let a =
+            UserIsotpStream::new(bus.open(), Id::Standard(0x7E0), Id::Standard(0x7E8))
+                .unwrap();
+        let b =
+            UserIsotpStream::new(bus.open(), Id::Standard(0x7E8), Id::Standard(0x7E0))
+                .unwrap();
+        (a, b)
+    }
+
+    fn roundtrip<const N: usize>(len: usize)
+    where
+        [(); N]: AllowedToBind,
+    {
+        let bus = VirtualBus::<N>::new();
+        let (a, b) = pair(&bus);
+        let message = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+        let receiver = thread::spawn(move || b.recv().unwrap());
+        a.send(&message).unwrap();
+        assert_eq!(receiver.join().unwrap(), message, "{len} bytes over {N}");
+    }
+
+    #[test]
+    fn roundtrip_messages() {
+        for len in [1, 7, 8, 62, 63, 4095, 4096, MAX_MESSAGE_LEN] {
+            roundtrip::<CAN_DATA_LEN>(len);
+            roundtrip::<CANFD_DATA_LEN>(len);
+        }
+    }
+
+    #[test]
+    fn segment_frames() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let (a, _b) = pair(&bus);
+        let monitor = bus.open();
+        let receiver = thread::spawn(move || {
+            let first = monitor.receive(None).unwrap();
+            // Let the sender proceed.
+            monitor
+                .transmit(&Frame {
+                    id: Id::Standard(0x7E8),
+                    len: 3,
+                    flags: 0,
+                    data: [0x30, 0, 0, 0, 0, 0, 0, 0],
+                })
+                .unwrap();
+            let second = monitor.receive(None).unwrap();
+            let third = monitor.receive(None).unwrap();
+            (first, second, third)
+        });
+        // `_b` is not receiving, so flow control comes from the monitor.
+        a.send(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])
+            .unwrap();
+        let (first, second, third) = receiver.join().unwrap();
+        assert_eq!(first.data, [0x10, 16, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(second.data, [0x21, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(&third.data[..usize::from(third.len)], [0x22, 14, 15, 16]);
+    }
+
+    #[test]
+    fn time_out_without_flow_control() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let (a, _b) = pair(&bus);
+        let err = a.send(&[0; 20]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn honor_options() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let mut options = IsotpOptions::default();
+        options.set_extended_address(0xF1).set_tx_padding(0xAA);
+        let a = IsotpStreamBuilder::<CAN_DATA_LEN>::new()
+            .isotp_opts(options)
+            .bind_userspace_io(bus.open(), Id::Standard(0x7E0), Id::Standard(0x7E8))
+            .unwrap();
+        let mut options = IsotpOptions::default();
+        options
+            .set_extended_address(0xF1)
+            .set_rx_padding(0xAA)
+            .set_flag(IsotpFlags::CheckPadLength)
+            .set_flag(IsotpFlags::CheckPadData);
+        let b = IsotpStreamBuilder::<CAN_DATA_LEN>::new()
+            .isotp_opts(options)
+            .flow_control_opts(FlowControlOptions {
+                block_size: Blocksize::Limited(2),
+                ..FlowControlOptions::default()
+            })
+            .bind_userspace_io(bus.open(), Id::Standard(0x7E8), Id::Standard(0x7E0))
+            .unwrap();
+        let monitor = bus.open();
+
+        let message = (0..40).collect::<Vec<u8>>();
+        let receiver = thread::spawn(move || b.recv().unwrap());
+        a.send(&message).unwrap();
+        assert_eq!(receiver.join().unwrap(), message);
+
+        // FF, then 6 CFs in blocks of 2, each preceded by a FC.
+        let frames = std::iter::from_fn(|| monitor.try_receive()).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 10);
+        assert!(frames.iter().all(|frame| frame.data[0] == 0xF1));
+        // Only the sender pads its frames.
+        let (flow_control, data): (Vec<&Frame<CAN_DATA_LEN>>, Vec<_>) = frames
+            .iter()
+            .partition(|frame| frame.id == Id::Standard(0x7E8));
+        assert_eq!(flow_control.len(), 3);
+        assert!(flow_control
+            .iter()
+            .all(|frame| frame.len == 4 && frame.data[..4] == [0xF1, 0x30, 2, 0]));
+        assert!(data.iter().all(|frame| frame.len == 8));
+        assert_eq!(frames[9].data, [0xF1, 0x26, 35, 36, 37, 38, 39, 0xAA]);
+    }
+
+    #[test]
+    fn reject_oversized_message() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let (a, b) = pair(&bus);
+        let receiver = thread::spawn(move || b.recv().unwrap_err());
+        let err = a.send(&vec![0; MAX_MESSAGE_LEN + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+        assert_eq!(receiver.join().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reject_bad_padding() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let a =
+            UserIsotpStream::new(bus.open(), Id::Standard(0x7E0), Id::Standard(0x7E8))
+                .unwrap();
+        let mut options = IsotpOptions::default();
+        options
+            .set_rx_padding(0xAA)
+            .set_flag(IsotpFlags::CheckPadLength);
+        let b = IsotpStreamBuilder::<CAN_DATA_LEN>::new()
+            .isotp_opts(options)
+            .bind_userspace_io(bus.open(), Id::Standard(0x7E8), Id::Standard(0x7E0))
+            .unwrap();
+        a.send(&[1, 2, 3]).unwrap();
+        assert_eq!(b.recv().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn listen_to_transfer() {
+        let bus = VirtualBus::<CANFD_DATA_LEN>::new();
+        let (a, b) = pair(&bus);
+        let mut options = IsotpOptions::default();
+        options.set_flag(IsotpFlags::ListenMode);
+        let listener = IsotpStreamBuilder::<CANFD_DATA_LEN>::new()
+            .isotp_opts(options)
+            .bind_userspace_io(bus.open(), Id::Standard(0x7E8), Id::Standard(0x7E0))
+            .unwrap();
+        let message = vec![0x5A; 300];
+        let receiver = thread::spawn(move || b.recv().unwrap());
+        let listener = thread::spawn(move || listener.recv().unwrap());
+        a.send(&message).unwrap();
+        assert_eq!(receiver.join().unwrap(), message);
+        assert_eq!(listener.join().unwrap(), message);
+    }
+
+    #[test]
+    fn decode_separation_time() {
+        assert_eq!(separation_time(0x14), Duration::from_millis(20));
+        assert_eq!(separation_time(0xF3), Duration::from_micros(300));
+        assert_eq!(separation_time(0x80), Duration::from_millis(127));
+    }
+}
@@ -0,0 +1,542 @@
+//! SAE J1939 sockets (`CAN_J1939`)
+//!
+//! The kernel takes care of the J1939 transport protocols, so messages of up to 1785 bytes (and
+//! more with the extended transport protocol) are sent and received in one piece. Nodes are
+//! addressed either by their 8 bits address or by their 64 bits [`Name`], which the kernel maps
+//! to addresses by tracking the address claims on the bus.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use can_rs::j1939::{J1939Addr, J1939Socket, Name, NameFields, Pgn};
+//!
+//! let name = Name::from(NameFields {
+//!     identity_number: 0x1234,
+//!     manufacturer_code: 0x7FF,
+//!     arbitrary_address_capable: false,
+//!     ..NameFields::default()
+//! });
+//! let socket = J1939Socket::bind(
+//!     "can0".parse().unwrap(),
+//!     J1939Addr {
+//!         name: Some(name),
+//!         pgn: None,
+//!         addr: Some(0x80),
+//!     },
+//! )
+//! .expect("failed to bind can0 J1939 socket");
+//! socket.claim_address().unwrap();
+//!
+//! // Proprietary B message to every node.
+//! socket.set_broadcast(true).unwrap();
+//! socket
+//!     .send_to(
+//!         &[1, 2, 3],
+//!         &J1939Addr {
+//!             pgn: Some(Pgn(0xFF00)),
+//!             ..J1939Addr::default()
+//!         },
+//!     )
+//!     .unwrap();
+//! ```
+
+use std::{
+    io, mem,
+    os::{
+        fd::OwnedFd,
+        unix::io::{AsRawFd, IntoRawFd, RawFd},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    addr::{CanAddr, AF_CAN},
+    socket, stream, Error, Protocol, Type,
+};
+
+/// Address of a node that failed to claim an address (`J1939_IDLE_ADDR`)
+pub const IDLE_ADDR: u8 = 0xFE;
+/// Largest address of a single node (`J1939_MAX_UNICAST_ADDR`)
+pub const MAX_UNICAST_ADDR: u8 = 0xFD;
+/// Maximum number of filters of a socket (`J1939_FILTER_MAX`)
+pub const FILTER_MAX: usize = 512;
+
+const NO_ADDR: u8 = 0xFF;
+const NO_NAME: u64 = 0;
+const NO_PGN: u32 = 0x40000;
+
+/// Time to wait for competing claims after claiming an address.
+const ADDRESS_CLAIM_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// The 64 bits NAME uniquely identifying a node (SAE J1939-81)
+///
+/// When two nodes claim the same address, the one with the lowest NAME wins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Name(pub u64);
+
+/// The fields of a [`Name`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NameFields {
+    /// 21 bits serial number.
+    pub identity_number: u32,
+    /// 11 bits code assigned by SAE.
+    pub manufacturer_code: u16,
+    /// 3 bits.
+    pub ecu_instance: u8,
+    /// 5 bits.
+    pub function_instance: u8,
+    pub function: u8,
+    /// 7 bits.
+    pub vehicle_system: u8,
+    /// 4 bits.
+    pub vehicle_system_instance: u8,
+    /// 3 bits.
+    pub industry_group: u8,
+    /// The node can pick another address when it loses an address claim.
+    pub arbitrary_address_capable: bool,
+}
+
+impl From<NameFields> for Name {
+    /// Fields are truncated to their width.
+    fn from(fields: NameFields) -> Self {
+        let field =
+            |value: u64, shift: u32, bits: u32| (value & ((1 << bits) - 1)) << shift;
+        Self(
+            field(fields.identity_number.into(), 0, 21)
+                | field(fields.manufacturer_code.into(), 21, 11)
+                | field(fields.ecu_instance.into(), 32, 3)
+                | field(fields.function_instance.into(), 35, 5)
+                | field(fields.function.into(), 40, 8)
+                | field(fields.vehicle_system.into(), 49, 7)
+                | field(fields.vehicle_system_instance.into(), 56, 4)
+                | field(fields.industry_group.into(), 60, 3)
+                | field(fields.arbitrary_address_capable.into(), 63, 1),
+        )
+    }
+}
+
+impl Name {
+    pub fn fields(&self) -> NameFields {
+        let field = |shift: u32, bits: u32| (self.0 >> shift) & ((1 << bits) - 1);
+        NameFields {
+            identity_number: field(0, 21) as u32,
+            manufacturer_code: field(21, 11) as u16,
+            ecu_instance: field(32, 3) as u8,
+            function_instance: field(35, 5) as u8,
+            function: field(40, 8) as u8,
+            vehicle_system: field(49, 7) as u8,
+            vehicle_system_instance: field(56, 4) as u8,
+            industry_group: field(60, 3) as u8,
+            arbitrary_address_capable: field(63, 1) != 0,
+        }
+    }
+}
+
+/// An 18 bits Parameter Group Number
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Pgn(pub u32);
+
+impl Pgn {
+    /// Request PGN (`J1939_PGN_REQUEST`)
+    pub const REQUEST: Pgn = Pgn(0x0EA00);
+    /// Address claimed PGN (`J1939_PGN_ADDRESS_CLAIMED`)
+    pub const ADDRESS_CLAIMED: Pgn = Pgn(0x0EE00);
+    /// Commanded address PGN (`J1939_PGN_ADDRESS_COMMANDED`)
+    pub const ADDRESS_COMMANDED: Pgn = Pgn(0x0FED8);
+    /// Largest PGN (`J1939_PGN_MAX`)
+    pub const MAX: Pgn = Pgn(0x3FFFF);
+
+    pub fn pdu_format(&self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    /// PDU1 messages are sent to a specific node, PDU2 messages are always broadcast.
+    pub fn is_pdu1(&self) -> bool {
+        self.pdu_format() < 0xF0
+    }
+}
+
+/// Address of a J1939 socket, or of the peer of a message
+///
+/// A `None` field is not taken into account. Bound to a socket, a `None` address only allows to
+/// receive broadcasts and messages addressed by NAME, and a `None` PGN receives every PGN. As a
+/// destination, a `None` address broadcasts the message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct J1939Addr {
+    pub name: Option<Name>,
+    pub pgn: Option<Pgn>,
+    pub addr: Option<u8>,
+}
+
+impl J1939Addr {
+    fn to_raw(self, ifindex: libc::c_int) -> RawJ1939Addr {
+        RawJ1939Addr {
+            family: AF_CAN,
+            ifindex,
+            name: self.name.map_or(NO_NAME, |name| name.0),
+            pgn: self.pgn.map_or(NO_PGN, |pgn| pgn.0),
+            addr: self.addr.unwrap_or(NO_ADDR),
+        }
+    }
+
+    fn from_raw(raw: &RawJ1939Addr) -> Self {
+        Self {
+            name: (raw.name != NO_NAME).then_some(Name(raw.name)),
+            pgn: (raw.pgn & NO_PGN == 0).then_some(Pgn(raw.pgn)),
+            addr: (raw.addr != NO_ADDR).then_some(raw.addr),
+        }
+    }
+}
+
+/// Receive filter of a J1939 socket
+///
+/// A message passes the filter if its source NAME, PGN and source address all match after
+/// masking. The default filter matches every message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct J1939Filter {
+    pub name: u64,
+    pub name_mask: u64,
+    pub pgn: u32,
+    pub pgn_mask: u32,
+    pub addr: u8,
+    pub addr_mask: u8,
+}
+
+/// Outcome of [`J1939Socket::claim_address`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressClaim {
+    /// No node with a lower NAME claimed the address.
+    Claimed(u8),
+    /// A node with a lower NAME claimed the address. J1939-81 requires to either claim another
+    /// address or announce the loss by claiming [`IDLE_ADDR`].
+    Lost { winner: Name },
+}
+
+/// A J1939 socket bound to a CAN interface
+pub struct J1939Socket {
+    pub(crate) fd: OwnedFd,
+    pub(crate) interface: CanAddr,
+    local: J1939Addr,
+}
+
+impl J1939Socket {
+    /// Binds to `local` on `interface`. The local address is used as the source of the sent
+    /// messages.
+    pub fn bind(interface: CanAddr, local: J1939Addr) -> Result<Self, Error> {
+        let socket = Self {
+            fd: socket::new(Type::DGRAM, Protocol::J1939)?,
+            interface,
+            local,
+        };
+        let raw = local.to_raw(socket.interface.inner.ifindex);
+        let ret = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                (&raw as *const RawJ1939Addr).cast::<libc::sockaddr>(),
+                mem::size_of::<RawJ1939Addr>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(socket.syscall_error("bind(2)", "binding"));
+        }
+        Ok(socket)
+    }
+
+    pub fn local_addr(&self) -> J1939Addr {
+        self.local
+    }
+
+    /// Sets the default destination of [`J1939Socket::send`], and only receives messages from
+    /// `peer`.
+    pub fn connect(&self, peer: &J1939Addr) -> Result<(), Error> {
+        let raw = peer.to_raw(self.interface.inner.ifindex);
+        let ret = unsafe {
+            libc::connect(
+                self.as_raw_fd(),
+                (&raw as *const RawJ1939Addr).cast::<libc::sockaddr>(),
+                mem::size_of::<RawJ1939Addr>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(self.syscall_error("connect(2)", "connecting"));
+        }
+        Ok(())
+    }
+
+    /// Sends a message to the connected peer.
+    pub fn send(&self, data: &[u8]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::send(
+                self.as_raw_fd(),
+                data.as_ptr().cast::<libc::c_void>(),
+                data.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    /// Sends a message to `dest`, which must hold a PGN. Broadcasts require
+    /// [`J1939Socket::set_broadcast`].
+    pub fn send_to(&self, data: &[u8], dest: &J1939Addr) -> io::Result<usize> {
+        let raw = dest.to_raw(self.interface.inner.ifindex);
+        let ret = unsafe {
+            libc::sendto(
+                self.as_raw_fd(),
+                data.as_ptr().cast::<libc::c_void>(),
+                data.len(),
+                0,
+                (&raw as *const RawJ1939Addr).cast::<libc::sockaddr>(),
+                mem::size_of::<RawJ1939Addr>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    /// Receives a message, returning its length and its source address and PGN.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, J1939Addr)> {
+        let mut raw = J1939Addr::default().to_raw(0);
+        let mut len = mem::size_of::<RawJ1939Addr>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::recvfrom(
+                self.as_raw_fd(),
+                buf.as_mut_ptr().cast::<libc::c_void>(),
+                buf.len(),
+                0,
+                (&mut raw as *mut RawJ1939Addr).cast::<libc::sockaddr>(),
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((ret as usize, J1939Addr::from_raw(&raw)))
+    }
+
+    /// Allows sending and receiving broadcasts (`SO_BROADCAST`).
+    pub fn set_broadcast(&self, broadcast: bool) -> Result<(), Error> {
+        socket::setsockopt(
+            self,
+            libc::SOL_SOCKET,
+            libc::SO_BROADCAST,
+            libc::c_int::from(broadcast),
+            "SO_BROADCAST",
+        )
+    }
+
+    /// Receives the messages addressed to any node (`SO_J1939_PROMISC`).
+    pub fn set_promiscuous(&self, promiscuous: bool) -> Result<(), Error> {
+        socket::setsockopt(
+            self,
+            libc::SOL_CAN_J1939,
+            libc::SO_J1939_PROMISC,
+            libc::c_int::from(promiscuous),
+            "SO_J1939_PROMISC",
+        )
+    }
+
+    /// Sets the priority of the sent messages, from 0 (highest) to 7 (`SO_J1939_SEND_PRIO`).
+    pub fn set_send_priority(&self, priority: u8) -> Result<(), Error> {
+        socket::setsockopt(
+            self,
+            libc::SOL_CAN_J1939,
+            libc::SO_J1939_SEND_PRIO,
+            libc::c_int::from(priority),
+            "SO_J1939_SEND_PRIO",
+        )
+    }
+
+    /// Only receives the messages passing one of the filters (`SO_J1939_FILTER`). An empty
+    /// list removes the filters.
+    pub fn set_filters(&self, filters: &[J1939Filter]) -> Result<(), Error> {
+        if filters.len() > FILTER_MAX {
+            return Err(Error::CanFilterOverflow(filters.len()));
+        }
+        let ret = unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::SOL_CAN_J1939,
+                libc::SO_J1939_FILTER,
+                filters.as_ptr().cast::<libc::c_void>(),
+                mem::size_of_val(filters) as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(self.syscall_error("setsockopt(2)", "setting SO_J1939_FILTER"));
+        }
+        Ok(())
+    }
+
+    /// Claims the address the socket is bound to for its NAME, and waits for competing claims.
+    ///
+    /// A competing claim from a node with a higher NAME is answered by claiming the address
+    /// again. Broadcasts are enabled on the socket, as address claims are broadcast.
+    pub fn claim_address(&self) -> Result<AddressClaim, Error> {
+        let (Some(name), Some(addr)) = (self.local.name, self.local.addr) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "claiming an address requires a socket bound to a NAME and an address",
+            )
+            .into());
+        };
+        self.set_broadcast(true)?;
+        self.send_address_claim(name)?;
+
+        let deadline = Instant::now() + ADDRESS_CLAIM_TIMEOUT;
+        let mut buf = [0u8; 8];
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(AddressClaim::Claimed(addr));
+            }
+            match stream::imp::wait_readable(self.as_raw_fd(), timeout) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    return Ok(AddressClaim::Claimed(addr));
+                }
+                Err(err) => return Err(err.into()),
+            }
+            let (len, src) = self.recv_from(&mut buf)?;
+            if len < buf.len()
+                || src.pgn != Some(Pgn::ADDRESS_CLAIMED)
+                || src.addr != Some(addr)
+            {
+                continue;
+            }
+            let competitor = Name(u64::from_le_bytes(buf));
+            if competitor < name {
+                return Ok(AddressClaim::Lost { winner: competitor });
+            }
+            if competitor != name {
+                self.send_address_claim(name)?;
+            }
+        }
+    }
+
+    /// Asks every node to announce its address, with a request for the address claimed PGN.
+    /// Requires [`J1939Socket::set_broadcast`].
+    pub fn request_address_claims(&self) -> io::Result<usize> {
+        let pgn = Pgn::ADDRESS_CLAIMED.0.to_le_bytes();
+        self.send_to(
+            &pgn[..3],
+            &J1939Addr {
+                pgn: Some(Pgn::REQUEST),
+                ..J1939Addr::default()
+            },
+        )
+    }
+
+    fn send_address_claim(&self, name: Name) -> Result<(), Error> {
+        self.send_to(
+            &name.0.to_le_bytes(),
+            &J1939Addr {
+                pgn: Some(Pgn::ADDRESS_CLAIMED),
+                ..J1939Addr::default()
+            },
+        )
+        .map_err(|source| Error::Syscall {
+            syscall: "sendto(2)".to_string(),
+            context: Some(format!("claiming address on {}", self.interface.name)),
+            source,
+        })?;
+        Ok(())
+    }
+
+    fn syscall_error(&self, syscall: &str, action: &str) -> Error {
+        Error::Syscall {
+            syscall: syscall.to_string(),
+            context: Some(format!("{action} J1939 socket on {}", self.interface.name)),
+            source: io::Error::last_os_error(),
+        }
+    }
+}
+
+impl AsRawFd for J1939Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for J1939Socket {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+/// `struct sockaddr_can` from `linux/can.h`, with the `j1939` member of the address union
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct RawJ1939Addr {
+    family: u16,
+    ifindex: libc::c_int,
+    name: u64,
+    pgn: u32,
+    addr: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_fields() {
+        let fields = NameFields {
+            identity_number: 0x1F_FFFF,
+            manufacturer_code: 0x123,
+            ecu_instance: 5,
+            function_instance: 17,
+            function: 0x81,
+            vehicle_system: 0x55,
+            vehicle_system_instance: 9,
+            industry_group: 2,
+            arbitrary_address_capable: true,
+        };
+        let name = Name::from(fields);
+        assert_eq!(name.fields(), fields);
+        assert_eq!(name.0 >> 63, 1);
+        assert_eq!(name.0 & 0x1F_FFFF, 0x1F_FFFF);
+        assert_eq!(Name::from(NameFields::default()), Name(0));
+        assert_eq!(
+            Name::from(NameFields {
+                identity_number: u32::MAX,
+                ..NameFields::default()
+            }),
+            Name(0x1F_FFFF)
+        );
+    }
+
+    #[test]
+    fn pdu_formats() {
+        assert!(Pgn::REQUEST.is_pdu1());
+        assert!(Pgn::ADDRESS_CLAIMED.is_pdu1());
+        assert!(!Pgn::ADDRESS_COMMANDED.is_pdu1());
+        assert_eq!(Pgn(0xFF00).pdu_format(), 0xFF);
+    }
+
+    #[test]
+    fn raw_addresses() {
+        // The kernel requires the address to extend to the end of the `j1939` member.
+        assert_eq!(mem::size_of::<RawJ1939Addr>(), 24);
+        assert_eq!(mem::size_of::<J1939Filter>(), 32);
+
+        let addr = J1939Addr {
+            name: Some(Name(42)),
+            pgn: Some(Pgn::ADDRESS_CLAIMED),
+            addr: None,
+        };
+        let raw = addr.to_raw(3);
+        assert_eq!((raw.ifindex, raw.pgn, raw.addr), (3, 0xEE00, NO_ADDR));
+        assert_eq!(J1939Addr::from_raw(&raw), addr);
+        assert_eq!(
+            J1939Addr::from_raw(&J1939Addr::default().to_raw(0)),
+            J1939Addr::default()
+        );
+    }
+}
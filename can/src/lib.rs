@@ -1,11 +1,20 @@
 pub mod addr;
+pub mod candump;
+pub mod error_frame;
 pub mod filter;
 pub mod frame;
+pub mod meta;
+pub mod netlink;
 mod socket;
 pub mod stream;
+pub mod vbus;
 
+#[cfg(feature = "bcm")]
+pub mod bcm;
 #[cfg(feature = "isotp")]
 pub mod isotp;
+#[cfg(feature = "j1939")]
+pub mod j1939;
 
 use std::{
     ffi::{CString, OsStr},
@@ -146,6 +155,9 @@ pub enum Error {
         source: io::Error,
     },
 
+    #[error("invalid candump log line `{line}`: {reason}")]
+    CandumpParse { line: String, reason: &'static str },
+
     #[error(transparent)]
     NulError(#[from] std::ffi::NulError),
 
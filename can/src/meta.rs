@@ -0,0 +1,178 @@
+use std::{
+    mem, ptr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Kind of kernel RX timestamps attached to received frames
+///
+/// The timestamps are reported in [`RecvMeta`] by [`FrameStream::recv_with_meta`].
+///
+/// [`FrameStream::recv_with_meta`]: crate::stream::FrameStream::recv_with_meta
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timestamping {
+    /// No timestamps are reported.
+    #[default]
+    Disabled,
+    /// Software timestamps taken by the kernel when the frame is received (`SO_TIMESTAMPNS`).
+    Software,
+    /// Software timestamps plus raw hardware timestamps from the CAN controller, if the driver
+    /// supports them (`SO_TIMESTAMPING`).
+    Hardware,
+}
+
+/// Metadata received next to a frame from `recvmsg(2)`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// Index of the interface the frame was received on.
+    pub ifindex: u32,
+    /// Kernel software RX timestamp. Requires [`Timestamping::Software`] or
+    /// [`Timestamping::Hardware`].
+    pub timestamp: Option<SystemTime>,
+    /// Raw hardware RX timestamp from the CAN controller. Its epoch is defined by the driver.
+    /// Requires [`Timestamping::Hardware`].
+    pub hw_timestamp: Option<Duration>,
+    /// Total number of frames dropped by the socket's receive queue since it was created.
+    /// Requires `SO_RXQ_OVFL` to be enabled, and is only reported once the first frame was
+    /// dropped.
+    pub dropped: Option<u32>,
+    /// The frame was sent from this host rather than received from the bus (`MSG_DONTROUTE`).
+    pub local: bool,
+    /// The frame was sent by this very socket (`MSG_CONFIRM`).
+    pub own: bool,
+}
+
+/// Room for `SCM_TIMESTAMPNS`, `SCM_TIMESTAMPING` and `SO_RXQ_OVFL` control messages.
+pub(crate) const CONTROL_LEN: usize = 256;
+
+/// Control message buffer with the alignment required by `cmsghdr`.
+#[repr(C, align(8))]
+pub(crate) struct ControlBuffer(pub(crate) [u8; CONTROL_LEN]);
+
+impl RecvMeta {
+    /// Parses the flags and the control messages of a message header filled by `recvmsg(2)`.
+    ///
+    /// # Safety
+    ///
+    /// `msg.msg_control` must point to `msg.msg_controllen` bytes of valid control messages.
+    pub(crate) unsafe fn from_msghdr(msg: &libc::msghdr, ifindex: u32) -> Self {
+        let mut meta = Self {
+            ifindex,
+            local: msg.msg_flags & libc::MSG_DONTROUTE != 0,
+            own: msg.msg_flags & libc::MSG_CONFIRM != 0,
+            ..Self::default()
+        };
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET {
+                match (*cmsg).cmsg_type {
+                    libc::SCM_TIMESTAMP => {
+                        let tv = ptr::read_unaligned(data.cast::<libc::timeval>());
+                        meta.timestamp = Some(
+                            UNIX_EPOCH
+                                + Duration::new(
+                                    tv.tv_sec as u64,
+                                    tv.tv_usec as u32 * 1000,
+                                ),
+                        );
+                    }
+                    libc::SCM_TIMESTAMPNS => {
+                        let ts = ptr::read_unaligned(data.cast::<libc::timespec>());
+                        meta.timestamp = Some(UNIX_EPOCH + timespec_to_duration(ts));
+                    }
+                    libc::SCM_TIMESTAMPING => {
+                        // [software, deprecated, raw hardware]
+                        let ts =
+                            ptr::read_unaligned(data.cast::<[libc::timespec; 3]>());
+                        let software = timespec_to_duration(ts[0]);
+                        let hardware = timespec_to_duration(ts[2]);
+                        if !software.is_zero() {
+                            meta.timestamp = Some(UNIX_EPOCH + software);
+                        }
+                        if !hardware.is_zero() {
+                            meta.hw_timestamp = Some(hardware);
+                        }
+                    }
+                    libc::SO_RXQ_OVFL => {
+                        meta.dropped = Some(ptr::read_unaligned(data.cast::<u32>()));
+                    }
+                    _ => {}
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+        meta
+    }
+}
+
+fn timespec_to_duration(ts: libc::timespec) -> Duration {
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Returns a zeroed message header. Some of the `msghdr` fields are private padding on some
+/// targets, so it can't be constructed with a struct literal.
+pub(crate) fn empty_msghdr() -> libc::msghdr {
+    unsafe { mem::zeroed() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn push_cmsg<T>(
+        msg: &libc::msghdr,
+        cmsg: *mut libc::cmsghdr,
+        ty: libc::c_int,
+        value: T,
+    ) -> *mut libc::cmsghdr {
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = ty;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<T>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<T>(), value);
+        libc::CMSG_NXTHDR(msg, cmsg)
+    }
+
+    #[test]
+    fn parse_control_messages() {
+        let mut control = ControlBuffer([0; CONTROL_LEN]);
+        let mut msg = empty_msghdr();
+        msg.msg_control = control.0.as_mut_ptr().cast();
+        msg.msg_controllen = CONTROL_LEN as _;
+        msg.msg_flags = libc::MSG_DONTROUTE;
+        let timestamping = [
+            libc::timespec {
+                tv_sec: 10,
+                tv_nsec: 20,
+            },
+            libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            libc::timespec {
+                tv_sec: 3,
+                tv_nsec: 4,
+            },
+        ];
+        let len = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            let cmsg = push_cmsg(&msg, cmsg, libc::SCM_TIMESTAMPING, timestamping);
+            push_cmsg(&msg, cmsg, libc::SO_RXQ_OVFL, 7_u32);
+            libc::CMSG_SPACE(mem::size_of::<[libc::timespec; 3]>() as u32)
+                + libc::CMSG_SPACE(mem::size_of::<u32>() as u32)
+        };
+        msg.msg_controllen = len as _;
+
+        let meta = unsafe { RecvMeta::from_msghdr(&msg, 3) };
+        assert_eq!(
+            meta,
+            RecvMeta {
+                ifindex: 3,
+                timestamp: Some(UNIX_EPOCH + Duration::new(10, 20)),
+                hw_timestamp: Some(Duration::new(3, 4)),
+                dropped: Some(7),
+                local: true,
+                own: false,
+            }
+        );
+    }
+}
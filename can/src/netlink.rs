@@ -0,0 +1,571 @@
+//! Managing CAN interfaces over rtnetlink
+//!
+//! [`Interface`] brings a CAN interface up and down, configures its bit timing, and reads its
+//! settings and statistics back. Changing the configuration requires `CAP_NET_ADMIN`, and the
+//! bit timing can only be changed while the interface is down.
+//!
+//! The bus state and the error counters are maintained by the CAN driver and are available even
+//! when no error frames are subscribed to. Virtual interfaces don't report them.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use can_rs::netlink::{Interface, LinkConfig};
+//!
+//! let can0 = Interface::new("can0").unwrap();
+//! can0.set_down().unwrap();
+//! can0.configure(&LinkConfig {
+//!     bitrate: Some(500_000),
+//!     data_bitrate: Some(2_000_000),
+//!     fd: Some(true),
+//!     restart_ms: Some(100),
+//! })
+//! .unwrap();
+//! can0.set_up().unwrap();
+//! assert_eq!(can0.info().unwrap().bitrate, Some(500_000));
+//! ```
+
+use std::{
+    io,
+    os::{
+        fd::OwnedFd,
+        unix::io::{AsRawFd, FromRawFd},
+    },
+};
+
+use crate::{addr::try_string_to_ifindex, error_frame::ErrorCounters, Error};
+
+const IFLA_CAN_BITTIMING: u16 = 1;
+const IFLA_CAN_STATE: u16 = 4;
+const IFLA_CAN_CTRLMODE: u16 = 5;
+const IFLA_CAN_RESTART_MS: u16 = 6;
+const IFLA_CAN_BERR_COUNTER: u16 = 8;
+const IFLA_CAN_DATA_BITTIMING: u16 = 9;
+const CAN_CTRLMODE_FD: u32 = 0x20;
+/// Size of `struct can_bittiming`
+const BITTIMING_LEN: usize = 8 * 4;
+const NLA_F_NESTED: u16 = 1 << 15;
+const NLA_TYPE_MASK: u16 = 0x3FFF;
+const RECV_BUFFER_LEN: usize = 32 * 1024;
+
+/// State of the CAN controller as defined by `enum can_state` of `linux/can/netlink.h`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusState {
+    /// RX/TX error count < 96
+    ErrorActive,
+    /// RX/TX error count < 128
+    ErrorWarning,
+    /// RX/TX error count < 256
+    ErrorPassive,
+    /// RX/TX error count >= 256
+    BusOff,
+    /// Device is stopped
+    Stopped,
+    /// Device is sleeping
+    Sleeping,
+    Unknown(u32),
+}
+
+impl From<u32> for BusState {
+    fn from(state: u32) -> Self {
+        match state {
+            0 => Self::ErrorActive,
+            1 => Self::ErrorWarning,
+            2 => Self::ErrorPassive,
+            3 => Self::BusOff,
+            4 => Self::Stopped,
+            5 => Self::Sleeping,
+            state => Self::Unknown(state),
+        }
+    }
+}
+
+/// Event counters of the CAN device (`struct can_device_stats`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceStats {
+    pub bus_error: u32,
+    pub error_warning: u32,
+    pub error_passive: u32,
+    pub bus_off: u32,
+    pub arbitration_lost: u32,
+    pub restarts: u32,
+}
+
+/// Bus status of a CAN interface
+///
+/// Every field is `None` if the driver doesn't report it, e.g. for `vcan` interfaces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BusStatus {
+    pub state: Option<BusState>,
+    pub counters: Option<ErrorCounters>,
+    pub stats: Option<DeviceStats>,
+}
+
+/// Bit timing and controller settings applied by [`Interface::configure`]
+///
+/// Settings left as `None` are not changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkConfig {
+    /// Nominal bitrate in bits per second. The kernel calculates the bit timing.
+    pub bitrate: Option<u32>,
+    /// CAN FD data phase bitrate in bits per second.
+    pub data_bitrate: Option<u32>,
+    /// Enables or disables CAN FD mode.
+    pub fd: Option<bool>,
+    /// Delay before an automatic restart after bus-off, `0` disables automatic restarts.
+    pub restart_ms: Option<u32>,
+}
+
+/// Settings and statistics of a CAN interface
+///
+/// The CAN specific settings are `None` for interfaces which don't report them, e.g. `vcan`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub up: bool,
+    pub mtu: Option<u32>,
+    pub bitrate: Option<u32>,
+    /// Sample point in tenths of a percent.
+    pub sample_point: Option<u32>,
+    pub data_bitrate: Option<u32>,
+    pub fd: Option<bool>,
+    pub restart_ms: Option<u32>,
+    pub bus: BusStatus,
+    pub stats: Option<InterfaceStats>,
+}
+
+/// Generic network interface counters (`struct rtnl_link_stats64`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+/// A network interface managed over rtnetlink
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub ifindex: u32,
+}
+
+impl Interface {
+    /// Resolves the interface index of `name`.
+    pub fn new(name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.to_string(),
+            ifindex: try_string_to_ifindex(name)?,
+        })
+    }
+
+    pub fn set_up(&self) -> Result<(), Error> {
+        self.set_flags(libc::IFF_UP as u32)
+    }
+
+    pub fn set_down(&self) -> Result<(), Error> {
+        self.set_flags(0)
+    }
+
+    fn set_flags(&self, flags: u32) -> Result<(), Error> {
+        let info = IfInfoMsg {
+            flags,
+            change: libc::IFF_UP as u32,
+            ..IfInfoMsg::new(self.ifindex)
+        };
+        NetlinkSocket::new()?.request(
+            libc::RTM_NEWLINK,
+            libc::NLM_F_ACK as u16,
+            &info,
+            &[],
+        )?;
+        Ok(())
+    }
+
+    /// Applies the given settings. The interface must be down to change the bit timing.
+    pub fn configure(&self, config: &LinkConfig) -> Result<(), Error> {
+        let mut data = Vec::new();
+        if let Some(bitrate) = config.bitrate {
+            push_attr(&mut data, IFLA_CAN_BITTIMING, &bittiming(bitrate));
+        }
+        if let Some(bitrate) = config.data_bitrate {
+            push_attr(&mut data, IFLA_CAN_DATA_BITTIMING, &bittiming(bitrate));
+        }
+        if let Some(fd) = config.fd {
+            let mut ctrlmode = CAN_CTRLMODE_FD.to_ne_bytes().to_vec();
+            ctrlmode.extend_from_slice(
+                &(if fd { CAN_CTRLMODE_FD } else { 0 }).to_ne_bytes(),
+            );
+            push_attr(&mut data, IFLA_CAN_CTRLMODE, &ctrlmode);
+        }
+        if let Some(restart_ms) = config.restart_ms {
+            push_attr(&mut data, IFLA_CAN_RESTART_MS, &restart_ms.to_ne_bytes());
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut linkinfo = Vec::new();
+        push_attr(&mut linkinfo, libc::IFLA_INFO_KIND, b"can");
+        push_attr(&mut linkinfo, libc::IFLA_INFO_DATA | NLA_F_NESTED, &data);
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, libc::IFLA_LINKINFO | NLA_F_NESTED, &linkinfo);
+        NetlinkSocket::new()?.request(
+            libc::RTM_NEWLINK,
+            libc::NLM_F_ACK as u16,
+            &IfInfoMsg::new(self.ifindex),
+            &attrs,
+        )?;
+        Ok(())
+    }
+
+    /// Reads the settings and the statistics of the interface.
+    pub fn info(&self) -> Result<LinkInfo, Error> {
+        let response = NetlinkSocket::new()?.request(
+            libc::RTM_GETLINK,
+            0,
+            &IfInfoMsg::new(self.ifindex),
+            &[],
+        )?;
+        let mut info = LinkInfo::default();
+        for (ty, payload) in messages(&response) {
+            if ty == libc::RTM_NEWLINK {
+                parse_link(payload, &mut info);
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// Reads the bus state and the error counters of the interface `ifname`.
+///
+/// A controller in [`BusState::BusOff`] or with rising error counters points to a bus fault,
+/// while an [`BusState::ErrorActive`] controller with a silent peer points to a dead node.
+pub fn bus_status(ifname: &str) -> Result<BusStatus, Error> {
+    Ok(Interface::new(ifname)?.info()?.bus)
+}
+
+fn parse_link(payload: &[u8], info: &mut LinkInfo) {
+    let Some(attrs) = payload.get(IFINFOMSG_LEN..) else {
+        return;
+    };
+    info.up = payload
+        .get(8..)
+        .and_then(read_u32)
+        .is_some_and(|flags| flags & libc::IFF_UP as u32 != 0);
+    info.mtu = find_attr(attrs, libc::IFLA_MTU).and_then(read_u32);
+    info.stats = find_attr(attrs, libc::IFLA_STATS64).and_then(|attr| {
+        let field = |i: usize| read_u64(attr.get(i * 8..)?);
+        Some(InterfaceStats {
+            rx_packets: field(0)?,
+            tx_packets: field(1)?,
+            rx_bytes: field(2)?,
+            tx_bytes: field(3)?,
+            rx_errors: field(4)?,
+            tx_errors: field(5)?,
+            rx_dropped: field(6)?,
+            tx_dropped: field(7)?,
+        })
+    });
+    let Some(linkinfo) = find_attr(attrs, libc::IFLA_LINKINFO) else {
+        return;
+    };
+    if let Some(data) = find_attr(linkinfo, libc::IFLA_INFO_DATA) {
+        if let Some(bittiming) = find_attr(data, IFLA_CAN_BITTIMING) {
+            info.bitrate = read_u32(bittiming);
+            info.sample_point = bittiming.get(4..).and_then(read_u32);
+        }
+        info.data_bitrate = find_attr(data, IFLA_CAN_DATA_BITTIMING).and_then(read_u32);
+        info.fd = find_attr(data, IFLA_CAN_CTRLMODE)
+            .and_then(|attr| read_u32(attr.get(4..)?))
+            .map(|flags| flags & CAN_CTRLMODE_FD != 0);
+        info.restart_ms = find_attr(data, IFLA_CAN_RESTART_MS).and_then(read_u32);
+        info.bus.state = find_attr(data, IFLA_CAN_STATE)
+            .and_then(read_u32)
+            .map(BusState::from);
+        info.bus.counters = find_attr(data, IFLA_CAN_BERR_COUNTER).and_then(|attr| {
+            Some(ErrorCounters {
+                tx: read_u16(attr)?,
+                rx: read_u16(attr.get(2..)?)?,
+            })
+        });
+    }
+    info.bus.stats = find_attr(linkinfo, libc::IFLA_INFO_XSTATS).and_then(|attr| {
+        let field = |i: usize| read_u32(attr.get(i * 4..)?);
+        Some(DeviceStats {
+            bus_error: field(0)?,
+            error_warning: field(1)?,
+            error_passive: field(2)?,
+            bus_off: field(3)?,
+            arbitration_lost: field(4)?,
+            restarts: field(5)?,
+        })
+    });
+}
+
+/// Encodes a `struct can_bittiming` with only the bitrate set, letting the kernel calculate the
+/// rest.
+fn bittiming(bitrate: u32) -> [u8; BITTIMING_LEN] {
+    let mut buf = [0; BITTIMING_LEN];
+    buf[..4].copy_from_slice(&bitrate.to_ne_bytes());
+    buf
+}
+
+/// `struct ifinfomsg` from `linux/rtnetlink.h`
+#[repr(C)]
+pub(crate) struct IfInfoMsg {
+    pub(crate) family: u8,
+    pub(crate) pad: u8,
+    pub(crate) ty: u16,
+    pub(crate) index: i32,
+    pub(crate) flags: u32,
+    pub(crate) change: u32,
+}
+
+impl IfInfoMsg {
+    pub(crate) fn new(ifindex: u32) -> Self {
+        Self {
+            family: libc::AF_UNSPEC as u8,
+            pad: 0,
+            ty: 0,
+            index: ifindex as i32,
+            flags: 0,
+            change: 0,
+        }
+    }
+}
+
+pub(crate) const IFINFOMSG_LEN: usize = std::mem::size_of::<IfInfoMsg>();
+const NLMSGHDR_LEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+
+/// A `NETLINK_ROUTE` socket sending one request at a time
+pub(crate) struct NetlinkSocket {
+    fd: OwnedFd,
+}
+
+impl NetlinkSocket {
+    pub(crate) fn new() -> Result<Self, Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::Syscall {
+                syscall: "socket(2)".to_string(),
+                context: Some("opening NETLINK_ROUTE socket".to_string()),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Sends an `ifinfomsg` request followed by the already encoded `attrs`, and returns the
+    /// response messages. Fails if the kernel replies with an error.
+    pub(crate) fn request(
+        &self,
+        ty: u16,
+        flags: u16,
+        info: &IfInfoMsg,
+        attrs: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let header = libc::nlmsghdr {
+            nlmsg_len: (NLMSGHDR_LEN + IFINFOMSG_LEN + attrs.len()) as u32,
+            nlmsg_type: ty,
+            nlmsg_flags: libc::NLM_F_REQUEST as u16 | flags,
+            nlmsg_seq: 1,
+            nlmsg_pid: 0,
+        };
+        let mut request = Vec::with_capacity(header.nlmsg_len as usize);
+        request.extend_from_slice(as_bytes(&header));
+        request.extend_from_slice(as_bytes(info));
+        request.extend_from_slice(attrs);
+
+        let ret = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                request.as_ptr().cast(),
+                request.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "send(2)".to_string(),
+                context: Some("sending netlink request".to_string()),
+                source: io::Error::last_os_error(),
+            });
+        }
+
+        let mut response = vec![0u8; RECV_BUFFER_LEN];
+        let ret = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                response.as_mut_ptr().cast(),
+                response.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "recv(2)".to_string(),
+                context: Some("receiving netlink response".to_string()),
+                source: io::Error::last_os_error(),
+            });
+        }
+        response.truncate(ret as usize);
+
+        for (message_ty, payload) in messages(&response) {
+            if message_ty == libc::NLMSG_ERROR as u16 {
+                let errno = payload
+                    .get(..4)
+                    .map(|b| i32::from_ne_bytes(b.try_into().unwrap()))
+                    .unwrap_or(0);
+                if errno != 0 {
+                    return Err(Error::Syscall {
+                        syscall: "netlink(7)".to_string(),
+                        context: Some(format!("request of type {ty} rejected")),
+                        source: io::Error::from_raw_os_error(-errno),
+                    });
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Iterates over the netlink messages in `buf`, yielding their types and payloads.
+pub(crate) fn messages(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = read_u32(buf)? as usize;
+        if len < NLMSGHDR_LEN || len > buf.len() {
+            return None;
+        }
+        let ty = read_u16(&buf[4..])?;
+        let payload = &buf[NLMSGHDR_LEN..len];
+        buf = buf.get(align(len)..).unwrap_or_default();
+        Some((ty, payload))
+    })
+}
+
+/// Iterates over the netlink attributes in `buf`, yielding their types and payloads.
+pub(crate) fn attrs(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = read_u16(buf)? as usize;
+        if len < 4 || len > buf.len() {
+            return None;
+        }
+        let ty = read_u16(&buf[2..])? & NLA_TYPE_MASK;
+        let payload = &buf[4..len];
+        buf = buf.get(align(len)..).unwrap_or_default();
+        Some((ty, payload))
+    })
+}
+
+pub(crate) fn find_attr(buf: &[u8], ty: u16) -> Option<&[u8]> {
+    attrs(buf)
+        .find(|(t, _)| *t == ty)
+        .map(|(_, payload)| payload)
+}
+
+/// Appends an attribute with the given payload to `buf`.
+pub(crate) fn push_attr(buf: &mut Vec<u8>, ty: u16, payload: &[u8]) {
+    let len = 4 + payload.len();
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&ty.to_ne_bytes());
+    buf.extend_from_slice(payload);
+    buf.resize(align(buf.len()), 0);
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(buf: &[u8]) -> Option<u16> {
+    Some(u16::from_ne_bytes(buf.get(..2)?.try_into().ok()?))
+}
+
+fn read_u32(buf: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(..4)?.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8]) -> Option<u64> {
+    Some(u64::from_ne_bytes(buf.get(..8)?.try_into().ok()?))
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts((value as *const T).cast(), std::mem::size_of::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_nested_attributes() {
+        let mut data = Vec::new();
+        push_attr(&mut data, IFLA_CAN_STATE, &3u32.to_ne_bytes());
+        let mut counters = 12u16.to_ne_bytes().to_vec();
+        counters.extend_from_slice(&34u16.to_ne_bytes());
+        push_attr(&mut data, IFLA_CAN_BERR_COUNTER, &counters);
+        let mut linkinfo = Vec::new();
+        push_attr(&mut linkinfo, libc::IFLA_INFO_KIND, b"can\0");
+        push_attr(&mut linkinfo, libc::IFLA_INFO_DATA, &data);
+
+        let data = find_attr(&linkinfo, libc::IFLA_INFO_DATA).unwrap();
+        assert_eq!(
+            find_attr(data, IFLA_CAN_STATE)
+                .and_then(read_u32)
+                .map(BusState::from),
+            Some(BusState::BusOff)
+        );
+        let counters = find_attr(data, IFLA_CAN_BERR_COUNTER).unwrap();
+        assert_eq!(read_u16(counters), Some(12));
+        assert_eq!(read_u16(&counters[2..]), Some(34));
+        assert_eq!(find_attr(&linkinfo, libc::IFLA_INFO_XSTATS), None);
+    }
+    #[test]
+    fn parse_link_info() {
+        let mut bittiming = bittiming(500_000);
+        bittiming[4..8].copy_from_slice(&875u32.to_ne_bytes());
+        let mut data = Vec::new();
+        push_attr(&mut data, IFLA_CAN_BITTIMING, &bittiming);
+        let mut ctrlmode = CAN_CTRLMODE_FD.to_ne_bytes().to_vec();
+        ctrlmode.extend_from_slice(&CAN_CTRLMODE_FD.to_ne_bytes());
+        push_attr(&mut data, IFLA_CAN_CTRLMODE, &ctrlmode);
+        push_attr(&mut data, IFLA_CAN_RESTART_MS, &100u32.to_ne_bytes());
+        let mut linkinfo = Vec::new();
+        push_attr(&mut linkinfo, libc::IFLA_INFO_DATA | NLA_F_NESTED, &data);
+
+        let mut payload = as_bytes(&IfInfoMsg {
+            flags: libc::IFF_UP as u32,
+            ..IfInfoMsg::new(3)
+        })
+        .to_vec();
+        push_attr(&mut payload, libc::IFLA_MTU, &72u32.to_ne_bytes());
+        push_attr(&mut payload, libc::IFLA_LINKINFO | NLA_F_NESTED, &linkinfo);
+
+        let mut info = LinkInfo::default();
+        parse_link(&payload, &mut info);
+        assert_eq!(
+            info,
+            LinkInfo {
+                up: true,
+                mtu: Some(72),
+                bitrate: Some(500_000),
+                sample_point: Some(875),
+                fd: Some(true),
+                restart_ms: Some(100),
+                ..LinkInfo::default()
+            }
+        );
+    }
+}
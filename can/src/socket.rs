@@ -11,8 +11,9 @@ use libc::CAN_RAW_LOOPBACK;
 use crate::{
     addr::{try_ifindex_to_ifname, RawCanAddr},
     filter::{Filter, RawFilter},
-    ifreq_siocgifmtu, Error, Protocol, Type, CAN_RAW_FD_FRAMES_ENABLE,
-    CAN_RAW_FILTER_MAX, MTU,
+    ifreq_siocgifmtu,
+    meta::Timestamping,
+    Error, Protocol, Type, CAN_RAW_FD_FRAMES_ENABLE, CAN_RAW_FILTER_MAX, MTU,
 };
 
 pub(crate) fn new(ty: Type, protocol: Protocol) -> Result<OwnedFd, Error> {
@@ -170,6 +171,30 @@ pub(crate) fn bind<T: AsRawFd, R: AsRef<RawCanAddr>>(
     Ok(())
 }
 
+/// Connect a socket to a CAN interface, as required by the connection oriented protocols, e.g.
+/// `CAN_BCM`
+#[cfg(feature = "bcm")]
+pub(crate) fn connect<T: AsRawFd, R: AsRef<RawCanAddr>>(
+    fd: T,
+    addr: R,
+) -> Result<(), Error> {
+    let ret = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            (addr.as_ref() as *const RawCanAddr) as *const libc::sockaddr,
+            std::mem::size_of::<RawCanAddr>() as libc::c_uint,
+        )
+    };
+    if ret == -1 {
+        return Err(Error::Syscall {
+            syscall: "connect(2)".to_string(),
+            context: None,
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
 /// Filters messages such that the socket only receives frames whose SFF/EFF ID
 /// bitwise AND a CAN filter mask matches the bitwise AND of that same CAN filter's
 /// mask.
@@ -291,3 +316,83 @@ pub(crate) fn filters_raw<T: AsRawFd>(
     }
     Ok(len / std::mem::size_of::<RawFilter>())
 }
+
+/// Enable or disable kernel RX timestamps reported with every received frame
+pub(crate) fn set_timestamping<T: AsRawFd>(
+    fd: &T,
+    timestamping: Timestamping,
+) -> Result<(), Error> {
+    let (nanos, flags): (libc::c_int, libc::c_uint) = match timestamping {
+        Timestamping::Disabled => (0, 0),
+        Timestamping::Software => (1, 0),
+        Timestamping::Hardware => (
+            0,
+            libc::SOF_TIMESTAMPING_RX_SOFTWARE
+                | libc::SOF_TIMESTAMPING_SOFTWARE
+                | libc::SOF_TIMESTAMPING_RX_HARDWARE
+                | libc::SOF_TIMESTAMPING_RAW_HARDWARE,
+        ),
+    };
+    setsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_TIMESTAMPNS,
+        nanos,
+        "SO_TIMESTAMPNS",
+    )?;
+    setsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_TIMESTAMPING,
+        flags,
+        "SO_TIMESTAMPING",
+    )
+}
+
+/// Enable or disable reporting the number of frames dropped by the socket's receive queue
+pub(crate) fn set_rxq_overflow<T: AsRawFd>(fd: &T, enable: bool) -> Result<(), Error> {
+    setsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_RXQ_OVFL,
+        libc::c_int::from(enable),
+        "SO_RXQ_OVFL",
+    )
+}
+
+/// Subscribe to the error frames of the classes in `mask`
+pub(crate) fn set_error_filter<T: AsRawFd>(fd: &T, mask: u32) -> Result<(), Error> {
+    setsockopt(
+        fd,
+        libc::SOL_CAN_RAW,
+        libc::CAN_RAW_ERR_FILTER,
+        mask,
+        "CAN_RAW_ERR_FILTER",
+    )
+}
+
+pub(crate) fn setsockopt<T: AsRawFd, V>(
+    fd: &T,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: V,
+    option: &str,
+) -> Result<(), Error> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            std::ptr::addr_of!(value).cast::<libc::c_void>(),
+            std::mem::size_of::<V>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Syscall {
+            syscall: "setsockopt(2)".to_string(),
+            context: Some(format!("setting {option}")),
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
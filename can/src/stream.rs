@@ -1,7 +1,17 @@
-use std::{io, os::fd::OwnedFd};
+use std::{io, os::fd::OwnedFd, time::Duration};
 
 use self::imp::{Empty, RawFrame, SetMut};
-use crate::{filter::Filter, *};
+use crate::{
+    filter::Filter,
+    meta::{RecvMeta, Timestamping},
+    *,
+};
+
+#[cfg(feature = "tokio")]
+mod async_stream;
+
+#[cfg(feature = "tokio")]
+pub use self::async_stream::AsyncFrameStream;
 
 /// A raw classical and flexible data-rate (FD) compatible CAN frame stream
 ///
@@ -56,6 +66,9 @@ pub struct FrameStream<const N: usize> {
 pub struct FrameStreamBuilder<const N: usize> {
     pub(crate) nonblocking: bool,
     pub(crate) filters: Vec<Filter>,
+    pub(crate) timestamping: Timestamping,
+    pub(crate) rxq_overflow: bool,
+    pub(crate) error_filter: u32,
 }
 
 impl<const N: usize> FrameStreamBuilder<N> {
@@ -63,6 +76,9 @@ impl<const N: usize> FrameStreamBuilder<N> {
         Self {
             nonblocking: false,
             filters: vec![],
+            timestamping: Timestamping::Disabled,
+            rxq_overflow: false,
+            error_filter: 0,
         }
     }
 
@@ -75,6 +91,25 @@ impl<const N: usize> FrameStreamBuilder<N> {
         self.filters = filters;
         self
     }
+
+    /// Kernel RX timestamps to report with [`FrameStream::recv_with_meta`].
+    pub fn timestamping(&mut self, timestamping: Timestamping) -> &mut Self {
+        self.timestamping = timestamping;
+        self
+    }
+
+    /// Report the dropped frames counter (`SO_RXQ_OVFL`) with [`FrameStream::recv_with_meta`].
+    pub fn rxq_overflow(&mut self, rxq_overflow: bool) -> &mut Self {
+        self.rxq_overflow = rxq_overflow;
+        self
+    }
+
+    /// Subscribe to the error frames of the given classes (`CAN_RAW_ERR_FILTER`). See
+    /// [`error_frame`](crate::error_frame) for the class constants and the decoder.
+    pub fn error_filter(&mut self, mask: u32) -> &mut Self {
+        self.error_filter = mask;
+        self
+    }
 }
 
 impl<const N: usize> Default for FrameStreamBuilder<N> {
@@ -135,6 +170,18 @@ impl<const N: usize> FrameStream<N> {
         filters.extend(ffi_filters.into_iter().map(Into::into));
         Ok(filters)
     }
+
+    pub fn set_timestamping(&self, timestamping: Timestamping) -> Result<(), Error> {
+        socket::set_timestamping(self, timestamping)
+    }
+
+    pub fn set_rxq_overflow(&self, rxq_overflow: bool) -> Result<(), Error> {
+        socket::set_rxq_overflow(self, rxq_overflow)
+    }
+
+    pub fn set_error_filter(&self, mask: u32) -> Result<(), Error> {
+        socket::set_error_filter(self, mask)
+    }
 }
 
 impl<const N: usize> FrameStream<N> {
@@ -162,6 +209,16 @@ impl<const N: usize> FrameStream<N> {
         Ok(size)
     }
 
+    /// Receives a frame together with its [`RecvMeta`] using `recvmsg(2)`.
+    ///
+    /// Timestamps and the dropped frames counter are only reported when enabled with
+    /// [`FrameStreamBuilder::timestamping`] and [`FrameStreamBuilder::rxq_overflow`].
+    pub fn recv_with_meta(&self, flags: c_int) -> io::Result<(Frame<N>, RecvMeta)> {
+        let mut raw = RawFrame::empty();
+        let meta = imp::recv_msg(self.as_raw_fd(), &mut raw, flags)?;
+        Ok((raw.into(), meta))
+    }
+
     pub fn send(&self, frame: &Frame<N>, flags: c_int) -> io::Result<usize> {
         let raw = RawFrame::from(*frame);
         imp::send_to(self, &raw, flags, Empty)
@@ -227,13 +284,53 @@ impl<const N: usize> IntoRawFd for FrameStream<N> {
     }
 }
 
-mod imp {
-    use std::{io, os::unix::prelude::AsRawFd};
+/// Frame level access to a CAN bus
+///
+/// Implemented by [`FrameStream`] for SocketCAN interfaces and by
+/// [`VirtualSocket`](crate::vbus::VirtualSocket) for the in-process
+/// [`VirtualBus`](crate::vbus::VirtualBus), so that protocols built on top of raw frames can run
+/// against either.
+pub trait FrameIo<const N: usize> {
+    /// Sends a single frame.
+    fn transmit(&self, frame: &Frame<N>) -> io::Result<()>;
+
+    /// Blocks until the next frame passing the filters is received. Fails with
+    /// [`io::ErrorKind::TimedOut`] if none arrived within `timeout`, `None` waits forever.
+    fn receive(&self, timeout: Option<Duration>) -> io::Result<Frame<N>>;
+
+    /// Replaces the receive filters, see [`FrameStream::set_filters`].
+    fn set_filters(&self, filters: &[Filter]) -> Result<(), Error>;
+}
+
+impl<const N: usize> FrameIo<N> for FrameStream<N> {
+    fn transmit(&self, frame: &Frame<N>) -> io::Result<()> {
+        self.send(frame, 0).map(drop)
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> io::Result<Frame<N>> {
+        if let Some(timeout) = timeout {
+            imp::wait_readable(self.as_raw_fd(), timeout)?;
+        }
+        self.recv_frame(0)
+    }
+
+    fn set_filters(&self, filters: &[Filter]) -> Result<(), Error> {
+        FrameStream::set_filters(self, filters)
+    }
+}
+
+pub(crate) mod imp {
+    use std::{
+        io,
+        os::unix::prelude::{AsRawFd, RawFd},
+        time::Duration,
+    };
 
     use super::{FrameStream, FrameStreamBuilder};
     use crate::{
         addr::CanAddr,
         filter::{Filter, RawFilter},
+        meta::{self, ControlBuffer, RecvMeta, Timestamping, CONTROL_LEN},
         socket, Error, Frame, Id, Protocol, RawCanAddr, Type, CANFD_DATA_LEN,
         CAN_DATA_LEN,
     };
@@ -270,6 +367,15 @@ mod imp {
         }
 
         socket::set_nonblocking(fd, options.nonblocking)?;
+        if options.timestamping != Timestamping::Disabled {
+            socket::set_timestamping(fd, options.timestamping)?;
+        }
+        if options.rxq_overflow {
+            socket::set_rxq_overflow(fd, true)?;
+        }
+        if options.error_filter != 0 {
+            socket::set_error_filter(fd, options.error_filter)?;
+        }
 
         set_filters_fd(fd, &options.filters)?;
         socket::bind(fd.as_raw_fd(), addr)?;
@@ -289,6 +395,24 @@ mod imp {
         )
     }
 
+    /// Waits up to `timeout` for `fd` to become readable.
+    pub(crate) fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<()> {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            ret if ret < 0 => Err(io::Error::last_os_error()),
+            0 => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for a CAN frame",
+            )),
+            _ => Ok(()),
+        }
+    }
+
     pub(crate) struct Empty;
     #[expect(dead_code)]
     pub(crate) struct Set<T: AsRef<RawCanAddr>>(pub(crate) T);
@@ -345,7 +469,7 @@ mod imp {
     }
 
     #[repr(C)]
-    pub(super) struct RawFrame<const N: usize> {
+    pub(crate) struct RawFrame<const N: usize> {
         id: u32,
         len: u8,
         flags: u8,
@@ -434,4 +558,35 @@ mod imp {
         }
         Ok(ret as usize)
     }
+
+    pub(super) fn recv_msg<const N: usize>(
+        fd: RawFd,
+        frame: &mut RawFrame<N>,
+        flags: libc::c_int,
+    ) -> io::Result<RecvMeta> {
+        let mut addr = RawCanAddr {
+            family: 0,
+            ifindex: 0,
+            rx_id: 0,
+            tx_id: 0,
+        };
+        let mut iov = libc::iovec {
+            iov_base: (frame as *mut RawFrame<N>) as *mut libc::c_void,
+            iov_len: std::mem::size_of::<RawFrame<N>>(),
+        };
+        let mut control = ControlBuffer([0; CONTROL_LEN]);
+        let mut msg = meta::empty_msghdr();
+        msg.msg_name = (&mut addr as *mut RawCanAddr) as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<RawCanAddr>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.0.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = CONTROL_LEN as _;
+
+        let ret = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { RecvMeta::from_msghdr(&msg, addr.ifindex as u32) })
+    }
 }
@@ -0,0 +1,166 @@
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::{Sink, Stream};
+use tokio::io::unix::AsyncFd;
+
+use super::FrameStream;
+use crate::{meta::RecvMeta, socket, Frame};
+
+/// An asynchronous [`FrameStream`] driven by the Tokio reactor
+///
+/// The stream implements [`Stream`] for the received frames and [`Sink`] for the frames to send.
+/// The underlying socket is switched to non-blocking mode on creation.
+///
+/// Receiving is cancellation safe: every frame is read with a single `recv(2)` call, so dropping
+/// a pending [`recv_frame`] future or [`Stream::poll_next`] never loses a frame.
+///
+/// [`recv_frame`]: AsyncFrameStream::recv_frame
+///
+/// # Examples
+///
+/// ```no_run
+/// use can_rs::{stream::FrameStream, CAN_DATA_LEN};
+/// use futures::StreamExt;
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let mut stream = FrameStream::<CAN_DATA_LEN>::new("can0".parse().unwrap())
+///     .expect("failed to bind can0 frame stream")
+///     .into_async()?;
+///
+/// while let Some(frame) = stream.next().await {
+///     println!("{:?}", frame?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncFrameStream<const N: usize> {
+    inner: AsyncFd<FrameStream<N>>,
+    pending: Option<Frame<N>>,
+}
+
+impl<const N: usize> FrameStream<N> {
+    /// Converts the stream into an [`AsyncFrameStream`]. Must be called inside a Tokio runtime.
+    pub fn into_async(self) -> io::Result<AsyncFrameStream<N>> {
+        AsyncFrameStream::new(self)
+    }
+}
+
+impl<const N: usize> AsyncFrameStream<N> {
+    /// Registers the stream with the Tokio reactor. Must be called inside a Tokio runtime.
+    pub fn new(stream: FrameStream<N>) -> io::Result<Self> {
+        socket::set_nonblocking(&stream, true).map_err(io::Error::other)?;
+        Ok(Self {
+            inner: AsyncFd::new(stream)?,
+            pending: None,
+        })
+    }
+
+    /// Returns a reference to the underlying [`FrameStream`].
+    pub fn get_ref(&self) -> &FrameStream<N> {
+        self.inner.get_ref()
+    }
+
+    /// Deregisters the stream from the Tokio reactor. The socket is left in non-blocking mode.
+    pub fn into_inner(self) -> FrameStream<N> {
+        self.inner.into_inner()
+    }
+
+    /// Waits for the next frame. Cancellation safe.
+    pub async fn recv_frame(&self) -> io::Result<Frame<N>> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            if let Ok(result) = guard.try_io(|stream| stream.get_ref().recv_frame(0)) {
+                return result;
+            }
+        }
+    }
+
+    /// Waits for the next frame and its [`RecvMeta`]. Cancellation safe.
+    pub async fn recv_with_meta(&self) -> io::Result<(Frame<N>, RecvMeta)> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            if let Ok(result) =
+                guard.try_io(|stream| stream.get_ref().recv_with_meta(0))
+            {
+                return result;
+            }
+        }
+    }
+
+    /// Waits until the socket has room for the frame and sends it.
+    pub async fn send(&self, frame: &Frame<N>) -> io::Result<usize> {
+        loop {
+            let mut guard = self.inner.writable().await?;
+            if let Ok(result) = guard.try_io(|stream| stream.get_ref().send(frame, 0)) {
+                return result;
+            }
+        }
+    }
+
+    /// Polls for the next frame, registering the waker of `cx` if there is none yet.
+    pub fn poll_recv_frame(&self, cx: &mut Context<'_>) -> Poll<io::Result<Frame<N>>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+            if let Ok(result) = guard.try_io(|stream| stream.get_ref().recv_frame(0)) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+
+    fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(frame) = self.pending {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+            if let Ok(result) = guard.try_io(|stream| stream.get_ref().send(&frame, 0))
+            {
+                self.pending = None;
+                result?;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<const N: usize> Stream for AsyncFrameStream<N> {
+    type Item = io::Result<Frame<N>>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.poll_recv_frame(cx).map(Some)
+    }
+}
+
+impl<const N: usize> Sink<Frame<N>> for AsyncFrameStream<N> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Frame<N>) -> io::Result<()> {
+        let this = self.get_mut();
+        assert!(this.pending.is_none(), "`poll_ready` must be called first");
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+}
+
+impl<const N: usize> AsRawFd for AsyncFrameStream<N> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
@@ -0,0 +1,267 @@
+//! An in-process virtual CAN bus
+//!
+//! [`VirtualBus`] connects any number of [`VirtualSocket`]s without going through the kernel, so
+//! code written against [`FrameIo`] can be exercised where no `vcan` interface is available.
+//! Frames sent by a socket are delivered to every other socket of the bus whose filters accept
+//! them, matching the default behavior of `CAN_RAW` sockets: own frames are not echoed back, and
+//! error frames are only delivered to sockets that subscribed to them.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use can_rs::{stream::FrameIo, vbus::VirtualBus, Frame, Id, CAN_DATA_LEN};
+//!
+//! let bus = VirtualBus::<CAN_DATA_LEN>::new();
+//! let (a, b) = (bus.open(), bus.open());
+//!
+//! let frame = Frame {
+//!     id: Id::Standard(0x123),
+//!     flags: 0,
+//!     len: 2,
+//!     data: [0xAB; CAN_DATA_LEN],
+//! };
+//! a.transmit(&frame).unwrap();
+//! assert_eq!(b.receive(Some(Duration::from_secs(1))).unwrap(), frame);
+//! ```
+
+use std::{
+    io,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use crate::{
+    filter::{Filter, RawFilter},
+    stream::FrameIo,
+    Error, Frame, Id, CAN_RAW_FILTER_MAX,
+};
+
+/// Bits of a filter mask taken into account by the kernel.
+const FILTER_MASK: u32 = libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG | libc::CAN_EFF_MASK;
+
+/// A loopback bus shared by all the sockets opened from it
+///
+/// Cloning the bus returns another handle to the same bus.
+#[derive(Clone, Default)]
+pub struct VirtualBus<const N: usize> {
+    inner: Arc<Mutex<Nodes<N>>>,
+}
+
+struct Nodes<const N: usize> {
+    next_id: u64,
+    nodes: Vec<Node<N>>,
+}
+
+impl<const N: usize> Default for Nodes<N> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            nodes: Vec::new(),
+        }
+    }
+}
+
+struct Node<const N: usize> {
+    id: u64,
+    tx: Sender<Frame<N>>,
+    filters: Vec<RawFilter>,
+    error_mask: u32,
+}
+
+impl<const N: usize> Node<N> {
+    fn accepts(&self, id: Id) -> bool {
+        if let Id::Error(class) = id {
+            return class & self.error_mask != 0;
+        }
+        let id = id.wire_value();
+        self.filters.iter().any(|filter| {
+            let mask = filter.mask & FILTER_MASK;
+            id & mask == filter.id & mask
+        })
+    }
+}
+
+/// A socket attached to a [`VirtualBus`]
+///
+/// Like a fresh `CAN_RAW` socket it receives every frame until filters are set. It is detached
+/// from the bus when dropped.
+pub struct VirtualSocket<const N: usize> {
+    bus: VirtualBus<N>,
+    id: u64,
+    rx: Mutex<Receiver<Frame<N>>>,
+}
+
+impl<const N: usize> VirtualBus<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches a new socket to the bus.
+    pub fn open(&self) -> VirtualSocket<N> {
+        let (tx, rx) = mpsc::channel();
+        let mut nodes = self.lock();
+        let id = nodes.next_id;
+        nodes.next_id += 1;
+        nodes.nodes.push(Node {
+            id,
+            tx,
+            filters: vec![RawFilter::empty()],
+            error_mask: 0,
+        });
+        VirtualSocket {
+            bus: self.clone(),
+            id,
+            rx: Mutex::new(rx),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Nodes<N>> {
+        // A panic while holding the lock can't leave the nodes half updated.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn with_node<T>(&self, id: u64, f: impl FnOnce(&mut Node<N>) -> T) -> T {
+        let mut nodes = self.lock();
+        let node = nodes
+            .nodes
+            .iter_mut()
+            .find(|node| node.id == id)
+            .expect("virtual socket is attached to its bus");
+        f(node)
+    }
+}
+
+impl<const N: usize> VirtualSocket<N> {
+    /// Subscribes to the error frames of the given classes, see
+    /// [`FrameStreamBuilder::error_filter`](crate::stream::FrameStreamBuilder::error_filter).
+    pub fn set_error_filter(&self, mask: u32) {
+        self.bus.with_node(self.id, |node| node.error_mask = mask);
+    }
+
+    pub fn filters(&self) -> Vec<Filter> {
+        self.bus.with_node(self.id, |node| {
+            node.filters.iter().cloned().map(Filter::from).collect()
+        })
+    }
+
+    /// Returns the next frame if one is already queued.
+    pub fn try_receive(&self) -> Option<Frame<N>> {
+        self.rx
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .try_recv()
+            .ok()
+    }
+}
+
+impl<const N: usize> FrameIo<N> for VirtualSocket<N> {
+    fn transmit(&self, frame: &Frame<N>) -> io::Result<()> {
+        let nodes = self.bus.lock();
+        for node in &nodes.nodes {
+            if node.id != self.id && node.accepts(frame.id) {
+                // The receiving socket may be in the middle of being dropped.
+                let _ = node.tx.send(*frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> io::Result<Frame<N>> {
+        let rx = self.rx.lock().unwrap_or_else(|err| err.into_inner());
+        match timeout {
+            // The socket's own node holds a sender, so the channel can't disconnect.
+            None => Ok(rx.recv().expect("virtual socket is attached to its bus")),
+            Some(timeout) => rx.recv_timeout(timeout).map_err(|err| match err {
+                RecvTimeoutError::Timeout => io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for a CAN frame",
+                ),
+                RecvTimeoutError::Disconnected => {
+                    unreachable!("virtual socket is attached to its bus")
+                }
+            }),
+        }
+    }
+
+    fn set_filters(&self, filters: &[Filter]) -> Result<(), Error> {
+        if filters.len() > CAN_RAW_FILTER_MAX {
+            return Err(Error::CanFilterOverflow(filters.len()));
+        }
+        let filters = filters.iter().cloned().map(RawFilter::from).collect();
+        self.bus.with_node(self.id, |node| node.filters = filters);
+        Ok(())
+    }
+}
+
+impl<const N: usize> Drop for VirtualSocket<N> {
+    fn drop(&mut self) {
+        self.bus.lock().nodes.retain(|node| node.id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_frame::CAN_ERR_BUSOFF, CAN_DATA_LEN};
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_millis(50));
+
+    fn frame(id: Id) -> Frame<CAN_DATA_LEN> {
+        Frame {
+            id,
+            len: 1,
+            flags: 0,
+            data: [7; CAN_DATA_LEN],
+        }
+    }
+
+    #[test]
+    fn deliver_to_other_sockets() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let (a, b, c) = (bus.open(), bus.open(), bus.open());
+        a.transmit(&frame(Id::Standard(1))).unwrap();
+        assert_eq!(b.receive(TIMEOUT).unwrap(), frame(Id::Standard(1)));
+        assert_eq!(c.receive(TIMEOUT).unwrap(), frame(Id::Standard(1)));
+        assert_eq!(
+            a.receive(TIMEOUT).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        drop(c);
+        b.transmit(&frame(Id::Standard(2))).unwrap();
+        assert_eq!(a.receive(TIMEOUT).unwrap(), frame(Id::Standard(2)));
+    }
+
+    #[test]
+    fn filter_like_can_raw() {
+        let bus = VirtualBus::<CAN_DATA_LEN>::new();
+        let (tx, rx) = (bus.open(), bus.open());
+        rx.set_filters(&[Filter {
+            id: Id::Standard(0x120),
+            mask: libc::CAN_EFF_FLAG | 0x7F0,
+        }])
+        .unwrap();
+        for id in [
+            Id::Standard(0x100),
+            Id::Extended(0x123),
+            Id::Standard(0x123),
+            Id::Error(CAN_ERR_BUSOFF),
+        ] {
+            tx.transmit(&frame(id)).unwrap();
+        }
+        assert_eq!(rx.receive(TIMEOUT).unwrap().id, Id::Standard(0x123));
+        assert_eq!(rx.try_receive(), None);
+
+        rx.set_error_filter(CAN_ERR_BUSOFF);
+        rx.set_filters(&[]).unwrap();
+        tx.transmit(&frame(Id::Standard(0x123))).unwrap();
+        tx.transmit(&frame(Id::Error(CAN_ERR_BUSOFF))).unwrap();
+        assert_eq!(rx.receive(TIMEOUT).unwrap().id, Id::Error(CAN_ERR_BUSOFF));
+        assert_eq!(rx.try_receive(), None);
+    }
+}
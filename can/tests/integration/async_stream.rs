@@ -0,0 +1,32 @@
+use can_rs::filter::Filter;
+use can_rs::stream::FrameStream;
+use can_rs::{Error, Frame, Id, CAN_DATA_LEN};
+use futures::StreamExt;
+
+use crate::{can_address, ID};
+
+#[tokio::test]
+#[ignore = "needs vcan interface"]
+async fn send_and_receive_async_can_frame() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let mut rx = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?
+        .into_async()?;
+    let tx = FrameStream::<CAN_DATA_LEN>::new(can_address())?.into_async()?;
+
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: CAN_DATA_LEN as u8,
+        data: [17u8; CAN_DATA_LEN],
+    };
+    tx.send(&frame).await?;
+
+    let recv_frame = rx.next().await.unwrap()?;
+    assert_eq!(frame, recv_frame);
+    Ok(())
+}
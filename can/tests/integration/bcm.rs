@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use can_rs::bcm::{BcmEvent, BcmSocket, RxOptions};
+use can_rs::filter::Filter;
+use can_rs::stream::FrameStream;
+use can_rs::{Error, Frame, Id, CAN_DATA_LEN};
+
+use crate::{can_address, ID};
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn cyclic_transmission() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let stream = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?;
+    let bcm = BcmSocket::<CAN_DATA_LEN>::new(can_address())?;
+
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: CAN_DATA_LEN as u8,
+        data: [19u8; CAN_DATA_LEN],
+    };
+    let start = Instant::now();
+    bcm.start_cyclic(&frame, Duration::from_millis(10))?;
+    for _ in 0..3 {
+        assert_eq!(stream.recv_frame(0)?, frame);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    bcm.stop_cyclic(frame.id)?;
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn receive_changes_and_timeout() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let bcm = BcmSocket::<CAN_DATA_LEN>::new(can_address())?;
+    bcm.rx_setup(
+        Id::Standard(id),
+        &RxOptions {
+            mask: Some([0xFF; CAN_DATA_LEN]),
+            timeout: Some(Duration::from_millis(50)),
+            ..RxOptions::default()
+        },
+    )?;
+
+    let stream = FrameStream::<CAN_DATA_LEN>::new(can_address())?;
+    let mut frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: CAN_DATA_LEN as u8,
+        data: [20u8; CAN_DATA_LEN],
+    };
+    stream.send(&frame, 0)?;
+    stream.send(&frame, 0)?;
+    frame.data[0] = 21;
+    stream.send(&frame, 0)?;
+
+    assert!(matches!(bcm.recv()?, BcmEvent::Changed(f) if f.data[0] == 20));
+    assert_eq!(bcm.recv()?, BcmEvent::Changed(frame));
+    assert_eq!(bcm.recv()?, BcmEvent::Timeout(Id::Standard(id)));
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use std::time::{Duration, Instant};
+
+use can_rs::candump::{self, LogFrame, LogReader};
+use can_rs::filter::Filter;
+use can_rs::stream::FrameStream;
+use can_rs::{Error, Id, CAN_DATA_LEN};
+
+use crate::{can_address, ID};
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn replay_log() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let stream = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?;
+
+    let log = format!(
+        "(100.000000) can0 {id:03X}#01\n\
+         (100.020000) can0 {id:03X}#02\n\
+         (100.040000) can0 {id:03X}#03\n"
+    );
+    let entries = LogReader::new(log.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+    let start = Instant::now();
+    for entry in candump::replay(entries, can_address()) {
+        let LogFrame::Can(sent) = entry?.frame else {
+            unreachable!();
+        };
+        assert_eq!(stream.recv_frame(0)?, sent);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(40));
+    Ok(())
+}
@@ -1,6 +1,9 @@
+use can_rs::addr::try_string_to_ifindex;
+use can_rs::error_frame::{CAN_ERR_BUSOFF, CAN_ERR_MASK_ALL};
 use can_rs::filter::Filter;
+use can_rs::meta::Timestamping;
 use can_rs::stream::FrameStream;
-use can_rs::{Error, Frame, Id, CANFD_DATA_LEN, CAN_DATA_LEN, MTU};
+use can_rs::{netlink, Error, Frame, Id, CANFD_DATA_LEN, CAN_DATA_LEN, MTU};
 use core::time;
 use std::{sync::mpsc, thread};
 
@@ -138,3 +141,48 @@ fn set_too_many_sockets() {
         .bind(can_address())
         .unwrap();
 }
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn receive_with_meta() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let rx = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .timestamping(Timestamping::Software)
+        .rxq_overflow(true)
+        .bind(can_address())?;
+    let tx = FrameStream::<CAN_DATA_LEN>::build().bind(can_address())?;
+
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: CAN_DATA_LEN as u8,
+        data: [18u8; CAN_DATA_LEN],
+    };
+    tx.send(&frame, 0)?;
+
+    let (recv_frame, meta) = rx.recv_with_meta(0)?;
+    assert_eq!(frame, recv_frame);
+    assert_eq!(meta.ifindex, try_string_to_ifindex(&can_address().name)?);
+    assert!(meta.timestamp.is_some());
+    assert!(meta.local);
+    assert!(!meta.own);
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn subscribe_to_error_frames() -> Result<(), Error> {
+    let stream = FrameStream::<CAN_DATA_LEN>::build()
+        .error_filter(CAN_ERR_MASK_ALL)
+        .bind(can_address())?;
+    stream.set_error_filter(CAN_ERR_BUSOFF)?;
+
+    // Virtual interfaces don't report the bus state.
+    let status = netlink::bus_status(&can_address().name)?;
+    assert_eq!(status.state, None);
+    Ok(())
+}
@@ -20,3 +20,14 @@ fn write_isotp_stream() -> Result<(), Error> {
     assert_eq!(got, bytes.len());
     Ok(())
 }
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn write_userspace_isotp_stream() -> Result<(), Error> {
+    let mut stream =
+        IsotpStream::<CAN_DATA_LEN>::build().bind_userspace(isotp_address())?;
+    let bytes: Vec<u8> = vec![0, 1, 2, 4, 8, 16];
+    let got = stream.write(bytes.as_slice())?;
+    assert_eq!(got, bytes.len());
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use can_rs::j1939::{AddressClaim, J1939Addr, J1939Socket, Name, Pgn};
+use can_rs::Error;
+
+use crate::{can_address, ID};
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn send_to_address() -> Result<(), Error> {
+    let id = ID.with(|id| *id) as u8;
+    let pgn = Pgn(0x0C000);
+    let receiver = J1939Socket::bind(
+        can_address(),
+        J1939Addr {
+            pgn: Some(pgn),
+            addr: Some(0x20 + id),
+            ..J1939Addr::default()
+        },
+    )?;
+    let sender = J1939Socket::bind(
+        can_address(),
+        J1939Addr {
+            addr: Some(0x60 + id),
+            ..J1939Addr::default()
+        },
+    )?;
+
+    let message = (0..100).collect::<Vec<u8>>();
+    sender.send_to(
+        &message,
+        &J1939Addr {
+            pgn: Some(pgn),
+            addr: Some(0x20 + id),
+            ..J1939Addr::default()
+        },
+    )?;
+    let mut buf = [0u8; 256];
+    let (len, src) = receiver.recv_from(&mut buf)?;
+    assert_eq!(&buf[..len], message);
+    assert_eq!(src.addr, Some(0x60 + id));
+    assert_eq!(src.pgn, Some(pgn));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn claim_address() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let socket = J1939Socket::bind(
+        can_address(),
+        J1939Addr {
+            name: Some(Name(0x1000 + u64::from(id))),
+            addr: Some(0xA0 + id as u8),
+            ..J1939Addr::default()
+        },
+    )?;
+    assert_eq!(
+        socket.claim_address()?,
+        AddressClaim::Claimed(0xA0 + id as u8)
+    );
+    Ok(())
+}
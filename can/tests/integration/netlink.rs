@@ -0,0 +1,29 @@
+use can_rs::netlink::{Interface, LinkConfig};
+use can_rs::{Error, CAN_MTU};
+
+use crate::CAN_ADDRESS_RAW;
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn query_interface() -> Result<(), Error> {
+    let info = Interface::new(CAN_ADDRESS_RAW)?.info()?;
+    assert!(info.up);
+    assert_eq!(info.mtu, Some(CAN_MTU as u32));
+    // Virtual interfaces have no bit timing.
+    assert_eq!(info.bitrate, None);
+    assert!(info.stats.is_some());
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface and CAP_NET_ADMIN"]
+fn set_interface_down_and_up() -> Result<(), Error> {
+    let interface = Interface::new(CAN_ADDRESS_RAW)?;
+    interface.set_down()?;
+    assert!(!interface.info()?.up);
+    // Nothing to change.
+    interface.configure(&LinkConfig::default())?;
+    interface.set_up()?;
+    assert!(interface.info()?.up);
+    Ok(())
+}
@@ -2,7 +2,6 @@ use async_trait::async_trait;
 use can_rs::filter::Filter;
 use can_rs::stream::FrameStream;
 use can_rs::{Frame, Id, CANFD_DATA_LEN};
-use color_eyre::eyre::{eyre, Context, Result};
 use futures::FutureExt as _;
 use orb_messages::CommonAckError;
 use prost::Message;
@@ -15,8 +14,8 @@ use tracing::{debug, trace};
 
 use crate::Device::{JetsonFromMain, JetsonFromSecurity, Main, Security};
 use crate::{
-    create_ack, handle_main_mcu_message, handle_sec_mcu_message, Device, McuPayload,
-    MessagingInterface,
+    create_ack, handle_main_mcu_message, handle_sec_mcu_message, Device, Error,
+    McuPayload, MessagingInterface,
 };
 
 use super::{CanTaskHandle, CanTaskJoinError, CanTaskPanic, ACK_RX_TIMEOUT};
@@ -40,7 +39,7 @@ impl CanRawMessaging {
         bus: String,
         can_node: Device,
         new_message_queue: mpsc::UnboundedSender<McuPayload>,
-    ) -> Result<(Self, CanTaskHandle)> {
+    ) -> Result<(Self, CanTaskHandle), Error> {
         let filters = match can_node {
             Main => vec![Filter {
                 id: Id::Extended(JetsonFromMain as u32),
@@ -50,15 +49,14 @@ impl CanRawMessaging {
                 id: Id::Extended(JetsonFromSecurity as u32),
                 mask: 0xff,
             }],
-            _ => return Err(eyre!("Invalid CAN node: {:?}", can_node)),
+            _ => return Err(Error::InvalidDevice(can_node)),
         };
 
         // open socket
         let stream = FrameStream::<CANFD_DATA_LEN>::build()
             .nonblocking(false)
             .filters(filters)
-            .bind(bus.as_str().parse().unwrap())
-            .wrap_err("Failed to bind CAN stream")?;
+            .bind(bus.as_str().parse()?)?;
 
         let (ack_tx, ack_rx) = mpsc::unbounded_channel();
         let (kill_tx, kill_rx) = oneshot::channel();
@@ -100,7 +98,10 @@ impl CanRawMessaging {
         ))
     }
 
-    async fn wait_ack(&mut self, expected_ack_number: u32) -> Result<CommonAckError> {
+    async fn wait_ack(
+        &mut self,
+        expected_ack_number: u32,
+    ) -> Result<CommonAckError, Error> {
         let recv_fut = async {
             while let Some((ack, number)) = self.ack_queue.recv().await {
                 if number == expected_ack_number {
@@ -108,33 +109,30 @@ impl CanRawMessaging {
                 }
             }
 
-            Err(eyre!("ack queue closed"))
+            Err(Error::TransportClosed)
         };
         timeout(ACK_RX_TIMEOUT, recv_fut)
-            .map(|result| result?)
+            .map(|result| result.unwrap_or(Err(Error::AckTimeout)))
             .await
-            .wrap_err("ack not received (raw)")
     }
 
     async fn send_wait_ack(
         &mut self,
         frame: Arc<Frame<CANFD_DATA_LEN>>,
         ack_number: u32,
-    ) -> Result<CommonAckError> {
+    ) -> Result<CommonAckError, Error> {
         let stream = self.stream.try_clone()?;
         tokio::task::spawn_blocking(move || {
-            let nbytes_written = stream
-                .send(&frame, 0)
-                .wrap_err("error while writing to canfd stream")?;
+            let nbytes_written = stream.send(&frame, 0)?;
             trace!(
                 "wrote {nbytes_written} bytes, for frame.len = {}, frame.data.len = {}",
                 frame.len,
                 frame.data.len(),
             );
-            Ok::<(), color_eyre::Report>(())
+            Ok::<(), std::io::Error>(())
         })
         .await
-        .wrap_err("send_wait_ack task panicked")??;
+        .map_err(std::io::Error::from)??;
 
         self.wait_ack(ack_number).await
     }
@@ -149,7 +147,7 @@ fn can_rx(
     ack_tx: mpsc::UnboundedSender<(CommonAckError, u32)>,
     new_message_queue: mpsc::UnboundedSender<McuPayload>,
     mut kill_rx: oneshot::Receiver<()>,
-) -> Result<()> {
+) -> Result<(), Error> {
     loop {
         let mut frame: Frame<CANFD_DATA_LEN> = Frame::empty();
         loop {
@@ -166,7 +164,7 @@ fn can_rx(
                     break;
                 }
                 Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
-                Err(err) => return Err(eyre!("failed to read: {}", err)),
+                Err(err) => return Err(err.into()),
             }
         }
         let status = match remote_node {
@@ -175,25 +173,20 @@ fn can_rx(
                     &frame.data[0..frame.len as usize],
                 )?;
                 handle_main_mcu_message(&message, &ack_tx, &new_message_queue)
-                    .wrap_err_with(|| "remote: main mcu")
             }
             Security => {
                 let message = orb_messages::McuMessage::decode_length_delimited(
                     &frame.data[0..frame.len as usize],
                 )?;
                 handle_sec_mcu_message(&message, &ack_tx, &new_message_queue)
-                    .wrap_err_with(|| "remote: security mcu")
             }
-            JetsonFromMain => Err(eyre!(
-                "JetsonFromMain is not a valid destination for receiving messages"
-            )),
-            JetsonFromSecurity => Err(eyre!(
-                "JetsonFromSecurity is not a valid destination for receiving messages"
-            )),
+            JetsonFromMain | JetsonFromSecurity => {
+                Err(Error::InvalidDevice(remote_node))
+            }
         };
 
         if let Err(e) = status {
-            debug!("Error handling message: {:#}", e);
+            debug!("Error handling message from {:?}: {:#}", remote_node, e);
         }
     }
 }
@@ -201,7 +194,7 @@ fn can_rx(
 #[async_trait]
 impl MessagingInterface for CanRawMessaging {
     /// Send payload into McuMessage
-    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError> {
+    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error> {
         let ack_number = create_ack(self.ack_num_lsb.fetch_add(1, Ordering::SeqCst));
 
         let bytes = match self.can_node {
@@ -217,9 +210,9 @@ impl MessagingInterface for CanRawMessaging {
                         )),
                     }
                 } else {
-                    return Err(eyre!("Invalid payload type for main mcu node"));
+                    return Err(Error::InvalidPayload(Box::new(payload)));
                 };
-                Some(to_encode.encode_length_delimited_to_vec())
+                to_encode.encode_length_delimited_to_vec()
             }
             Security => {
                 let to_encode = if let McuPayload::ToSec(p) = payload {
@@ -235,37 +228,26 @@ impl MessagingInterface for CanRawMessaging {
                         ),
                     }
                 } else {
-                    return Err(eyre!("Invalid payload type for security mcu node"));
+                    return Err(Error::InvalidPayload(Box::new(payload)));
                 };
-                Some(to_encode.encode_length_delimited_to_vec())
-            }
-            JetsonFromMain => {
-                return Err(eyre!(
-                    "JetsonFromMain is not a valid destination for sending messages"
-                ));
+                to_encode.encode_length_delimited_to_vec()
             }
-            JetsonFromSecurity => {
-                return Err(eyre!(
-                    "JetsonFromSecurity is not a valid destination for sending messages"
-                ));
+            device @ (JetsonFromMain | JetsonFromSecurity) => {
+                return Err(Error::InvalidDevice(device));
             }
         };
 
-        if let Some(bytes) = bytes {
-            let mut buf = [0u8; CANFD_DATA_LEN];
-            buf[..bytes.len()].copy_from_slice(bytes.as_slice());
+        let mut buf = [0u8; CANFD_DATA_LEN];
+        buf[..bytes.len()].copy_from_slice(bytes.as_slice());
 
-            let node_addr = self.can_node as u32;
-            let frame = Frame {
-                id: Id::Extended(node_addr),
-                len: bytes.len() as u8,
-                flags: can_rs::CANFD_BRS_FLAG | can_rs::CANFD_FDF_FLAG,
-                data: buf,
-            };
+        let node_addr = self.can_node as u32;
+        let frame = Frame {
+            id: Id::Extended(node_addr),
+            len: bytes.len() as u8,
+            flags: can_rs::CANFD_BRS_FLAG | can_rs::CANFD_FDF_FLAG,
+            data: buf,
+        };
 
-            self.send_wait_ack(Arc::new(frame), ack_number).await
-        } else {
-            Err(eyre!("Failed to encode payload"))
-        }
+        self.send_wait_ack(Arc::new(frame), ack_number).await
     }
 }
@@ -1,5 +1,4 @@
 use async_trait::async_trait;
-use color_eyre::eyre::{eyre, Context, Result};
 use futures::FutureExt as _;
 use orb_messages::CommonAckError;
 use prost::Message;
@@ -16,7 +15,7 @@ use can_rs::{Id, CAN_DATA_LEN};
 
 use crate::can::CanTaskPanic;
 use crate::{
-    create_ack, handle_main_mcu_message, handle_sec_mcu_message, McuPayload,
+    create_ack, handle_main_mcu_message, handle_sec_mcu_message, Error, McuPayload,
     MessagingInterface,
 };
 
@@ -79,14 +78,11 @@ pub struct CanIsoTpMessaging {
 }
 
 /// Create ISO-TP pair of addresses, based on our addressing scheme
-fn create_pair(
-    src: IsoTpNodeIdentifier,
-    dest: IsoTpNodeIdentifier,
-) -> Result<(u32, u32)> {
-    Ok((
+fn create_pair(src: IsoTpNodeIdentifier, dest: IsoTpNodeIdentifier) -> (u32, u32) {
+    (
         CAN_ADDR_IS_ISOTP | (src as u32) << 4 | dest as u32,
         CAN_ADDR_IS_DEST | CAN_ADDR_IS_ISOTP | (src as u32) << 4 | dest as u32,
-    ))
+    )
 }
 
 impl CanIsoTpMessaging {
@@ -106,21 +102,17 @@ impl CanIsoTpMessaging {
         local: IsoTpNodeIdentifier,
         remote: IsoTpNodeIdentifier,
         new_message_queue: mpsc::UnboundedSender<McuPayload>,
-    ) -> Result<(Self, CanTaskHandle)> {
-        let (tx_stdid_src, tx_stdid_dst) = create_pair(local, remote)?;
+    ) -> Result<(Self, CanTaskHandle), Error> {
+        let (tx_stdid_src, tx_stdid_dst) = create_pair(local, remote);
         debug!("Sending on 0x{:x}->0x{:x}", tx_stdid_src, tx_stdid_dst);
 
         // open TX stream
-        let tx_isotp_stream = IsotpStream::<CAN_DATA_LEN>::build()
-            .bind(
-                CanIsotpAddr::new(
-                    bus.as_str(),
-                    Id::Standard(tx_stdid_dst),
-                    Id::Standard(tx_stdid_src),
-                )
-                .expect("Unable to build IsoTpStream"),
-            )
-            .wrap_err("Failed to bind CAN ISO-TP stream")?;
+        let tx_isotp_stream =
+            IsotpStream::<CAN_DATA_LEN>::build().bind(CanIsotpAddr::new(
+                bus.as_str(),
+                Id::Standard(tx_stdid_dst),
+                Id::Standard(tx_stdid_src),
+            )?)?;
 
         let (ack_tx, ack_rx) = mpsc::unbounded_channel();
         let (kill_tx, kill_rx) = oneshot::channel();
@@ -160,7 +152,10 @@ impl CanIsoTpMessaging {
         ))
     }
 
-    async fn wait_ack(&mut self, expected_ack_number: u32) -> Result<CommonAckError> {
+    async fn wait_ack(
+        &mut self,
+        expected_ack_number: u32,
+    ) -> Result<CommonAckError, Error> {
         let recv_fut = async {
             while let Some((ack, number)) = self.ack_queue.recv().await {
                 if number == expected_ack_number {
@@ -168,25 +163,22 @@ impl CanIsoTpMessaging {
                 }
             }
 
-            Err(eyre!("ack queue closed"))
+            Err(Error::TransportClosed)
         };
         timeout(ACK_RX_TIMEOUT, recv_fut)
-            .map(|result| result?)
+            .map(|result| result.unwrap_or(Err(Error::AckTimeout)))
             .await
-            .wrap_err("ack not received (isotp)")
     }
 
     async fn send_wait_ack(
         &mut self,
         frame: Vec<u8>,
         ack_number: u32,
-    ) -> Result<CommonAckError> {
+    ) -> Result<CommonAckError, Error> {
         let mut stream = self.stream.try_clone()?;
-        tokio::task::spawn_blocking(move || -> Result<()> {
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
             let bytes = frame.as_slice();
-            let nbytes_written = stream
-                .write(bytes)
-                .wrap_err("error while writing to isotp stream")?;
+            let nbytes_written = stream.write(bytes)?;
             trace!(
                 "wrote {nbytes_written} bytes, for frame of length {}",
                 frame.len()
@@ -194,7 +186,7 @@ impl CanIsoTpMessaging {
             Ok(())
         })
         .await
-        .wrap_err("send_wait_ack task panicked")??;
+        .map_err(std::io::Error::from)??;
 
         self.wait_ack(ack_number).await
     }
@@ -210,19 +202,17 @@ fn can_rx(
     ack_tx: mpsc::UnboundedSender<(CommonAckError, u32)>,
     new_message_queue: mpsc::UnboundedSender<McuPayload>,
     mut kill_rx: oneshot::Receiver<()>,
-) -> Result<()> {
+) -> Result<(), Error> {
     // rx messages <=> from remote to local
-    let (rx_stdid_src, rx_stdid_dest) = create_pair(remote, local)?;
+    let (rx_stdid_src, rx_stdid_dest) = create_pair(remote, local);
     debug!("Listening on 0x{:x}->0x{:x}", rx_stdid_src, rx_stdid_dest);
 
-    let mut rx_isotp_stream = IsotpStream::<CAN_DATA_LEN>::build().bind(
-        CanIsotpAddr::new(
+    let mut rx_isotp_stream =
+        IsotpStream::<CAN_DATA_LEN>::build().bind(CanIsotpAddr::new(
             bus.as_str(),
             Id::Standard(rx_stdid_src),
             Id::Standard(rx_stdid_dest),
-        )
-        .expect("Unable to build IsoTpAddr"),
-    )?;
+        )?)?;
 
     loop {
         let mut buffer = [0; 1024];
@@ -249,20 +239,18 @@ fn can_rx(
                     buffer.as_slice(),
                 )?;
                 handle_main_mcu_message(&message, &ack_tx, &new_message_queue)
-                    .wrap_err_with(|| "remote: main mcu")
             }
             IsoTpNodeIdentifier::SecurityMcu => {
                 let message = orb_messages::McuMessage::decode_length_delimited(
                     buffer.as_slice(),
                 )?;
                 handle_sec_mcu_message(&message, &ack_tx, &new_message_queue)
-                    .wrap_err_with(|| "remote: security mcu")
             }
-            _ => Err(eyre!("Invalid destination: {:?}", local)),
+            _ => Err(Error::UnsupportedIsoTpNode(remote)),
         };
 
         if let Err(e) = status {
-            debug!("Error handling message: {:#}", e);
+            debug!("Error handling message from {:?}: {:#}", remote, e);
         }
     }
 }
@@ -271,7 +259,7 @@ fn can_rx(
 impl MessagingInterface for CanIsoTpMessaging {
     /// Send payload into McuMessage
     /// One could decide to only listen for ISO-TP message so allow dead code for `send` method
-    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError> {
+    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error> {
         let ack_number = create_ack(self.ack_num_lsb.fetch_add(1, Ordering::SeqCst));
 
         let bytes = match payload {
@@ -301,7 +289,9 @@ impl MessagingInterface for CanIsoTpMessaging {
                 };
                 to_encode.encode_length_delimited_to_vec()
             }
-            _ => return Err(eyre!("Invalid payload")),
+            payload @ (McuPayload::FromMain(_) | McuPayload::FromSec(_)) => {
+                return Err(Error::InvalidPayload(Box::new(payload)))
+            }
         };
 
         self.send_wait_ack(bytes, ack_number).await
@@ -6,7 +6,7 @@ use tokio::sync::oneshot;
 pub mod canfd;
 pub mod isotp;
 
-const ACK_RX_TIMEOUT: Duration = Duration::from_millis(1500);
+pub(crate) const ACK_RX_TIMEOUT: Duration = Duration::from_millis(1500);
 
 pub type CanTaskResult = Result<(), CanTaskJoinError>;
 
@@ -15,7 +15,7 @@ pub type CanTaskResult = Result<(), CanTaskJoinError>;
 /// Note that dropping this handle doesn't kill the task.
 #[pin_project]
 #[derive(Debug)]
-pub struct CanTaskHandle(#[pin] oneshot::Receiver<CanTaskResult>);
+pub struct CanTaskHandle(#[pin] pub(crate) oneshot::Receiver<CanTaskResult>);
 
 impl std::future::Future for CanTaskHandle {
     type Output = CanTaskResult;
@@ -50,7 +50,7 @@ pub enum CanTaskJoinError {
     #[error(transparent)]
     Panic(#[from] CanTaskPanic),
     #[error(transparent)]
-    Err(#[from] color_eyre::Report),
+    Err(#[from] crate::Error),
 }
 
 #[derive(thiserror::Error)]
@@ -59,7 +59,7 @@ pub enum CanTaskJoinError {
 pub struct CanTaskPanic(std::sync::Mutex<Box<dyn Any + Send + 'static>>);
 
 impl CanTaskPanic {
-    fn new(err: Box<dyn Any + Send + 'static>) -> Self {
+    pub(crate) fn new(err: Box<dyn Any + Send + 'static>) -> Self {
         Self(std::sync::Mutex::new(err))
     }
 
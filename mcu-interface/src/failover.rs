@@ -0,0 +1,325 @@
+//! Messaging over the best available transport, with failover
+//!
+//! The MCUs can be reached over CAN-FD, ISO-TP and UART. [`FailoverMessaging`] opens all the
+//! transports it can, sends over the first one the MCU answers on and moves on to the next one
+//! when a message can't be delivered, e.g. when its ack times out after
+//! `ACK_RX_TIMEOUT`.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use orb_messages::CommonAckError;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::can::canfd::CanRawMessaging;
+use crate::can::isotp::{CanIsoTpMessaging, IsoTpNodeIdentifier};
+use crate::can::CanTaskHandle;
+use crate::serial::SerialMessaging;
+use crate::{Device, Error, McuPayload, MessagingInterface};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+    CanFd,
+    IsoTp,
+    Serial,
+}
+
+impl Transport {
+    /// Transports in order of preference.
+    pub const PREFERENCE: [Transport; 3] =
+        [Transport::CanFd, Transport::IsoTp, Transport::Serial];
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::CanFd => write!(f, "can-fd"),
+            Transport::IsoTp => write!(f, "iso-tp"),
+            Transport::Serial => write!(f, "serial"),
+        }
+    }
+}
+
+/// Messaging interface boxed for [`FailoverMessaging::with_interfaces`].
+pub type BoxedInterface = Box<dyn MessagingInterface + Send>;
+
+/// [`MessagingInterface`] sending over one of several transports
+///
+/// All the opened transports keep receiving and push incoming messages to the same queue.
+///
+/// Note that a message whose ack got lost is sent again over the next transport, so the MCU
+/// may receive it twice.
+pub struct FailoverMessaging {
+    device: Device,
+    interfaces: Vec<(Transport, BoxedInterface)>,
+    active: usize,
+}
+
+impl FailoverMessaging {
+    /// Opens the given `transports` to `device` and picks the active one with
+    /// [`FailoverMessaging::with_interfaces`].
+    ///
+    /// `bus` is the CAN bus used by the CAN transports, and `local` the ISO-TP address of
+    /// this program. The `Versions` answering the probe end up in `new_message_queue`.
+    ///
+    /// Fails with the error of the last transport if none of them could be opened, or with
+    /// [`Error::TransportClosed`] if `transports` is empty. Otherwise returns the handles of
+    /// the tasks receiving on each opened transport.
+    pub async fn new(
+        bus: &str,
+        device: Device,
+        local: IsoTpNodeIdentifier,
+        transports: &[Transport],
+        new_message_queue: mpsc::UnboundedSender<McuPayload>,
+    ) -> Result<(Self, Vec<(Transport, CanTaskHandle)>), Error> {
+        let remote = match device {
+            Device::Main => IsoTpNodeIdentifier::MainMcu,
+            Device::Security => IsoTpNodeIdentifier::SecurityMcu,
+            Device::JetsonFromMain | Device::JetsonFromSecurity => {
+                return Err(Error::InvalidDevice(device));
+            }
+        };
+
+        let mut interfaces = Vec::new();
+        let mut task_handles = Vec::new();
+        let mut last_err = None;
+        for &transport in transports {
+            let opened = match transport {
+                Transport::CanFd => CanRawMessaging::new(
+                    bus.to_owned(),
+                    device,
+                    new_message_queue.clone(),
+                )
+                .map(|(iface, handle)| (Box::new(iface) as BoxedInterface, handle)),
+                Transport::IsoTp => CanIsoTpMessaging::new(
+                    bus.to_owned(),
+                    local,
+                    remote,
+                    new_message_queue.clone(),
+                )
+                .map(|(iface, handle)| (Box::new(iface) as BoxedInterface, handle)),
+                Transport::Serial => {
+                    SerialMessaging::new(device, new_message_queue.clone()).map(
+                        |(iface, handle)| (Box::new(iface) as BoxedInterface, handle),
+                    )
+                }
+            };
+            match opened {
+                Ok((iface, handle)) => {
+                    debug!("{transport} transport to {device:?} opened");
+                    interfaces.push((transport, iface));
+                    task_handles.push((transport, handle));
+                }
+                Err(e) => {
+                    warn!("{transport} transport to {device:?} unavailable: {e:#}");
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        if interfaces.is_empty() {
+            return Err(last_err.unwrap_or(Error::TransportClosed));
+        }
+        let messaging = Self::with_interfaces(device, interfaces).await?;
+
+        Ok((messaging, task_handles))
+    }
+
+    /// Sends over already opened `interfaces` to `device`, given in order of preference.
+    ///
+    /// The interfaces are probed in order with a `FirmwareVersions` request. The first one
+    /// over which the request is acked becomes the active transport, or the first one if the
+    /// MCU doesn't ack any.
+    ///
+    /// Fails with [`Error::TransportClosed`] if `interfaces` is empty.
+    pub async fn with_interfaces(
+        device: Device,
+        mut interfaces: Vec<(Transport, BoxedInterface)>,
+    ) -> Result<Self, Error> {
+        let value_get = orb_messages::ValueGet {
+            value: orb_messages::value_get::Value::FirmwareVersions as i32,
+        };
+        let probe = match device {
+            Device::Main => McuPayload::ToMain(
+                orb_messages::main::jetson_to_mcu::Payload::ValueGet(value_get),
+            ),
+            Device::Security => McuPayload::ToSec(
+                orb_messages::sec::jetson_to_sec::Payload::ValueGet(value_get),
+            ),
+            Device::JetsonFromMain | Device::JetsonFromSecurity => {
+                return Err(Error::InvalidDevice(device));
+            }
+        };
+        if interfaces.is_empty() {
+            return Err(Error::TransportClosed);
+        }
+
+        let mut active = None;
+        for (i, (transport, iface)) in interfaces.iter_mut().enumerate() {
+            match iface.send(probe.clone()).await {
+                Ok(ack) => {
+                    debug!("{transport} transport to {device:?} acked probe: {ack:?}");
+                    active = Some(i);
+                    break;
+                }
+                Err(e) => {
+                    warn!("{transport} transport to {device:?} failed probe: {e}")
+                }
+            }
+        }
+        let active = active.unwrap_or_else(|| {
+            warn!("{device:?} didn't answer on any transport");
+            0
+        });
+        info!("sending to {:?} over {}", device, interfaces[active].0);
+
+        Ok(Self {
+            device,
+            interfaces,
+            active,
+        })
+    }
+
+    /// Transport currently used to send messages.
+    pub fn active_transport(&self) -> Transport {
+        self.interfaces[self.active].0
+    }
+
+    /// Transports that were opened, in order of preference.
+    pub fn transports(&self) -> impl Iterator<Item = Transport> + '_ {
+        self.interfaces.iter().map(|(transport, _)| *transport)
+    }
+}
+
+#[async_trait]
+impl MessagingInterface for FailoverMessaging {
+    /// Sends over the active transport, and over the next ones in turn if it fails with a
+    /// [transport error](Error::is_transport).
+    ///
+    /// The transport that delivered the message stays active for the next messages.
+    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error> {
+        // don't blame the transports for an invalid payload
+        match (self.device, &payload) {
+            (Device::Main, McuPayload::ToMain(_))
+            | (Device::Security, McuPayload::ToSec(_)) => {}
+            _ => return Err(Error::InvalidPayload(Box::new(payload))),
+        }
+
+        let mut last_err = None;
+        for _ in 0..self.interfaces.len() {
+            let (failed, iface) = &mut self.interfaces[self.active];
+            match iface.send(payload.clone()).await {
+                Ok(ack) => return Ok(ack),
+                Err(e) if !e.is_transport() => return Err(e),
+                Err(e) => {
+                    let failed = *failed;
+                    self.active = (self.active + 1) % self.interfaces.len();
+                    warn!(
+                        "sending over {} failed, switching to {}: {:#}",
+                        failed,
+                        self.active_transport(),
+                        e
+                    );
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        Err(last_err.expect("at least one transport"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::simulated::SimulatedMcu;
+
+    /// Link answering with scripted results, then timing out.
+    struct Link(VecDeque<Result<CommonAckError, Error>>);
+
+    #[async_trait]
+    impl MessagingInterface for Link {
+        async fn send(
+            &mut self,
+            _payload: McuPayload,
+        ) -> Result<CommonAckError, Error> {
+            self.0.pop_front().unwrap_or(Err(Error::AckTimeout))
+        }
+    }
+
+    fn link(
+        results: impl IntoIterator<Item = Result<CommonAckError, Error>>,
+    ) -> BoxedInterface {
+        Box::new(Link(results.into_iter().collect()))
+    }
+
+    fn simulated(queue: &mpsc::UnboundedSender<McuPayload>) -> BoxedInterface {
+        Box::new(SimulatedMcu::new(Device::Main, queue.clone()).unwrap())
+    }
+
+    fn reboot() -> McuPayload {
+        McuPayload::ToMain(orb_messages::main::jetson_to_mcu::Payload::Reboot(
+            orb_messages::RebootWithDelay { delay: 1 },
+        ))
+    }
+
+    #[tokio::test]
+    async fn skip_unacked_transport() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let messaging = FailoverMessaging::with_interfaces(
+            Device::Main,
+            vec![
+                (Transport::CanFd, link([])),
+                (Transport::IsoTp, simulated(&tx)),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(messaging.active_transport(), Transport::IsoTp);
+    }
+
+    #[tokio::test]
+    async fn pick_first_acked_transport() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let messaging = FailoverMessaging::with_interfaces(
+            Device::Main,
+            vec![
+                (Transport::CanFd, simulated(&tx)),
+                (Transport::IsoTp, simulated(&tx)),
+                (Transport::Serial, link([])),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(messaging.active_transport(), Transport::CanFd);
+        assert_eq!(
+            messaging.transports().collect::<Vec<_>>(),
+            Transport::PREFERENCE
+        );
+    }
+
+    #[tokio::test]
+    async fn switch_transport_on_error() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut messaging = FailoverMessaging::with_interfaces(
+            Device::Main,
+            vec![
+                // acks the probe, then loses the connection
+                (Transport::CanFd, link([Ok(CommonAckError::Success)])),
+                (Transport::IsoTp, simulated(&tx)),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(messaging.active_transport(), Transport::CanFd);
+
+        assert_eq!(
+            messaging.send(reboot()).await.unwrap(),
+            CommonAckError::Success
+        );
+        assert_eq!(messaging.active_transport(), Transport::IsoTp);
+    }
+}
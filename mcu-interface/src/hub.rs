@@ -0,0 +1,300 @@
+//! Fan out of incoming MCU messages to several consumers
+//!
+//! The messaging interfaces push every unsolicited message into a single queue.
+//! [`MessageHub`] takes the receiving end of that queue and dispatches each message to the
+//! [`Subscription`]s interested in its [`PayloadKind`], so that several consumers can share
+//! the same interface.
+//!
+//! Each subscription has its own bounded queue: a slow subscriber doesn't hold back the
+//! others, it misses messages instead and is told how many with [`RecvError::Lagged`].
+//!
+//! A [`McuClient`] can get its responses through a subscription as well, see
+//! [`McuClient::with_subscription`].
+//!
+//! [`McuClient`]: crate::request::McuClient
+//! [`McuClient::with_subscription`]: crate::request::McuClient::with_subscription
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use orb_messages::main::mcu_to_jetson::Payload as MainPayload;
+use orb_messages::sec::sec_to_jetson::Payload as SecPayload;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, trace};
+
+use crate::McuPayload;
+
+/// Category of an incoming payload, used to subscribe
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PayloadKind {
+    Log,
+    Versions,
+    Hardware,
+    Battery,
+    Temperature,
+    FanStatus,
+    Imu,
+    /// Any payload that doesn't fall in the categories above.
+    Other,
+}
+
+impl PayloadKind {
+    pub fn of(payload: &McuPayload) -> Self {
+        match payload {
+            McuPayload::FromMain(payload) => match payload {
+                MainPayload::Log(_) => Self::Log,
+                MainPayload::Versions(_) => Self::Versions,
+                MainPayload::Hardware(_) => Self::Hardware,
+                MainPayload::BatteryCapacity(_)
+                | MainPayload::BatteryVoltage(_)
+                | MainPayload::BatteryIsCharging(_) => Self::Battery,
+                MainPayload::Temperature(_) => Self::Temperature,
+                MainPayload::FanStatus(_) => Self::FanStatus,
+                MainPayload::ImuData(_) => Self::Imu,
+                _ => Self::Other,
+            },
+            McuPayload::FromSec(payload) => match payload {
+                SecPayload::Log(_) => Self::Log,
+                SecPayload::Versions(_) => Self::Versions,
+                SecPayload::BatteryStatus(_) => Self::Battery,
+                _ => Self::Other,
+            },
+            McuPayload::ToMain(_) | McuPayload::ToSec(_) => Self::Other,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum RecvError {
+    /// The subscriber didn't keep up, the given number of messages were dropped.
+    #[error("subscriber lagged behind, {0} messages dropped")]
+    Lagged(u64),
+    /// The hub or the messaging interface feeding it is gone.
+    #[error("message hub closed")]
+    Closed,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    list: Vec<Subscriber>,
+    /// Set once the queue is closed, new subscribers are then closed right away.
+    closed: bool,
+}
+
+struct Subscriber {
+    /// `None` to receive every payload.
+    kinds: Option<Vec<PayloadKind>>,
+    tx: mpsc::Sender<McuPayload>,
+    lagged: Arc<AtomicU64>,
+}
+
+/// Dispatches the messages of a queue to its subscribers
+///
+/// The dispatching task stops when the hub is dropped or when the queue is closed.
+pub struct MessageHub {
+    subscribers: Arc<Mutex<Subscribers>>,
+    task: JoinHandle<()>,
+}
+
+/// Receiving end of a subscription to a [`MessageHub`]
+///
+/// Dropping it unsubscribes.
+#[derive(Debug)]
+pub struct Subscription {
+    rx: mpsc::Receiver<McuPayload>,
+    lagged: Arc<AtomicU64>,
+}
+
+impl MessageHub {
+    /// Starts dispatching `message_queue`, the receiving end of the queue given to a
+    /// messaging interface.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(mut message_queue: mpsc::UnboundedReceiver<McuPayload>) -> Self {
+        let subscribers: Arc<Mutex<Subscribers>> = Arc::default();
+        let task = tokio::task::spawn({
+            let subscribers = Arc::clone(&subscribers);
+            async move {
+                while let Some(payload) = message_queue.recv().await {
+                    dispatch(&mut lock(&subscribers).list, payload);
+                }
+                debug!("message queue closed, stopping message hub");
+                let mut subscribers = lock(&subscribers);
+                subscribers.closed = true;
+                // closes the subscriptions
+                subscribers.list.clear();
+            }
+        });
+
+        Self { subscribers, task }
+    }
+
+    /// Subscribes to the payloads of the given kinds, buffering up to `capacity` of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn subscribe(&self, kinds: &[PayloadKind], capacity: usize) -> Subscription {
+        self.add_subscriber(Some(kinds.to_vec()), capacity)
+    }
+
+    /// Subscribes to every payload, buffering up to `capacity` of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn subscribe_all(&self, capacity: usize) -> Subscription {
+        self.add_subscriber(None, capacity)
+    }
+
+    fn add_subscriber(
+        &self,
+        kinds: Option<Vec<PayloadKind>>,
+        capacity: usize,
+    ) -> Subscription {
+        let (tx, rx) = mpsc::channel(capacity);
+        let lagged = Arc::new(AtomicU64::new(0));
+        let mut subscribers = lock(&self.subscribers);
+        if !subscribers.closed {
+            subscribers.list.push(Subscriber {
+                kinds,
+                tx,
+                lagged: Arc::clone(&lagged),
+            });
+        }
+        Subscription { rx, lagged }
+    }
+}
+
+impl Drop for MessageHub {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn lock(subscribers: &Mutex<Subscribers>) -> MutexGuard<'_, Subscribers> {
+    // dispatching can't leave the list in an inconsistent state
+    subscribers.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn dispatch(subscribers: &mut Vec<Subscriber>, payload: McuPayload) {
+    let kind = PayloadKind::of(&payload);
+    subscribers.retain(|subscriber| {
+        if subscriber
+            .kinds
+            .as_ref()
+            .is_some_and(|kinds| !kinds.contains(&kind))
+        {
+            return true;
+        }
+        match subscriber.tx.try_send(payload.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                trace!(?kind, "subscriber queue full, dropping payload");
+                subscriber.lagged.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    });
+}
+
+impl Subscription {
+    /// Receives the next payload.
+    ///
+    /// If payloads were dropped because the queue was full, [`RecvError::Lagged`] is
+    /// returned once, and the following calls resume with the payloads that were kept.
+    pub async fn recv(&mut self) -> Result<McuPayload, RecvError> {
+        let lagged = self.lagged.swap(0, Ordering::Relaxed);
+        if lagged > 0 {
+            return Err(RecvError::Lagged(lagged));
+        }
+        self.rx.recv().await.ok_or(RecvError::Closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(text: &str) -> McuPayload {
+        let mut payload = MainPayload::Log(Default::default());
+        if let MainPayload::Log(log) = &mut payload {
+            log.log = text.to_owned();
+        }
+        McuPayload::FromMain(payload)
+    }
+
+    fn text(payload: McuPayload) -> String {
+        match payload {
+            McuPayload::FromMain(MainPayload::Log(log)) => log.log,
+            payload => panic!("unexpected payload: {payload:?}"),
+        }
+    }
+
+    fn versions() -> McuPayload {
+        McuPayload::FromMain(MainPayload::Versions(Default::default()))
+    }
+
+    #[tokio::test]
+    async fn dispatch_subscribed_kinds() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut hub = MessageHub::new(rx);
+        let mut logs = hub.subscribe(&[PayloadKind::Log], 4);
+        let mut versions = hub.subscribe(&[PayloadKind::Versions], 4);
+        let mut all = hub.subscribe_all(4);
+        for payload in [log("first"), versions(), log("second")] {
+            tx.send(payload).unwrap();
+        }
+        drop(tx);
+        (&mut hub.task).await.unwrap();
+
+        assert_eq!(text(logs.recv().await.unwrap()), "first");
+        assert_eq!(text(logs.recv().await.unwrap()), "second");
+        assert_eq!(logs.recv().await.unwrap_err(), RecvError::Closed);
+
+        assert_eq!(
+            PayloadKind::of(&versions.recv().await.unwrap()),
+            PayloadKind::Versions
+        );
+        assert_eq!(versions.recv().await.unwrap_err(), RecvError::Closed);
+
+        let kinds = [PayloadKind::Log, PayloadKind::Versions, PayloadKind::Log];
+        for kind in kinds {
+            assert_eq!(PayloadKind::of(&all.recv().await.unwrap()), kind);
+        }
+        assert_eq!(all.recv().await.unwrap_err(), RecvError::Closed);
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_on_drop() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let hub = MessageHub::new(rx);
+        let dropped = hub.subscribe_all(1);
+        let mut kept = hub.subscribe(&[PayloadKind::Log], 1);
+        drop(dropped);
+        tx.send(log("first")).unwrap();
+        // dispatched once the remaining subscriber got it
+        assert_eq!(text(kept.recv().await.unwrap()), "first");
+
+        assert_eq!(lock(&hub.subscribers).list.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn report_lag_before_queued_messages() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut hub = MessageHub::new(rx);
+        let mut subscription = hub.subscribe(&[PayloadKind::Log], 1);
+        for text in ["first", "second", "third"] {
+            tx.send(log(text)).unwrap();
+        }
+        drop(tx);
+        // wait for the hub to dispatch everything
+        (&mut hub.task).await.unwrap();
+
+        assert_eq!(subscription.recv().await.unwrap_err(), RecvError::Lagged(2));
+        assert_eq!(text(subscription.recv().await.unwrap()), "first");
+        assert_eq!(subscription.recv().await.unwrap_err(), RecvError::Closed);
+    }
+}
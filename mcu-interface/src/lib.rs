@@ -1,13 +1,16 @@
 use std::process;
 
 use async_trait::async_trait;
-use color_eyre::eyre::{eyre, Result};
 use orb_messages::CommonAckError;
 use tokio::sync::mpsc;
 use tracing::debug;
 
 pub mod can;
+pub mod failover;
+pub mod hub;
+pub mod request;
 pub mod serial;
+pub mod simulated;
 
 pub use orb_messages;
 
@@ -28,21 +31,82 @@ pub enum Device {
     JetsonFromSecurity = 0x81,
 }
 
-impl From<u8> for Device {
-    fn from(device: u8) -> Device {
+impl TryFrom<u8> for Device {
+    type Error = Error;
+
+    fn try_from(device: u8) -> Result<Device, Error> {
         match device {
-            0x01 => Device::Main,
-            0x02 => Device::Security,
-            0x80 => Device::JetsonFromMain,
-            0x81 => Device::JetsonFromSecurity,
-            _ => panic!("Unknown device: {}", device),
+            0x01 => Ok(Device::Main),
+            0x02 => Ok(Device::Security),
+            0x80 => Ok(Device::JetsonFromMain),
+            0x81 => Ok(Device::JetsonFromSecurity),
+            _ => Err(Error::UnknownNode(device)),
         }
     }
 }
 
+/// Errors returned when exchanging messages with the MCUs
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("unknown node id: 0x{0:x}")]
+    UnknownNode(u8),
+
+    #[error("messages from ISO-TP node {0:?} are not supported")]
+    UnsupportedIsoTpNode(can::isotp::IsoTpNodeIdentifier),
+
+    #[error("{0:?} is not a valid remote device")]
+    InvalidDevice(Device),
+
+    #[error("payload can't be sent to this device: {0:?}")]
+    InvalidPayload(Box<McuPayload>),
+
+    #[error("unknown message version {0}")]
+    ProtocolVersion(i32),
+
+    #[error("incompatible message: {0:?}")]
+    IncompatibleMessage(Box<orb_messages::McuMessage>),
+
+    #[error("failed to decode message")]
+    Decode(#[from] prost::DecodeError),
+
+    #[error("ack not received")]
+    AckTimeout,
+
+    #[error("message not acknowledged: {0:?}")]
+    Nack(CommonAckError),
+
+    #[error("no response received")]
+    ResponseTimeout,
+
+    #[error("transport closed")]
+    TransportClosed,
+
+    #[error(transparent)]
+    Can(#[from] can_rs::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// Whether the error comes from the transport rather than from the message or the MCU,
+    /// in which case sending over another transport might succeed.
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Error::AckTimeout | Error::TransportClosed | Error::Can(_) | Error::Io(_)
+        )
+    }
+}
+
 #[async_trait]
 pub trait MessagingInterface {
-    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError>;
+    /// Sends `payload` and waits for the MCU to ack it.
+    ///
+    /// The ack is returned as is: an ack other than [`CommonAckError::Success`] is only
+    /// turned into [`Error::Nack`] by higher-level APIs such as
+    /// [`McuClient`](request::McuClient).
+    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error>;
 }
 
 /// Create a unique ack number
@@ -68,12 +132,12 @@ fn handle_main_mcu_message(
     message: &orb_messages::McuMessage,
     ack_tx: &mpsc::UnboundedSender<(CommonAckError, u32)>,
     new_message_queue: &mpsc::UnboundedSender<McuPayload>,
-) -> Result<()> {
+) -> Result<(), Error> {
     match message {
         &orb_messages::McuMessage { version, .. }
             if version != orb_messages::Version::Version0 as i32 =>
         {
-            return Err(eyre!("unknown message version {:?}", version));
+            return Err(Error::ProtocolVersion(version));
         }
         orb_messages::McuMessage {
             version: _,
@@ -86,7 +150,9 @@ fn handle_main_mcu_message(
                 )),
         } => {
             if is_ack_for_us(ack.ack_number) {
-                ack_tx.send((CommonAckError::from(ack.error), ack.ack_number))?;
+                ack_tx
+                    .send((CommonAckError::from(ack.error), ack.ack_number))
+                    .map_err(|_| Error::TransportClosed)?;
             } else {
                 debug!("Ignoring ack # 0x{:x?}", ack.ack_number)
             }
@@ -98,11 +164,13 @@ fn handle_main_mcu_message(
                     orb_messages::main::McuToJetson { payload: Some(p) },
                 )),
         } => {
-            new_message_queue.send(McuPayload::FromMain(p.clone()))?;
+            new_message_queue
+                .send(McuPayload::FromMain(p.clone()))
+                .map_err(|_| Error::TransportClosed)?;
         }
         _ => {
             if message.message.is_some() {
-                return Err(eyre!("incompatible message: {:?}", message));
+                return Err(Error::IncompatibleMessage(Box::new(message.clone())));
             } else {
                 debug!("Ignoring empty message")
             }
@@ -116,12 +184,12 @@ fn handle_sec_mcu_message(
     message: &orb_messages::McuMessage,
     ack_tx: &mpsc::UnboundedSender<(CommonAckError, u32)>,
     new_message_queue: &mpsc::UnboundedSender<McuPayload>,
-) -> Result<()> {
+) -> Result<(), Error> {
     match message {
         &orb_messages::McuMessage { version, .. }
             if version != orb_messages::sec::Version::Version0 as i32 =>
         {
-            return Err(eyre!("unknown message version {:?}", version));
+            return Err(Error::ProtocolVersion(version));
         }
         orb_messages::McuMessage {
             version: _,
@@ -134,7 +202,9 @@ fn handle_sec_mcu_message(
                 )),
         } => {
             if is_ack_for_us(ack.ack_number) {
-                ack_tx.send((CommonAckError::from(ack.error), ack.ack_number))?;
+                ack_tx
+                    .send((CommonAckError::from(ack.error), ack.ack_number))
+                    .map_err(|_| Error::TransportClosed)?;
             }
         }
         orb_messages::McuMessage {
@@ -144,11 +214,13 @@ fn handle_sec_mcu_message(
                     orb_messages::sec::SecToJetson { payload: Some(p) },
                 )),
         } => {
-            new_message_queue.send(McuPayload::FromSec(p.clone()))?;
+            new_message_queue
+                .send(McuPayload::FromSec(p.clone()))
+                .map_err(|_| Error::TransportClosed)?;
         }
         _ => {
             if message.message.is_some() {
-                return Err(eyre!("incompatible message: {:?}", message));
+                return Err(Error::IncompatibleMessage(Box::new(message.clone())));
             } else {
                 debug!("Ignoring empty message")
             }
@@ -0,0 +1,230 @@
+//! Typed request/response on top of a [`MessagingInterface`]
+//!
+//! The MCUs answer a `ValueGet` request with a dedicated payload sent outside of the ack
+//! mechanism, so the response has to be picked out of the stream of incoming messages.
+//! [`McuClient`] does that for any type implementing [`Request`]:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), orb_mcu_interface::Error> {
+//! use orb_mcu_interface::can::canfd::CanRawMessaging;
+//! use orb_mcu_interface::orb_messages::Versions;
+//! use orb_mcu_interface::request::McuClient;
+//! use orb_mcu_interface::Device;
+//!
+//! let (msg_tx, msg_rx) = tokio::sync::mpsc::unbounded_channel();
+//! let (iface, _task_handle) =
+//!     CanRawMessaging::new(String::from("can0"), Device::Main, msg_tx)?;
+//! let mut client = McuClient::new(iface, Device::Main, msg_rx);
+//! let versions = client.request::<Versions>().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! To share the incoming messages with other consumers, hand them to a
+//! [`MessageHub`](crate::hub::MessageHub) and create the client with
+//! [`McuClient::with_subscription`] instead.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use orb_messages::CommonAckError;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::{debug, trace, warn};
+
+use crate::hub::{RecvError, Subscription};
+use crate::{Device, Error, McuPayload, MessagingInterface};
+
+/// Default time to wait for the response once the request has been acked.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maximum number of messages kept while waiting for responses, the oldest ones are
+/// dropped beyond that.
+pub const MAX_PENDING: usize = 64;
+
+/// A value that can be fetched from an MCU with a `ValueGet` request
+pub trait Request: Sized {
+    /// Value to ask for in the `ValueGet` request.
+    const VALUE: orb_messages::value_get::Value;
+
+    /// Extracts the response from a received payload, returns `None` if the payload
+    /// isn't a response to this request.
+    fn from_payload(payload: &McuPayload) -> Option<Self>;
+}
+
+/// Firmware versions, answered by both MCUs.
+impl Request for orb_messages::Versions {
+    const VALUE: orb_messages::value_get::Value =
+        orb_messages::value_get::Value::FirmwareVersions;
+
+    fn from_payload(payload: &McuPayload) -> Option<Self> {
+        match payload {
+            McuPayload::FromMain(
+                orb_messages::main::mcu_to_jetson::Payload::Versions(v),
+            )
+            | McuPayload::FromSec(
+                orb_messages::sec::sec_to_jetson::Payload::Versions(v),
+            ) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Hardware version, answered by the main MCU.
+impl Request for orb_messages::Hardware {
+    const VALUE: orb_messages::value_get::Value =
+        orb_messages::value_get::Value::HardwareVersions;
+
+    fn from_payload(payload: &McuPayload) -> Option<Self> {
+        match payload {
+            McuPayload::FromMain(
+                orb_messages::main::mcu_to_jetson::Payload::Hardware(h),
+            ) => Some(h.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Owns a [`MessagingInterface`] and the queue it pushes incoming messages to, or a
+/// [`Subscription`] to a hub dispatching that queue.
+///
+/// Messages received while waiting for a response, but which are not the response, are
+/// kept and returned by [`McuClient::recv`], up to [`MAX_PENDING`] of them.
+pub struct McuClient<I> {
+    iface: I,
+    device: Device,
+    incoming: Incoming,
+    pending: VecDeque<McuPayload>,
+    response_timeout: Duration,
+}
+
+impl<I: MessagingInterface> McuClient<I> {
+    /// `message_queue` must be the receiving end of the queue passed to `iface` on
+    /// creation.
+    pub fn new(
+        iface: I,
+        device: Device,
+        message_queue: mpsc::UnboundedReceiver<McuPayload>,
+    ) -> Self {
+        Self::with_incoming(iface, device, Incoming::Queue(message_queue))
+    }
+
+    /// Receives the incoming messages from a hub `subscription` instead of the queue of
+    /// `iface`. The subscription must include the payloads answering the requests, see
+    /// [`PayloadKind`](crate::hub::PayloadKind).
+    ///
+    /// Messages dropped because the subscription lagged behind are skipped.
+    pub fn with_subscription(
+        iface: I,
+        device: Device,
+        subscription: Subscription,
+    ) -> Self {
+        Self::with_incoming(iface, device, Incoming::Subscription(subscription))
+    }
+
+    fn with_incoming(iface: I, device: Device, incoming: Incoming) -> Self {
+        Self {
+            iface,
+            device,
+            incoming,
+            pending: VecDeque::new(),
+            response_timeout: RESPONSE_TIMEOUT,
+        }
+    }
+
+    /// Sets the time to wait for a response, defaults to [`RESPONSE_TIMEOUT`].
+    pub fn with_timeout(mut self, response_timeout: Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.iface
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iface
+    }
+
+    /// Sends a payload without waiting for anything else than the ack.
+    pub async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error> {
+        self.iface.send(payload).await
+    }
+
+    /// Receives the next incoming message that wasn't consumed by [`McuClient::request`].
+    pub async fn recv(&mut self) -> Option<McuPayload> {
+        match self.pending.pop_front() {
+            Some(payload) => Some(payload),
+            None => self.incoming.recv().await,
+        }
+    }
+
+    /// Asks the MCU for `T` and waits for the response.
+    pub async fn request<T: Request>(&mut self) -> Result<T, Error> {
+        let value_get = orb_messages::ValueGet {
+            value: T::VALUE as i32,
+        };
+        let payload = match self.device {
+            Device::Main => McuPayload::ToMain(
+                orb_messages::main::jetson_to_mcu::Payload::ValueGet(value_get),
+            ),
+            Device::Security => McuPayload::ToSec(
+                orb_messages::sec::jetson_to_sec::Payload::ValueGet(value_get),
+            ),
+            Device::JetsonFromMain | Device::JetsonFromSecurity => {
+                return Err(Error::InvalidDevice(self.device));
+            }
+        };
+
+        // drop stale responses to previous requests that timed out
+        self.pending.retain(|p| T::from_payload(p).is_none());
+
+        let ack = self.iface.send(payload).await?;
+        if ack != CommonAckError::Success {
+            return Err(Error::Nack(ack));
+        }
+
+        let response_timeout = self.response_timeout;
+        let recv_fut = async {
+            while let Some(payload) = self.incoming.recv().await {
+                if let Some(response) = T::from_payload(&payload) {
+                    return Ok(response);
+                }
+                trace!("keeping message received while waiting for a response");
+                if self.pending.len() == MAX_PENDING {
+                    warn!("too many messages kept, dropping the oldest one");
+                    self.pending.pop_front();
+                }
+                self.pending.push_back(payload);
+            }
+
+            Err(Error::TransportClosed)
+        };
+        timeout(response_timeout, recv_fut)
+            .await
+            .unwrap_or(Err(Error::ResponseTimeout))
+    }
+}
+
+/// Source of the incoming messages of a [`McuClient`]
+enum Incoming {
+    Queue(mpsc::UnboundedReceiver<McuPayload>),
+    Subscription(Subscription),
+}
+
+impl Incoming {
+    async fn recv(&mut self) -> Option<McuPayload> {
+        match self {
+            Incoming::Queue(queue) => queue.recv().await,
+            Incoming::Subscription(subscription) => loop {
+                match subscription.recv().await {
+                    Ok(payload) => return Some(payload),
+                    Err(RecvError::Lagged(dropped)) => {
+                        debug!("client lagged behind, {dropped} messages dropped");
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+        }
+    }
+}
@@ -1,58 +1,220 @@
-use crate::{Device, McuPayload, MessagingInterface};
+use crate::can::{CanTaskHandle, CanTaskJoinError, CanTaskPanic, ACK_RX_TIMEOUT};
+use crate::{
+    create_ack, handle_main_mcu_message, handle_sec_mcu_message, Device, Error,
+    McuPayload, MessagingInterface,
+};
 use async_trait::async_trait;
-use color_eyre::eyre::{eyre, Result};
+use futures::FutureExt as _;
 use orb_messages::CommonAckError;
 use prost::Message;
+use std::io;
+use std::panic::AssertUnwindSafe;
 use std::sync::atomic::{AtomicU16, Ordering};
-use std::time::Duration;
-use std::vec;
-use tokio::io::AsyncWriteExt;
-use tokio::time;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
 use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
-use tracing::debug;
+use tracing::{debug, trace};
+
+/// UART message: magic (2B) + size (2B, little endian) + payload (protobuf-encoded McuMessage)
+const MAGIC: [u8; 2] = [0x8e, 0xad];
+const HEADER_LEN: usize = MAGIC.len() + 2;
+/// Larger sizes are considered corrupted, and the decoder resyncs on the next magic.
+const MAX_PAYLOAD_LEN: usize = 1024;
 
 pub struct SerialMessaging {
     device: Device,
-    port: SerialStream,
+    writer: WriteHalf<SerialStream>,
     ack_num_lsb: AtomicU16,
+    ack_queue: mpsc::UnboundedReceiver<(CommonAckError, u32)>,
+    /// Ensures that the task is killed when Self is dropped.
+    _kill_tx: oneshot::Sender<()>,
 }
 
 impl SerialMessaging {
-    pub fn new(device: Device) -> Result<Self> {
-        let mut port = match device {
-            Device::Main => {
-                tokio_serial::new("/dev/ttyTHS0", 1000000).open_native_async()?
+    /// Opens the UART connected to `device` and starts listening for incoming messages in a new
+    /// task.
+    ///
+    /// Returns a handle to join on the task and retrieve any errors it produces.
+    pub fn new(
+        device: Device,
+        new_message_queue: mpsc::UnboundedSender<McuPayload>,
+    ) -> Result<(Self, CanTaskHandle), Error> {
+        let path = match device {
+            Device::Main => "/dev/ttyTHS0",
+            Device::Security => "/dev/ttyTHS1",
+            Device::JetsonFromMain | Device::JetsonFromSecurity => {
+                return Err(Error::InvalidDevice(device));
             }
-            Device::Security => {
-                tokio_serial::new("/dev/ttyTHS1", 1000000).open_native_async()?
+        };
+        let mut port = tokio_serial::new(path, 1000000)
+            .open_native_async()
+            .map_err(io::Error::from)?;
+
+        port.set_data_bits(tokio_serial::DataBits::Eight)
+            .map_err(io::Error::from)?;
+        port.set_stop_bits(tokio_serial::StopBits::One)
+            .map_err(io::Error::from)?;
+        port.set_parity(tokio_serial::Parity::None)
+            .map_err(io::Error::from)?;
+
+        let (reader, writer) = tokio::io::split(port);
+        let (ack_tx, ack_rx) = mpsc::unbounded_channel();
+        let (kill_tx, kill_rx) = oneshot::channel();
+        let (task_join_tx, task_join_rx) = oneshot::channel();
+        let task_join_rx = CanTaskHandle(task_join_rx);
+        tokio::task::spawn(async move {
+            let result: Result<(), CanTaskJoinError> = match AssertUnwindSafe(
+                serial_rx(reader, device, ack_tx, new_message_queue, kill_rx),
+            )
+            .catch_unwind()
+            .await
+            {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(err)) => Err(CanTaskJoinError::Err(err)),
+                Err(panic) => Err(CanTaskPanic::new(panic).into()),
+            };
+            debug!(result=?result, "serial_rx task terminated");
+            task_join_tx.send(result)
+        });
+
+        Ok((
+            Self {
+                device,
+                writer,
+                ack_num_lsb: AtomicU16::new(0),
+                ack_queue: ack_rx,
+                _kill_tx: kill_tx,
+            },
+            task_join_rx,
+        ))
+    }
+
+    async fn wait_ack(
+        &mut self,
+        expected_ack_number: u32,
+    ) -> Result<CommonAckError, Error> {
+        let recv_fut = async {
+            while let Some((ack, number)) = self.ack_queue.recv().await {
+                if number == expected_ack_number {
+                    return Ok(ack);
+                }
             }
-            Device::JetsonFromMain | Device::JetsonFromSecurity => {
-                return Err(eyre!("Cannot open serial from Jetson to Jetson"));
+
+            Err(Error::TransportClosed)
+        };
+        timeout(ACK_RX_TIMEOUT, recv_fut)
+            .map(|result| result.unwrap_or(Err(Error::AckTimeout)))
+            .await
+    }
+}
+
+/// Splits the UART byte stream into message payloads
+///
+/// Bytes are dropped until the magic is found, so that the decoder resyncs after corrupted or
+/// partially received messages.
+#[derive(Default)]
+struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the payload of the next complete message, if any.
+    fn next_payload(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.buffer.windows(MAGIC.len()).position(|w| w == MAGIC) {
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    // keep a trailing byte that could be the start of the magic
+                    let keep = usize::from(self.buffer.last() == Some(&MAGIC[0]));
+                    self.buffer.drain(..self.buffer.len() - keep);
+                    return None;
+                }
             }
+            if self.buffer.len() < HEADER_LEN {
+                return None;
+            }
+            let len = usize::from(u16::from_le_bytes([self.buffer[2], self.buffer[3]]));
+            if len > MAX_PAYLOAD_LEN {
+                debug!("Dropping message with invalid size {len}");
+                self.buffer.drain(..MAGIC.len());
+                continue;
+            }
+            if self.buffer.len() < HEADER_LEN + len {
+                return None;
+            }
+            let payload = self.buffer[HEADER_LEN..HEADER_LEN + len].to_vec();
+            self.buffer.drain(..HEADER_LEN + len);
+            return Some(payload);
+        }
+    }
+}
+
+/// Receive UART messages
+/// - relay acks to `ack_tx`
+/// - relay new McuMessage to `new_message_queue`
+async fn serial_rx(
+    mut reader: ReadHalf<SerialStream>,
+    remote_node: Device,
+    ack_tx: mpsc::UnboundedSender<(CommonAckError, u32)>,
+    new_message_queue: mpsc::UnboundedSender<McuPayload>,
+    mut kill_rx: oneshot::Receiver<()>,
+) -> Result<(), Error> {
+    let mut decoder = Decoder::default();
+    let mut buffer = [0u8; 256];
+    loop {
+        // terminate task on kill signal
+        let nbytes = tokio::select! {
+            _ = &mut kill_rx => return Ok(()),
+            read = reader.read(&mut buffer) => read?,
         };
+        if nbytes == 0 {
+            return Err(Error::TransportClosed);
+        }
+        trace!("read {nbytes} bytes from serial");
+        decoder.push(&buffer[..nbytes]);
 
-        port.set_data_bits(tokio_serial::DataBits::Eight)?;
-        port.set_stop_bits(tokio_serial::StopBits::One)?;
-        port.set_parity(tokio_serial::Parity::None)?;
+        while let Some(payload) = decoder.next_payload() {
+            let status =
+                handle_payload(&payload, remote_node, &ack_tx, &new_message_queue);
+            if let Err(e) = status {
+                debug!("Error handling message: {:#}", e);
+            }
+        }
+    }
+}
 
-        Ok(Self {
-            device,
-            port,
-            ack_num_lsb: AtomicU16::new(0),
-        })
+fn handle_payload(
+    payload: &[u8],
+    remote_node: Device,
+    ack_tx: &mpsc::UnboundedSender<(CommonAckError, u32)>,
+    new_message_queue: &mpsc::UnboundedSender<McuPayload>,
+) -> Result<(), Error> {
+    let message = orb_messages::McuMessage::decode_length_delimited(payload)?;
+    match remote_node {
+        Device::Main => handle_main_mcu_message(&message, ack_tx, new_message_queue),
+        Device::Security => handle_sec_mcu_message(&message, ack_tx, new_message_queue),
+        Device::JetsonFromMain | Device::JetsonFromSecurity => {
+            Err(Error::InvalidDevice(remote_node))
+        }
     }
 }
 
 #[async_trait]
 impl MessagingInterface for SerialMessaging {
-    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError> {
-        let ack_number = self.ack_num_lsb.load(Ordering::Relaxed) as u32;
+    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error> {
+        let ack_number = create_ack(self.ack_num_lsb.fetch_add(1, Ordering::SeqCst));
         let mut payload = match self.device {
             Device::Main => {
-                let payload = if let McuPayload::ToMain(payload) = payload {
-                    payload
-                } else {
-                    return Err(eyre!("Invalid payload for Main"));
+                let payload = match payload {
+                    McuPayload::ToMain(payload) => payload,
+                    payload => return Err(Error::InvalidPayload(Box::new(payload))),
                 };
                 let to_encode = orb_messages::McuMessage {
                     version: orb_messages::Version::Version0 as i32,
@@ -66,10 +228,9 @@ impl MessagingInterface for SerialMessaging {
                 to_encode.encode_length_delimited_to_vec()
             }
             Device::Security => {
-                let payload = if let McuPayload::ToSec(payload) = payload {
-                    payload
-                } else {
-                    return Err(eyre!("Invalid payload for Main"));
+                let payload = match payload {
+                    McuPayload::ToSec(payload) => payload,
+                    payload => return Err(Error::InvalidPayload(Box::new(payload))),
                 };
 
                 let to_encode = orb_messages::McuMessage {
@@ -86,26 +247,76 @@ impl MessagingInterface for SerialMessaging {
                 };
                 to_encode.encode_length_delimited_to_vec()
             }
-            _ => {
-                panic!("Invalid device: {:?}", self.device)
+            Device::JetsonFromMain | Device::JetsonFromSecurity => {
+                return Err(Error::InvalidDevice(self.device));
             }
         };
 
-        // UART message: magic (2B) + size (2B) + payload (protobuf-encoded McuMessage)
         let mut size = Vec::from((payload.len() as u16).to_le_bytes());
-        let mut bytes: Vec<u8> = vec![0x8e, 0xad];
+        let mut bytes: Vec<u8> = Vec::from(MAGIC);
         bytes.append(&mut size);
         bytes.append(&mut payload);
 
         debug!("Sending {} bytes: {:?}", bytes.len(), bytes);
 
-        self.port
-            .write_all(bytes.as_slice())
-            .await
-            .expect("unable to write test message");
+        self.writer.write_all(bytes.as_slice()).await?;
 
-        time::sleep(Duration::from_millis(8)).await;
+        self.wait_ack(ack_number).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::from(MAGIC);
+        bytes.extend((payload.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn skip_garbage_before_magic() {
+        let mut decoder = Decoder::default();
+        decoder.push(&[0x00, MAGIC[0], 0x42, MAGIC[1]]);
+        decoder.push(&message(&[1, 2, 3]));
+        assert_eq!(decoder.next_payload(), Some(vec![1, 2, 3]));
+        assert_eq!(decoder.next_payload(), None);
+    }
+
+    #[test]
+    fn join_magic_split_across_reads() {
+        let bytes = message(&[1, 2, 3]);
+        let mut decoder = Decoder::default();
+        decoder.push(&[0x00, bytes[0]]);
+        assert_eq!(decoder.next_payload(), None);
+        decoder.push(&bytes[1..5]);
+        assert_eq!(decoder.next_payload(), None);
+        decoder.push(&bytes[5..]);
+        assert_eq!(decoder.next_payload(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn resync_after_oversized_length() {
+        let mut decoder = Decoder::default();
+        decoder.push(&MAGIC);
+        decoder.push(&(MAX_PAYLOAD_LEN as u16 + 1).to_le_bytes());
+        decoder.push(&message(&[1, 2, 3]));
+        assert_eq!(decoder.next_payload(), Some(vec![1, 2, 3]));
+        assert_eq!(decoder.next_payload(), None);
+    }
 
-        Ok(CommonAckError::Success)
+    #[test]
+    fn split_back_to_back_messages() {
+        let mut decoder = Decoder::default();
+        let mut bytes = message(&[1, 2, 3]);
+        bytes.extend(message(&[]));
+        bytes.extend(message(&[4, 5]));
+        decoder.push(&bytes);
+        assert_eq!(decoder.next_payload(), Some(vec![1, 2, 3]));
+        assert_eq!(decoder.next_payload(), Some(vec![]));
+        assert_eq!(decoder.next_payload(), Some(vec![4, 5]));
+        assert_eq!(decoder.next_payload(), None);
     }
 }
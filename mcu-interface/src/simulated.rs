@@ -0,0 +1,452 @@
+//! A simulated MCU, to exercise the MCU clients without boards
+//!
+//! [`SimulatedMcu`] implements [`MessagingInterface`]: sent payloads are encoded into an
+//! `McuMessage` and decoded back as a board would, and the answers go through the same
+//! handling as the messages received from a real board, acks included.
+
+use std::collections::VecDeque;
+
+use async_trait::async_trait;
+use orb_messages::main::jetson_to_mcu::Payload as ToMainPayload;
+use orb_messages::main::mcu_to_jetson::Payload as MainPayload;
+use orb_messages::sec::jetson_to_sec::Payload as ToSecPayload;
+use orb_messages::sec::sec_to_jetson::Payload as SecPayload;
+use orb_messages::CommonAckError;
+use prost::Message;
+use tokio::sync::mpsc;
+use tracing::{debug, trace};
+
+use crate::{
+    create_ack, handle_main_mcu_message, handle_sec_mcu_message, Device, Error,
+    McuPayload, MessagingInterface,
+};
+
+/// Request received by a simulated MCU, decoded from the wire format
+#[derive(Clone, Debug)]
+enum Request {
+    Main(ToMainPayload),
+    Sec(ToSecPayload),
+}
+
+/// Firmware update in progress or completed on a [`SimulatedMcu`]
+#[derive(Clone, Debug, Default)]
+struct Dfu {
+    image: Vec<u8>,
+    block_count: u32,
+    next_block: u32,
+    checked: bool,
+}
+
+/// Simulated main or security MCU
+///
+/// - acks every message with [`CommonAckError::Success`], unless told otherwise with
+///   [`SimulatedMcu::set_ack`] or [`SimulatedMcu::push_ack`]
+/// - answers firmware versions requests, and hardware versions requests for the main MCU
+/// - accepts DFU blocks sent in order, checks the CRC32 of the received image and allows
+///   activating it once checked
+/// - emits logs on demand with [`SimulatedMcu::emit_log`]
+pub struct SimulatedMcu {
+    device: Device,
+    new_message_queue: mpsc::UnboundedSender<McuPayload>,
+    ack_tx: mpsc::UnboundedSender<(CommonAckError, u32)>,
+    ack_rx: mpsc::UnboundedReceiver<(CommonAckError, u32)>,
+    ack_num_lsb: u16,
+    ack: CommonAckError,
+    next_acks: VecDeque<CommonAckError>,
+    versions: orb_messages::Versions,
+    hardware: orb_messages::Hardware,
+    dfu: Dfu,
+    activated: bool,
+    received: Vec<McuPayload>,
+}
+
+impl SimulatedMcu {
+    /// `new_message_queue` receives the messages sent by the simulated MCU, as with the
+    /// other messaging interfaces.
+    pub fn new(
+        device: Device,
+        new_message_queue: mpsc::UnboundedSender<McuPayload>,
+    ) -> Result<Self, Error> {
+        if !matches!(device, Device::Main | Device::Security) {
+            return Err(Error::InvalidDevice(device));
+        }
+        let (ack_tx, ack_rx) = mpsc::unbounded_channel();
+
+        Ok(Self {
+            device,
+            new_message_queue,
+            ack_tx,
+            ack_rx,
+            ack_num_lsb: 0,
+            ack: CommonAckError::Success,
+            next_acks: VecDeque::new(),
+            versions: orb_messages::Versions::default(),
+            hardware: orb_messages::Hardware::default(),
+            dfu: Dfu::default(),
+            activated: false,
+            received: Vec::new(),
+        })
+    }
+
+    /// Sets the firmware versions reported by the MCU.
+    pub fn with_versions(mut self, versions: orb_messages::Versions) -> Self {
+        self.versions = versions;
+        self
+    }
+
+    /// Sets the hardware version reported by the main MCU.
+    pub fn with_hardware(mut self, hardware: orb_messages::Hardware) -> Self {
+        self.hardware = hardware;
+        self
+    }
+
+    /// Sets the ack sent for every message, [`CommonAckError::Success`] by default.
+    pub fn set_ack(&mut self, ack: CommonAckError) {
+        self.ack = ack;
+    }
+
+    /// Acks the next message with `ack`, whatever the message. Queued acks are used in
+    /// order, before falling back to the one set with [`SimulatedMcu::set_ack`].
+    pub fn push_ack(&mut self, ack: CommonAckError) {
+        self.next_acks.push_back(ack);
+    }
+
+    /// Payloads received so far, in order.
+    pub fn received(&self) -> &[McuPayload] {
+        &self.received
+    }
+
+    /// Firmware image received by DFU, once all its blocks were received.
+    pub fn dfu_image(&self) -> Option<&[u8]> {
+        (self.dfu.block_count != 0 && self.dfu.next_block == self.dfu.block_count)
+            .then_some(self.dfu.image.as_slice())
+    }
+
+    /// Whether the received firmware image has been activated.
+    pub fn is_image_activated(&self) -> bool {
+        self.activated
+    }
+
+    /// Sends a log message, as the MCU firmware does.
+    pub fn emit_log(&self, text: &str) -> Result<(), Error> {
+        match self.device {
+            Device::Main => {
+                let mut payload = MainPayload::Log(Default::default());
+                if let MainPayload::Log(log) = &mut payload {
+                    log.log = text.to_owned();
+                }
+                self.reply_main(payload)
+            }
+            _ => {
+                let mut payload = SecPayload::Log(Default::default());
+                if let SecPayload::Log(log) = &mut payload {
+                    log.log = text.to_owned();
+                }
+                self.reply_sec(payload)
+            }
+        }
+    }
+
+    /// Encodes the payload as it would be sent to a board, and decodes it back.
+    fn transmit(&self, payload: McuPayload, ack_number: u32) -> Result<Request, Error> {
+        let message = match (self.device, payload) {
+            (Device::Main, McuPayload::ToMain(p)) => orb_messages::McuMessage {
+                version: orb_messages::Version::Version0 as i32,
+                message: Some(orb_messages::mcu_message::Message::JMessage(
+                    orb_messages::main::JetsonToMcu {
+                        ack_number,
+                        payload: Some(p),
+                    },
+                )),
+            },
+            (Device::Security, McuPayload::ToSec(p)) => orb_messages::McuMessage {
+                version: orb_messages::Version::Version0 as i32,
+                message: Some(orb_messages::mcu_message::Message::JetsonToSecMessage(
+                    orb_messages::sec::JetsonToSec {
+                        ack_number,
+                        payload: Some(p),
+                    },
+                )),
+            },
+            (_, payload) => return Err(Error::InvalidPayload(Box::new(payload))),
+        };
+        let bytes = message.encode_length_delimited_to_vec();
+        trace!("simulated {:?} received {} bytes", self.device, bytes.len());
+
+        let message =
+            orb_messages::McuMessage::decode_length_delimited(bytes.as_slice())?;
+        match message.message {
+            Some(orb_messages::mcu_message::Message::JMessage(
+                orb_messages::main::JetsonToMcu {
+                    payload: Some(p), ..
+                },
+            )) => Ok(Request::Main(p)),
+            Some(orb_messages::mcu_message::Message::JetsonToSecMessage(
+                orb_messages::sec::JetsonToSec {
+                    payload: Some(p), ..
+                },
+            )) => Ok(Request::Sec(p)),
+            other => Err(Error::IncompatibleMessage(Box::new(
+                orb_messages::McuMessage {
+                    version: message.version,
+                    message: other,
+                },
+            ))),
+        }
+    }
+
+    /// Handles a request like the firmware would, returning the ack to send.
+    fn process(&mut self, request: Request) -> Result<CommonAckError, Error> {
+        let ack = self.next_acks.pop_front().unwrap_or(self.ack);
+        if ack != CommonAckError::Success {
+            debug!("simulated {:?} rejecting {:?}", self.device, request);
+            return Ok(ack);
+        }
+
+        match request {
+            Request::Main(ToMainPayload::ValueGet(v))
+            | Request::Sec(ToSecPayload::ValueGet(v))
+                if v.value
+                    == orb_messages::value_get::Value::FirmwareVersions as i32 =>
+            {
+                self.reply_versions()?
+            }
+            Request::Main(ToMainPayload::ValueGet(v))
+                if v.value
+                    == orb_messages::value_get::Value::HardwareVersions as i32 =>
+            {
+                self.reply_main(MainPayload::Hardware(self.hardware.clone()))?
+            }
+            Request::Main(ToMainPayload::DfuBlock(block))
+            | Request::Sec(ToSecPayload::DfuBlock(block)) => {
+                return Ok(self.dfu_block(block));
+            }
+            Request::Main(ToMainPayload::FwImageCheck(check))
+            | Request::Sec(ToSecPayload::FwImageCheck(check)) => {
+                self.dfu.checked = self
+                    .dfu_image()
+                    .is_some_and(|image| crc32fast::hash(image) == check.crc32);
+                if !self.dfu.checked {
+                    return Ok(CommonAckError::Fail);
+                }
+            }
+            Request::Main(ToMainPayload::FwImageSecondaryActivate(_))
+            | Request::Sec(ToSecPayload::FwImageSecondaryActivate(_)) => {
+                if !self.dfu.checked {
+                    return Ok(CommonAckError::Fail);
+                }
+                self.activated = true;
+            }
+            _ => (),
+        }
+
+        Ok(CommonAckError::Success)
+    }
+
+    fn dfu_block(&mut self, block: orb_messages::FirmwareUpdateData) -> CommonAckError {
+        if block.block_number == 0 {
+            self.dfu = Dfu {
+                block_count: block.block_count,
+                ..Dfu::default()
+            };
+            self.activated = false;
+        }
+        if block.block_number != self.dfu.next_block
+            || block.block_count != self.dfu.block_count
+        {
+            debug!(
+                "simulated {:?}: unexpected DFU block {}/{}",
+                self.device, block.block_number, block.block_count
+            );
+            return CommonAckError::Fail;
+        }
+        self.dfu.image.extend_from_slice(&block.image_block);
+        self.dfu.next_block += 1;
+
+        CommonAckError::Success
+    }
+
+    fn reply_versions(&self) -> Result<(), Error> {
+        match self.device {
+            Device::Main => {
+                self.reply_main(MainPayload::Versions(self.versions.clone()))
+            }
+            _ => self.reply_sec(SecPayload::Versions(self.versions.clone())),
+        }
+    }
+
+    fn reply_main(&self, payload: MainPayload) -> Result<(), Error> {
+        let message = orb_messages::McuMessage {
+            version: orb_messages::Version::Version0 as i32,
+            message: Some(orb_messages::mcu_message::Message::MMessage(
+                orb_messages::main::McuToJetson {
+                    payload: Some(payload),
+                },
+            )),
+        };
+        handle_main_mcu_message(&message, &self.ack_tx, &self.new_message_queue)
+    }
+
+    fn reply_sec(&self, payload: SecPayload) -> Result<(), Error> {
+        let message = orb_messages::McuMessage {
+            version: orb_messages::sec::Version::Version0 as i32,
+            message: Some(orb_messages::mcu_message::Message::SecToJetsonMessage(
+                orb_messages::sec::SecToJetson {
+                    payload: Some(payload),
+                },
+            )),
+        };
+        handle_sec_mcu_message(&message, &self.ack_tx, &self.new_message_queue)
+    }
+
+    fn reply_ack(&self, ack: CommonAckError, ack_number: u32) -> Result<(), Error> {
+        match self.device {
+            Device::Main => {
+                let mut payload = MainPayload::Ack(Default::default());
+                if let MainPayload::Ack(a) = &mut payload {
+                    a.ack_number = ack_number;
+                    a.error = ack as i32;
+                }
+                self.reply_main(payload)
+            }
+            _ => {
+                let mut payload = SecPayload::Ack(Default::default());
+                if let SecPayload::Ack(a) = &mut payload {
+                    a.ack_number = ack_number;
+                    a.error = ack as i32;
+                }
+                self.reply_sec(payload)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MessagingInterface for SimulatedMcu {
+    async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError, Error> {
+        let ack_number = create_ack(self.ack_num_lsb);
+        self.ack_num_lsb = self.ack_num_lsb.wrapping_add(1);
+
+        let request = self.transmit(payload.clone(), ack_number)?;
+        self.received.push(payload);
+        let ack = self.process(request)?;
+        self.reply_ack(ack, ack_number)?;
+
+        match self.ack_rx.try_recv() {
+            Ok((ack, number)) if number == ack_number => Ok(ack),
+            _ => Err(Error::AckTimeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::{McuClient, MAX_PENDING};
+
+    fn client(device: Device) -> McuClient<SimulatedMcu> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        McuClient::new(SimulatedMcu::new(device, tx).unwrap(), device, rx)
+    }
+
+    #[tokio::test]
+    async fn request_versions() {
+        let mut versions = orb_messages::Versions {
+            primary_app: Some(Default::default()),
+            ..Default::default()
+        };
+        versions.primary_app.as_mut().unwrap().commit_hash = 0x1234;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mcu = SimulatedMcu::new(Device::Main, tx)
+            .unwrap()
+            .with_versions(versions.clone());
+        let mut client = McuClient::new(mcu, Device::Main, rx);
+
+        assert_eq!(
+            client.request::<orb_messages::Versions>().await.unwrap(),
+            versions
+        );
+    }
+
+    #[tokio::test]
+    async fn update_firmware() {
+        let mut client = client(Device::Security);
+        let image = (0..100).collect::<Vec<u8>>();
+        for (block_number, block) in image.chunks(40).enumerate() {
+            let block = orb_messages::FirmwareUpdateData {
+                block_number: block_number as u32,
+                block_count: 3,
+                image_block: block.to_vec(),
+            };
+            let ack = client
+                .send(McuPayload::ToSec(ToSecPayload::DfuBlock(block)))
+                .await
+                .unwrap();
+            assert_eq!(ack, CommonAckError::Success);
+        }
+        assert_eq!(client.get_mut().dfu_image(), Some(image.as_slice()));
+
+        let check = |crc32| {
+            McuPayload::ToSec(ToSecPayload::FwImageCheck(
+                orb_messages::FirmwareImageCheck { crc32 },
+            ))
+        };
+        let activate = McuPayload::ToSec(ToSecPayload::FwImageSecondaryActivate(
+            orb_messages::FirmwareActivateSecondary {
+                force_permanent: false,
+            },
+        ));
+        let crc32 = crc32fast::hash(&image);
+        assert_eq!(
+            client.send(check(!crc32)).await.unwrap(),
+            CommonAckError::Fail
+        );
+        assert_eq!(
+            client.send(activate.clone()).await.unwrap(),
+            CommonAckError::Fail
+        );
+        assert_eq!(
+            client.send(check(crc32)).await.unwrap(),
+            CommonAckError::Success
+        );
+        assert_eq!(
+            client.send(activate).await.unwrap(),
+            CommonAckError::Success
+        );
+        assert!(client.get_mut().is_image_activated());
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_pending_messages() {
+        let mut client = client(Device::Main);
+        for i in 0..MAX_PENDING + 2 {
+            client.get_mut().emit_log(&i.to_string()).unwrap();
+        }
+        client.request::<orb_messages::Versions>().await.unwrap();
+
+        let mut logs = Vec::new();
+        for _ in 0..MAX_PENDING {
+            match client.recv().await {
+                Some(McuPayload::FromMain(MainPayload::Log(log))) => logs.push(log.log),
+                payload => panic!("unexpected payload: {payload:?}"),
+            }
+        }
+        let expected = (2..MAX_PENDING + 2).map(|i| i.to_string());
+        assert!(logs.into_iter().eq(expected));
+    }
+
+    #[tokio::test]
+    async fn reject_nacked_request() {
+        let mut client = client(Device::Main);
+        client.get_mut().push_ack(CommonAckError::Fail);
+
+        let err = client
+            .request::<orb_messages::Versions>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Nack(CommonAckError::Fail)), "{err:?}");
+        // the versions were not sent, the next request gets its own answer
+        client.request::<orb_messages::Versions>().await.unwrap();
+        assert_eq!(client.get_mut().received().len(), 2);
+    }
+}
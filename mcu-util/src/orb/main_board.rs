@@ -20,9 +20,13 @@ use super::BoardTaskHandles;
 
 const REBOOT_DELAY: u32 = 3;
 
-pub struct MainBoard {
-    canfd_iface: CanRawMessaging,
-    isotp_iface: CanIsoTpMessaging,
+/// The main microcontroller, reached over CAN-FD and ISO-TP
+///
+/// The interfaces are generic so that the board can be driven by other
+/// [`MessagingInterface`]s, such as a simulated MCU.
+pub struct MainBoard<C = CanRawMessaging, I = CanIsoTpMessaging> {
+    canfd_iface: C,
+    isotp_iface: I,
     message_queue_rx: mpsc::UnboundedReceiver<McuPayload>,
     canfd: bool,
 }
@@ -60,12 +64,7 @@ impl MainBoardBuilder {
         .wrap_err("Failed to create CanIsoTpMessaging for MainBoard")?;
 
         Ok((
-            MainBoard {
-                canfd_iface,
-                isotp_iface,
-                message_queue_rx: self.message_queue_rx,
-                canfd,
-            },
+            MainBoard::new(canfd_iface, isotp_iface, self.message_queue_rx, canfd),
             BoardTaskHandles {
                 raw: raw_can_task_handle,
                 isotp: isotp_can_task_handle,
@@ -78,6 +77,28 @@ impl MainBoard {
     pub fn builder() -> MainBoardBuilder {
         MainBoardBuilder::new()
     }
+}
+
+impl<C, I> MainBoard<C, I>
+where
+    C: MessagingInterface + Send,
+    I: MessagingInterface + Send,
+{
+    /// `message_queue_rx` must receive the messages of both interfaces. The board
+    /// sends over `canfd_iface` if `canfd` is set, and over `isotp_iface` otherwise.
+    pub fn new(
+        canfd_iface: C,
+        isotp_iface: I,
+        message_queue_rx: mpsc::UnboundedReceiver<McuPayload>,
+        canfd: bool,
+    ) -> Self {
+        Self {
+            canfd_iface,
+            isotp_iface,
+            message_queue_rx,
+            canfd,
+        }
+    }
 
     /// Send a message to the security board with preferred interface
     pub async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError> {
@@ -87,11 +108,12 @@ impl MainBoard {
                 if self.canfd { "can-fd" } else { "iso-tp" },
                 payload
             );
-            if self.canfd {
-                self.canfd_iface.send(payload).await
+            let ack = if self.canfd {
+                self.canfd_iface.send(payload).await?
             } else {
-                self.isotp_iface.send(payload).await
-            }
+                self.isotp_iface.send(payload).await?
+            };
+            Ok(ack)
         } else {
             Err(eyre!(
                 "Message not targeted to security board: {:?}",
@@ -154,7 +176,11 @@ impl MainBoard {
 }
 
 #[async_trait]
-impl Board for MainBoard {
+impl<C, I> Board for MainBoard<C, I>
+where
+    C: MessagingInterface + Send,
+    I: MessagingInterface + Send,
+{
     async fn reboot(&mut self, delay: Option<u32>) -> Result<()> {
         let delay = delay.unwrap_or(REBOOT_DELAY);
         let reboot_msg =
@@ -387,7 +413,14 @@ impl MainBoardInfo {
     /// Fetches `MainBoardInfo` from the main board
     /// doesn't fail, but lazily fetches as much info as it could
     /// on timeout, returns the info that was fetched so far
-    async fn build(mut self, main_board: &mut MainBoard) -> Result<Self, Self> {
+    async fn build<C, I>(
+        mut self,
+        main_board: &mut MainBoard<C, I>,
+    ) -> Result<Self, Self>
+    where
+        C: MessagingInterface + Send,
+        I: MessagingInterface + Send,
+    {
         let mut is_err = false;
 
         if let Err(e) = main_board
@@ -457,7 +490,7 @@ impl MainBoardInfo {
     /// Mutates `self` while listening for board info messages.
     ///
     /// Does not terminate until all board info is populated.
-    async fn listen_for_board_info(&mut self, main_board: &mut MainBoard) {
+    async fn listen_for_board_info<C, I>(&mut self, main_board: &mut MainBoard<C, I>) {
         let mut battery_status = BatteryStatus {
             percentage: None,
             voltage_mv: None,
@@ -516,3 +549,45 @@ impl MainBoardInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use orb_mcu_interface::simulated::SimulatedMcu;
+
+    use super::*;
+
+    fn board(canfd: bool) -> MainBoard<SimulatedMcu, SimulatedMcu> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        MainBoard::new(
+            SimulatedMcu::new(Device::Main, tx.clone()).unwrap(),
+            SimulatedMcu::new(Device::Main, tx).unwrap(),
+            rx,
+            canfd,
+        )
+    }
+
+    #[tokio::test]
+    async fn reboot_over_preferred_interface() {
+        let mut board = board(true);
+        board.reboot(Some(5)).await.unwrap();
+        assert!(matches!(
+            board.canfd_iface.received(),
+            [McuPayload::ToMain(
+                main_messaging::jetson_to_mcu::Payload::Reboot(
+                    orb_messages::RebootWithDelay { delay: 5 }
+                )
+            )]
+        ));
+        assert!(board.isotp_iface.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn gimbal_auto_home_over_isotp() {
+        let mut board = board(true);
+        board.gimbal_auto_home().await.unwrap();
+        assert_eq!(board.isotp_iface.received().len(), 1);
+
+        board.isotp_iface.push_ack(CommonAckError::Fail);
+        assert!(board.gimbal_auto_home().await.is_err());
+    }
+}
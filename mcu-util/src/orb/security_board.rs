@@ -20,9 +20,13 @@ use super::BoardTaskHandles;
 
 const REBOOT_DELAY: u32 = 3;
 
-pub struct SecurityBoard {
-    canfd_iface: CanRawMessaging,
-    isotp_iface: CanIsoTpMessaging,
+/// The security microcontroller, reached over CAN-FD and ISO-TP
+///
+/// The interfaces are generic so that the board can be driven by other
+/// [`MessagingInterface`]s, such as a simulated MCU.
+pub struct SecurityBoard<C = CanRawMessaging, I = CanIsoTpMessaging> {
+    canfd_iface: C,
+    isotp_iface: I,
     message_queue_rx: mpsc::UnboundedReceiver<McuPayload>,
     canfd: bool,
 }
@@ -60,12 +64,7 @@ impl SecurityBoardBuilder {
         .wrap_err("Failed to create CanIsoTpMessaging for SecurityBoard")?;
 
         Ok((
-            SecurityBoard {
-                canfd_iface,
-                isotp_iface,
-                message_queue_rx: self.message_queue_rx,
-                canfd,
-            },
+            SecurityBoard::new(canfd_iface, isotp_iface, self.message_queue_rx, canfd),
             BoardTaskHandles {
                 raw: raw_can_task,
                 isotp: isotp_can_task,
@@ -78,6 +77,28 @@ impl SecurityBoard {
     pub fn builder() -> SecurityBoardBuilder {
         SecurityBoardBuilder::new()
     }
+}
+
+impl<C, I> SecurityBoard<C, I>
+where
+    C: MessagingInterface + Send,
+    I: MessagingInterface + Send,
+{
+    /// `message_queue_rx` must receive the messages of both interfaces. The board
+    /// sends over `canfd_iface` if `canfd` is set, and over `isotp_iface` otherwise.
+    pub fn new(
+        canfd_iface: C,
+        isotp_iface: I,
+        message_queue_rx: mpsc::UnboundedReceiver<McuPayload>,
+        canfd: bool,
+    ) -> Self {
+        Self {
+            canfd_iface,
+            isotp_iface,
+            message_queue_rx,
+            canfd,
+        }
+    }
 
     /// Send a message to the security board with preferred interface
     pub async fn send(&mut self, payload: McuPayload) -> Result<CommonAckError> {
@@ -87,11 +108,12 @@ impl SecurityBoard {
                 if self.canfd { "can-fd" } else { "iso-tp" },
                 payload
             );
-            if self.canfd {
-                self.canfd_iface.send(payload).await
+            let ack = if self.canfd {
+                self.canfd_iface.send(payload).await?
             } else {
-                self.isotp_iface.send(payload).await
-            }
+                self.isotp_iface.send(payload).await?
+            };
+            Ok(ack)
         } else {
             Err(eyre!(
                 "Message not targeted to security board: {:?}",
@@ -118,7 +140,11 @@ impl SecurityBoard {
 }
 
 #[async_trait]
-impl Board for SecurityBoard {
+impl<C, I> Board for SecurityBoard<C, I>
+where
+    C: MessagingInterface + Send,
+    I: MessagingInterface + Send,
+{
     async fn reboot(&mut self, delay: Option<u32>) -> Result<()> {
         let delay = delay.unwrap_or(REBOOT_DELAY);
         let reboot_msg =
@@ -345,7 +371,14 @@ impl SecurityBoardInfo {
 
     /// Fetches `SecurityBoardInfo` from the security board
     /// on timeout, returns the info that was fetched so far
-    async fn build(mut self, sec_board: &mut SecurityBoard) -> Result<Self, Self> {
+    async fn build<C, I>(
+        mut self,
+        sec_board: &mut SecurityBoard<C, I>,
+    ) -> Result<Self, Self>
+    where
+        C: MessagingInterface + Send,
+        I: MessagingInterface + Send,
+    {
         let mut is_err = false;
         if let Err(e) = sec_board
             .send(McuPayload::ToSec(
@@ -414,7 +447,10 @@ impl SecurityBoardInfo {
     /// Mutates `self` while listening for board info messages.
     ///
     /// Does not terminate until all board info is populated.
-    async fn listen_for_board_info(&mut self, sec_board: &mut SecurityBoard) {
+    async fn listen_for_board_info<C, I>(
+        &mut self,
+        sec_board: &mut SecurityBoard<C, I>,
+    ) {
         let mut battery_status = BatteryStatus {
             percentage: None,
             voltage_mv: None,
@@ -458,3 +494,29 @@ impl SecurityBoardInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use orb_mcu_interface::simulated::SimulatedMcu;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn power_cycle_secure_element() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut board = SecurityBoard::new(
+            SimulatedMcu::new(Device::Security, tx.clone()).unwrap(),
+            SimulatedMcu::new(Device::Security, tx).unwrap(),
+            rx,
+            false,
+        );
+        board.power_cycle_secure_element().await.unwrap();
+        assert!(matches!(
+            board.isotp_iface.received(),
+            [McuPayload::ToSec(
+                security_messaging::jetson_to_sec::Payload::SeRequest(_)
+            )]
+        ));
+        assert!(board.canfd_iface.received().is_empty());
+    }
+}